@@ -0,0 +1,350 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+};
+
+use petgraph::{
+    algo::tarjan_scc,
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    schema::{DerivedTypeVariable, FieldLabel},
+    solver::QuotientGraph,
+};
+
+/// Render the quotient graph from shape inference as C declarations.
+///
+/// - `DerefPattern` edges become struct fields placed at their byte offset.
+/// - `Load`/`Store` edges make the class a pointer to its target.
+/// - `InPattern`/`OutPattern` edges make the class a function.
+/// - Classes reachable from themselves get a forward declaration.
+pub fn emit_c_types(quotient: &QuotientGraph) -> String {
+    let emitter = Emitter::new(&quotient.graph);
+    let mut out = String::new();
+
+    let mut recursive: Vec<NodeIndex> = emitter.recursive.iter().cloned().collect();
+    recursive.sort_by(|a, b| emitter.names[a].cmp(&emitter.names[b]));
+    for ind in &recursive {
+        writeln!(out, "struct {};", emitter.names[ind]).unwrap();
+    }
+    if !recursive.is_empty() {
+        writeln!(out).unwrap();
+    }
+
+    for ind in emitter.sorted_classes() {
+        let shape = &emitter.shapes[&ind];
+        if shape.fields.is_empty() {
+            continue;
+        }
+        writeln!(out, "struct {} {{", emitter.names[&ind]).unwrap();
+        for (offset, (size, target)) in &shape.fields {
+            let ty = emitter.type_of(*target, Some(*size), &mut Vec::new());
+            writeln!(
+                out,
+                "    {}; // offset {}",
+                ty.declare(&field_name(*offset)),
+                offset
+            )
+            .unwrap();
+        }
+        writeln!(out, "}};\n").unwrap();
+    }
+
+    // top level declarations for the named variables.
+    for ind in emitter.sorted_classes() {
+        let shape = &emitter.shapes[&ind];
+        if !emitter.is_named[&ind] || !shape.fields.is_empty() {
+            continue;
+        }
+        let name = &emitter.names[&ind];
+        if shape.is_function() {
+            let ty = emitter.function_type(ind, &mut vec![ind]);
+            writeln!(out, "{};", ty.declare(name)).unwrap();
+        } else if shape.pointee.is_some() {
+            let ty = emitter.type_of(ind, None, &mut Vec::new());
+            writeln!(out, "typedef {};", ty.declare(name)).unwrap();
+        }
+    }
+    out
+}
+
+/// Describe the quotient graph from shape inference as JSON for a Ghidra
+/// data type importer, with the same names and types as `emit_c_types`:
+///
+/// - `structures`: the classes with `DerefPattern` edges, with each field's
+///   name, byte offset, size and C type.
+/// - `pointers`: the named pointer classes and the C type they point to.
+/// - `functions`: the named function classes, with their return type and
+///   parameters.
+pub fn emit_ghidra_json(quotient: &QuotientGraph) -> Value {
+    let emitter = Emitter::new(&quotient.graph);
+    let mut structures = Vec::new();
+    let mut pointers = Vec::new();
+    let mut functions = Vec::new();
+    for ind in emitter.sorted_classes() {
+        let shape = &emitter.shapes[&ind];
+        let name = &emitter.names[&ind];
+        if !shape.fields.is_empty() {
+            let fields: Vec<Value> = shape
+                .fields
+                .iter()
+                .map(|(offset, (size, target))| {
+                    json!({
+                        "name": field_name(*offset),
+                        "offset": offset,
+                        "size": size,
+                        "type": emitter.type_of(*target, Some(*size), &mut Vec::new()).declare(""),
+                    })
+                })
+                .collect();
+            structures.push(json!({ "name": name, "fields": fields }));
+        } else if !emitter.is_named[&ind] {
+            continue;
+        } else if shape.is_function() {
+            let visiting = &mut vec![ind];
+            let ret = match shape.ret {
+                Some(ret) => emitter.type_of(ret, None, visiting),
+                None => CType::simple("void"),
+            };
+            let params: Vec<Value> = shape
+                .params
+                .iter()
+                .map(|(param, target)| {
+                    json!({
+                        "name": sanitize(&FieldLabel::InPattern(param.clone()).to_string()),
+                        "type": emitter.type_of(*target, None, visiting).declare(""),
+                    })
+                })
+                .collect();
+            functions
+                .push(json!({ "name": name, "return": ret.declare(""), "parameters": params }));
+        } else if let Some(pointee) = shape.pointee {
+            let target = emitter.type_of(pointee, None, &mut vec![ind]).declare("");
+            pointers.push(json!({ "name": name, "target": target }));
+        }
+    }
+    json!({ "structures": structures, "pointers": pointers, "functions": functions })
+}
+
+/// A C type split around the declarator, so `int (*f)(int)` is
+/// `prefix: "int (*"`, `suffix: ")(int)"`.
+pub(crate) struct CType {
+    prefix: String,
+    suffix: String,
+}
+
+impl CType {
+    pub(crate) fn simple(name: &str) -> CType {
+        CType {
+            prefix: format!("{} ", name),
+            suffix: String::new(),
+        }
+    }
+
+    pub(crate) fn pointer(self) -> CType {
+        if self.suffix.is_empty() {
+            CType {
+                prefix: format!("{}*", self.prefix),
+                suffix: self.suffix,
+            }
+        } else {
+            CType {
+                prefix: format!("{}(*", self.prefix),
+                suffix: format!("){}", self.suffix),
+            }
+        }
+    }
+
+    pub(crate) fn declare(&self, name: &str) -> String {
+        format!("{}{}{}", self.prefix, name, self.suffix)
+            .trim_end()
+            .to_string()
+    }
+
+    /// A function returning `ret`, with the parameters already declared.
+    pub(crate) fn function(ret: CType, params: Vec<String>) -> CType {
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        CType {
+            prefix: ret.prefix,
+            suffix: format!("({}){}", params, ret.suffix),
+        }
+    }
+}
+
+/// The outgoing structure of one equivalence class.
+#[derive(Default)]
+struct Shape {
+    /// offset -> (size, target)
+    fields: BTreeMap<i32, (u32, NodeIndex)>,
+    pointee: Option<NodeIndex>,
+    params: BTreeMap<String, NodeIndex>,
+    ret: Option<NodeIndex>,
+}
+
+impl Shape {
+    fn is_function(&self) -> bool {
+        !self.params.is_empty() || self.ret.is_some()
+    }
+}
+
+struct Emitter {
+    shapes: HashMap<NodeIndex, Shape>,
+    names: HashMap<NodeIndex, String>,
+    /// whether the class contains a variable without fields.
+    is_named: HashMap<NodeIndex, bool>,
+    recursive: HashSet<NodeIndex>,
+}
+
+impl Emitter {
+    fn new(g: &DiGraph<Vec<DerivedTypeVariable>, FieldLabel>) -> Self {
+        let mut shapes = HashMap::new();
+        for ind in g.node_indices() {
+            let mut shape = Shape::default();
+            for edge in g.edges(ind) {
+                let target = edge.target();
+                match edge.weight() {
+                    FieldLabel::DerefPattern { size, offset, .. } => {
+                        shape.fields.entry(*offset).or_insert((*size, target));
+                    }
+                    FieldLabel::Load | FieldLabel::Store => {
+                        shape.pointee.get_or_insert(target);
+                    }
+                    FieldLabel::InPattern(name) => {
+                        shape.params.entry(name.clone()).or_insert(target);
+                    }
+                    FieldLabel::OutPattern(_) => {
+                        shape.ret.get_or_insert(target);
+                    }
+                }
+            }
+            shapes.insert(ind, shape);
+        }
+
+        let mut recursive = HashSet::new();
+        for scc in tarjan_scc(g) {
+            if scc.len() > 1 || g.contains_edge(scc[0], scc[0]) {
+                recursive.extend(scc);
+            }
+        }
+
+        // name each class after its smallest base variable, or its smallest
+        // derived variable if it has none.
+        let mut reps: Vec<(bool, String, NodeIndex)> = g
+            .node_indices()
+            .map(|ind| {
+                let dtvs = g.node_weight(ind).unwrap();
+                let base = dtvs
+                    .iter()
+                    .filter(|d| d.fields.is_empty())
+                    .map(|d| d.to_string())
+                    .min();
+                match base {
+                    Some(name) => (true, name, ind),
+                    None => (
+                        false,
+                        dtvs.iter().map(|d| d.to_string()).min().unwrap_or_default(),
+                        ind,
+                    ),
+                }
+            })
+            .collect();
+        reps.sort_by(|a, b| (!a.0, &a.1).cmp(&(!b.0, &b.1)));
+        let mut used = HashSet::new();
+        let mut names = HashMap::new();
+        let mut is_named = HashMap::new();
+        for (named, rep, ind) in reps {
+            let base = sanitize(&rep);
+            let mut name = base.clone();
+            let mut i = 1;
+            while !used.insert(name.clone()) {
+                name = format!("{}_{}", base, i);
+                i += 1;
+            }
+            names.insert(ind, name);
+            is_named.insert(ind, named);
+        }
+
+        Emitter {
+            shapes,
+            names,
+            is_named,
+            recursive,
+        }
+    }
+
+    fn sorted_classes(&self) -> Vec<NodeIndex> {
+        let mut classes: Vec<NodeIndex> = self.shapes.keys().cloned().collect();
+        classes.sort_by(|a, b| self.names[a].cmp(&self.names[b]));
+        classes
+    }
+
+    /// The type of a value in class `ind`. `size` is the byte size known from
+    /// the field that holds the value, if any.
+    fn type_of(&self, ind: NodeIndex, size: Option<u32>, visiting: &mut Vec<NodeIndex>) -> CType {
+        let shape = &self.shapes[&ind];
+        if !shape.fields.is_empty() || (self.recursive.contains(&ind) && visiting.contains(&ind)) {
+            return CType::simple(&format!("struct {}", self.names[&ind]));
+        }
+        if visiting.contains(&ind) {
+            return CType::simple("void");
+        }
+        visiting.push(ind);
+        let ty = if let Some(pointee) = shape.pointee {
+            self.type_of(pointee, None, visiting).pointer()
+        } else if shape.is_function() {
+            self.function_type(ind, visiting).pointer()
+        } else {
+            CType::simple(&scalar_name(size))
+        };
+        visiting.pop();
+        ty
+    }
+
+    fn function_type(&self, ind: NodeIndex, visiting: &mut Vec<NodeIndex>) -> CType {
+        let shape = &self.shapes[&ind];
+        let ret = match shape.ret {
+            Some(ret) => self.type_of(ret, None, visiting),
+            None => CType::simple("void"),
+        };
+        let params: Vec<String> = shape
+            .params
+            .values()
+            .map(|p| self.type_of(*p, None, visiting).declare(""))
+            .collect();
+        CType::function(ret, params)
+    }
+}
+
+pub(crate) fn scalar_name(size: Option<u32>) -> String {
+    match size {
+        Some(size @ (1 | 2 | 4 | 8)) => format!("uint{}_t", size * 8),
+        _ => "int".to_string(),
+    }
+}
+
+pub(crate) fn field_name(offset: i32) -> String {
+    if offset < 0 {
+        format!("field_neg{}", -(offset as i64))
+    } else {
+        format!("field_{}", offset)
+    }
+}
+
+pub(crate) fn sanitize(name: &str) -> String {
+    let mut s: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if s.is_empty() || s.starts_with(|c: char| c.is_ascii_digit()) {
+        s.insert(0, '_');
+    }
+    s
+}
@@ -1,5 +1,8 @@
 use std::{
-    collections::{HashMap, HashSet}, env, error::Error, fmt::{self, Debug, Display}, fs::File, io::Write
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Display},
+    fs,
+    path::Path,
 };
 
 use petgraph::{
@@ -8,13 +11,18 @@ use petgraph::{
     graph::{DiGraph, EdgeIndex, NodeIndex},
     visit::EdgeRef,
 };
+use rayon::prelude::*;
 
-use crate::schema::{Constraint, DerivedTypeVariable, FieldLabel, Program, Variance};
+use crate::c_types::sanitize;
+use crate::schema::{
+    Constraint, DerivedTypeVariable, FieldLabel, FieldLabelId, PointerCapabilities, Program,
+    Scheme, Variance, VarianceTable,
+};
 
 /// This file contains the graph used for saturation and transducer in Appendix D.
 ///
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum EdgeLabel {
     One,
     Forget {
@@ -50,7 +58,7 @@ impl Debug for EdgeLabel {
 //     pub variance: Variance,
 // }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum SideMark {
     None,
     Left,
@@ -67,7 +75,17 @@ impl Display for SideMark {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+impl SideMark {
+    pub fn opposite(&self) -> SideMark {
+        match self {
+            SideMark::None => SideMark::None,
+            SideMark::Left => SideMark::Right,
+            SideMark::Right => SideMark::Left,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub struct Node {
     pub base: DerivedTypeVariable,
     pub suffix_variance: Variance,
@@ -87,25 +105,213 @@ impl Debug for Node {
 }
 
 impl Node {
-    pub fn forget_once(&self) -> Option<(FieldLabel, Node)> {
+    /// The START node of the transducer.
+    pub fn start() -> Node {
+        Node::pseudo("#START")
+    }
+    /// The END node of the transducer.
+    pub fn end() -> Node {
+        Node::pseudo("#END")
+    }
+    fn pseudo(name: &str) -> Node {
+        Node {
+            base: DerivedTypeVariable::new(name),
+            suffix_variance: Variance::Covariant,
+            sidemark: SideMark::None,
+        }
+    }
+    /// The node with the opposite variance. The 1-labeled edge is reversed
+    /// between the two variance copies, so the side mark flips too.
+    pub fn inverse(&self) -> Node {
+        Node {
+            base: self.base.clone(),
+            suffix_variance: self.suffix_variance.invert(),
+            sidemark: self.sidemark.opposite(),
+        }
+    }
+    /// Split off the last label, with the variance of the node for the
+    /// remaining prefix.
+    pub fn forget_once(&self, table: &VarianceTable) -> Option<(FieldLabel, Node)> {
         if self.base.fields.is_empty() {
             return None;
         }
         let mut base = self.base.clone();
-        let last = base.fields.pop().unwrap();
-        let variance = self.suffix_variance.combine(&last.variance());
+        let last = base.fields_mut().pop().unwrap();
+        let variance = self.suffix_variance.combine(&last.variance_with(table));
         let node = Node {
             base,
             suffix_variance: variance,
             sidemark: self.sidemark.clone(),
         };
-        Some((last, node))
+        Some((last.get().clone(), node))
+    }
+}
+
+/// The reaching sets of the saturation, with the elements still to be
+/// propagated.
+#[derive(Default, Clone)]
+struct ReachingSet {
+    sets: HashMap<NodeIndex, HashSet<(FieldLabel, NodeIndex)>>,
+    worklist: Vec<(NodeIndex, (FieldLabel, NodeIndex))>,
+}
+
+impl ReachingSet {
+    /// Add `elem` to R(`dest`), queueing it if it is new.
+    fn insert(&mut self, dest: NodeIndex, elem: (FieldLabel, NodeIndex)) -> bool {
+        if self.sets.entry(dest).or_default().insert(elem.clone()) {
+            self.worklist.push((dest, elem));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A state of the search of `ConstraintGraph::path_expr`: the node reached
+/// and the labels pending on each side, each with the node it was taken
+/// from.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PathState {
+    node: NodeIndex,
+    recalls: Vec<(NodeIndex, FieldLabel)>,
+    forgets: Vec<(NodeIndex, FieldLabel)>,
+    /// variance of the node where the first forget was taken, `None` while
+    /// still recalling.
+    middle: Option<Variance>,
+}
+
+/// Counters of the work done by `ConstraintGraph::saturate`.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct SaturationStats {
+    /// rounds of the worklist, each followed by the pointer rule.
+    pub iterations: usize,
+    /// 1-labeled edges added to the graph.
+    pub edges_added: usize,
+    /// elements added to the reaching sets.
+    pub reaching_elems: usize,
+    /// reaching set elements added by the pointer rule.
+    pub pointer_rule_applications: usize,
+    /// 1-labeled edges left out because of `SaturationMode::Approximate`.
+    pub edges_skipped: usize,
+}
+
+/// What `ConstraintGraph::diff` finds between two graphs, each list sorted.
+/// Nodes and edges are matched by their values, not their indices.
+#[derive(Default, PartialEq, Clone)]
+pub struct GraphDiff {
+    /// in the other graph only.
+    pub added_nodes: Vec<Node>,
+    pub removed_nodes: Vec<Node>,
+    pub added_edges: Vec<(Node, Node, EdgeLabel)>,
+    pub removed_edges: Vec<(Node, Node, EdgeLabel)>,
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
     }
 }
 
+impl Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for node in &self.removed_nodes {
+            writeln!(f, "- {}", node)?;
+        }
+        for node in &self.added_nodes {
+            writeln!(f, "+ {}", node)?;
+        }
+        for (source, target, label) in &self.removed_edges {
+            writeln!(f, "- {} -> {} ({})", source, target, label)?;
+        }
+        for (source, target, label) in &self.added_edges {
+            writeln!(f, "+ {} -> {} ({})", source, target, label)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// How far `ConstraintGraph::saturate` goes, see `with_saturation_mode`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaturationMode {
+    /// Derive every edge.
+    #[default]
+    Exact,
+    /// Stop adding `One` edges once the graph has `edge_budget` edges. The
+    /// pointer rule still runs, so the graph may end up a few edges over.
+    Approximate { edge_budget: usize },
+}
+
+/// The default for `ConstraintGraph::with_max_iterations`.
+pub const DEFAULT_MAX_ITERATIONS: usize = 100_000;
+
+/// A saturation that did not reach its fixpoint.
+#[derive(PartialEq, Clone)]
+pub enum SaturationError {
+    /// the worklist was still not empty after this many rounds.
+    IterationLimit { iterations: usize },
+}
+
+impl Display for SaturationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaturationError::IterationLimit { iterations } => {
+                write!(
+                    f,
+                    "saturation did not terminate after {} iterations",
+                    iterations
+                )
+            }
+        }
+    }
+}
+
+impl Debug for SaturationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for SaturationError {}
+
+#[derive(Clone)]
 pub struct ConstraintGraph {
     pub graph: DiGraph<Node, EdgeLabel>,
     pub graph_node_map: HashMap<Node, NodeIndex>,
+    /// the START and END nodes, once added by `add_start_end`.
+    pub start: Option<NodeIndex>,
+    pub end: Option<NodeIndex>,
+    /// the variance of the labels used to build the graph.
+    pub variance_table: VarianceTable,
+    /// variables with more fields than this get no prefix chain, see
+    /// `is_truncated`. `None` expands every prefix.
+    pub max_capability_depth: Option<usize>,
+    /// the labels the pointer rule applies to.
+    pub pointer_capabilities: PointerCapabilities,
+    /// the rounds a saturation may take, see `with_max_iterations`.
+    max_iterations: usize,
+    saturation_mode: SaturationMode,
+    /// the `One` edges the saturation derived but left out, see
+    /// `is_incomplete`.
+    skipped: HashSet<(NodeIndex, NodeIndex)>,
+    /// the nodes whose prefix chain was cut by `max_capability_depth`.
+    truncated: HashSet<NodeIndex>,
+    /// the variables marked interesting by `build_initial_graph`.
+    interesting: HashSet<String>,
+    /// the reaching sets of the last saturation, kept for `resaturate`.
+    reaching_set: ReachingSet,
+    /// the number of edges the reaching sets already account for.
+    saturated_edges: usize,
+    /// the `One` edges added by saturation.
+    derived: HashSet<EdgeIndex>,
 }
 
 impl ConstraintGraph {
@@ -113,29 +319,406 @@ impl ConstraintGraph {
         ConstraintGraph {
             graph: DiGraph::new(),
             graph_node_map: HashMap::new(),
+            start: None,
+            end: None,
+            variance_table: VarianceTable::default(),
+            max_capability_depth: None,
+            pointer_capabilities: PointerCapabilities::default(),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            saturation_mode: SaturationMode::Exact,
+            skipped: HashSet::new(),
+            truncated: HashSet::new(),
+            interesting: HashSet::new(),
+            reaching_set: ReachingSet::default(),
+            saturated_edges: 0,
+            derived: HashSet::new(),
         }
     }
-    pub fn new(constraints: Vec<&Constraint>) -> Self {
-        let mut g = ConstraintGraph {
-            graph: DiGraph::new(),
-            graph_node_map: HashMap::new(),
-        };
+    /// Build and saturate the graph. Nodes of the `interesting` variables
+    /// get a side mark, see `build_initial_graph`.
+    pub fn new(constraints: Vec<&Constraint>, interesting: &HashSet<String>) -> Self {
+        ConstraintGraph::with_variance_table(
+            constraints,
+            interesting,
+            VarianceTable::default(),
+            None,
+        )
+    }
+    /// Fail the saturation with `SaturationError::IterationLimit` once it
+    /// takes more than `iterations` rounds, instead of running on.
+    ///
+    /// Like the other `with_` settings, this only applies to the saturations
+    /// after it: `new` and the constructors that take the constraints have
+    /// already saturated the graph with the defaults. Set it on a graph from
+    /// `construct`, before `build_and_saturate`.
+    pub fn with_max_iterations(mut self, iterations: usize) -> Self {
+        self.max_iterations = iterations;
+        self
+    }
+    /// Saturate in `mode`, trading completeness for a bounded graph size
+    /// with `SaturationMode::Approximate`. See `with_max_iterations` for
+    /// when it applies.
+    pub fn with_saturation_mode(mut self, mode: SaturationMode) -> Self {
+        self.saturation_mode = mode;
+        self
+    }
+    /// `new`, with the label variances overridden by `table`. See
+    /// `build_and_saturate` for `dot_dir`. A saturation that hits the
+    /// iteration limit is logged, and leaves the graph partly saturated;
+    /// use `construct` and `build_and_saturate` to get the error instead.
+    pub fn with_variance_table(
+        constraints: Vec<&Constraint>,
+        interesting: &HashSet<String>,
+        table: VarianceTable,
+        dot_dir: Option<&Path>,
+    ) -> Self {
+        let mut g = ConstraintGraph::construct();
+        g.variance_table = table;
+        if let Err(e) = g.build_and_saturate(constraints, interesting, dot_dir) {
+            log::error!("{}", e);
+        }
+        g
+    }
+    /// `new`, with the saturation continued from the reaching sets `prior`
+    /// of an earlier one, see `import_reaching_set`. The prior is keyed by
+    /// nodes instead of indices, so it can come from another graph.
+    pub fn with_reaching_set(
+        mut constraints: Vec<&Constraint>,
+        interesting: &HashSet<String>,
+        prior: HashMap<Node, HashSet<(FieldLabel, Node)>>,
+    ) -> Self {
+        let mut g = ConstraintGraph::construct();
+        constraints.sort_by_cached_key(|c| c.to_string());
+        g.build_initial_graph(constraints, interesting);
+        g.import_reaching_set(prior);
+        if let Err(e) = g.resaturate() {
+            log::error!("{}", e);
+        }
+        g
+    }
+    /// Build the graph and saturate it, with the settings already made.
+    /// If `dot_dir` is set, the initial and saturated graphs are written to
+    /// it for debugging, as `transducer-init.dot`, `transducer-sat.dot` and
+    /// `transducer-sat.graphml`.
+    ///
+    /// The constraints are sorted by their text first, so the node indices
+    /// do not depend on the order they come in.
+    pub fn build_and_saturate(
+        &mut self,
+        mut constraints: Vec<&Constraint>,
+        interesting: &HashSet<String>,
+        dot_dir: Option<&Path>,
+    ) -> Result<SaturationStats, SaturationError> {
+        constraints.sort_by_cached_key(|c| c.to_string());
         // 1. build the initial graph (Algorithm D.1 Transducer)
-        g.build_initial_graph(constraints);
+        self.build_initial_graph(constraints, interesting);
         // print the graph for debugging
-        if let Some(path) = env::var("DEBUG_TRANS_INIT_GRAPH").ok() {
-            let mut file = File::create(path).unwrap();
-            write!(file, "{:?}", Dot::new(&g.graph)).unwrap();
-        }
+        write_debug_graph(dot_dir, "transducer-init.dot", || self.to_dot());
         // 2. saturate the graph
-        g.saturate();
+        let stats = self.saturate();
+        log::debug!("saturation: {:?}", stats);
         // print the graph for debugging
-        if let Some(path) = env::var("DEBUG_TRANS_SAT_GRAPH").ok() {
-            let mut file = File::create(path).unwrap();
-            write!(file, "{:?}", Dot::new(&g.graph)).unwrap();
+        write_debug_graph(dot_dir, "transducer-sat.dot", || self.to_dot());
+        write_debug_graph(dot_dir, "transducer-sat.graphml", || self.to_graphml());
+        stats
+    }
+    /// The reaching set R(`node`) of the last saturation: the `(ℓ, m)` such
+    /// that a path from `m` reads `forget ℓ` and then 1-labeled edges to
+    /// `node`. `None` if nothing reaches the node, or the graph was not
+    /// saturated.
+    pub fn reaching_set(&self, node: NodeIndex) -> Option<&HashSet<(FieldLabel, NodeIndex)>> {
+        self.reaching_set.sets.get(&node)
+    }
+    /// Every reaching set of the last saturation, keyed by nodes so it can
+    /// seed another graph with `with_reaching_set`.
+    pub fn reaching_sets(&self) -> HashMap<Node, HashSet<(FieldLabel, Node)>> {
+        let node = |ind: &NodeIndex| self.graph.node_weight(*ind).unwrap().clone();
+        self.reaching_set
+            .sets
+            .iter()
+            .map(|(dest, set)| {
+                (
+                    node(dest),
+                    set.iter()
+                        .map(|(label, source)| (label.clone(), node(source)))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+    /// Add the elements of `prior` to the reaching sets, with the `One`
+    /// edges they imply: `(ℓ, m)` in R(n) and a `recall ℓ` edge from `n` to
+    /// `t` give an edge from `m` to `t`. Elements over nodes missing from
+    /// the graph are dropped. Call `resaturate` after, to derive what the
+    /// prior misses; with a complete prior it adds no edges.
+    pub fn import_reaching_set(&mut self, prior: HashMap<Node, HashSet<(FieldLabel, Node)>>) {
+        for (dest, set) in prior {
+            let Some(&dest) = self.graph_node_map.get(&dest) else {
+                continue;
+            };
+            for (label, source) in set {
+                if let Some(&source) = self.graph_node_map.get(&source) {
+                    self.reaching_set
+                        .sets
+                        .entry(dest)
+                        .or_default()
+                        .insert((label, source));
+                }
+            }
+        }
+        let mut to_add = Vec::new();
+        for (dest, set) in &self.reaching_set.sets {
+            for edge in self.graph.edges(*dest) {
+                if let EdgeLabel::Recall { capability } = edge.weight() {
+                    to_add.extend(
+                        set.iter()
+                            .filter(|(label, _)| label == capability)
+                            .map(|(_, m)| (*m, edge.target())),
+                    );
+                }
+            }
+        }
+        to_add.sort();
+        for (source, target) in to_add {
+            if self.add_edge(source, target, EdgeLabel::One) {
+                self.derived
+                    .insert(EdgeIndex::new(self.graph.edge_count() - 1));
+            }
+        }
+    }
+    /// The endpoints of the `One` edges that saturation added, as opposed to
+    /// the ones from the constraints, in the order they were added.
+    pub fn derived_edges(&self) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut derived: Vec<EdgeIndex> = self.derived.iter().cloned().collect();
+        derived.sort();
+        derived
+            .into_iter()
+            .map(|edge| self.graph.edge_endpoints(edge).unwrap())
+            .collect()
+    }
+    /// Whether an approximate saturation left out some `One` edges, so the
+    /// graph may not prove every subtyping the constraints imply.
+    pub fn is_incomplete(&self) -> bool {
+        !self.skipped.is_empty()
+    }
+    /// The endpoints of the `One` edges left out by an approximate
+    /// saturation, sorted.
+    pub fn skipped_edges(&self) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut skipped: Vec<(NodeIndex, NodeIndex)> = self.skipped.iter().cloned().collect();
+        skipped.sort();
+        skipped
+    }
+    /// Whether `node` is deeper than `max_capability_depth`. Such a node has
+    /// no `recall`/`forget` edges to its prefixes, so nothing is derived
+    /// about its fields.
+    pub fn is_truncated(&self, node: NodeIndex) -> bool {
+        self.truncated.contains(&node)
+    }
+    /// Whether the graph proves `left <= right`: the covariant node of
+    /// `right` is reachable from the one of `left` through 1-labeled edges.
+    /// False if either variable has no node.
+    pub fn proves_subtype(&self, left: &DerivedTypeVariable, right: &DerivedTypeVariable) -> bool {
+        // a node of an interesting variable may carry either side mark.
+        let nodes = |dtv: &DerivedTypeVariable| -> Vec<NodeIndex> {
+            [SideMark::None, SideMark::Left, SideMark::Right]
+                .into_iter()
+                .filter_map(|sidemark| {
+                    self.graph_node_map
+                        .get(&Node {
+                            base: dtv.clone(),
+                            suffix_variance: Variance::Covariant,
+                            sidemark,
+                        })
+                        .cloned()
+                })
+                .collect()
+        };
+        let targets: HashSet<NodeIndex> = nodes(right).into_iter().collect();
+        let mut worklist = nodes(left);
+        let mut visited: HashSet<NodeIndex> = worklist.iter().cloned().collect();
+        while let Some(ind) = worklist.pop() {
+            if targets.contains(&ind) {
+                return true;
+            }
+            for edge in self.graph.edges(ind) {
+                if edge.weight() == &EdgeLabel::One && visited.insert(edge.target()) {
+                    worklist.push(edge.target());
+                }
+            }
+        }
+        false
+    }
+    /// For each base variable, the base variables the graph proves to be its
+    /// subtypes, ignoring fields: `y` is in the set of `x` when the
+    /// covariant node of `x` is reachable from the one of `y` through
+    /// 1-labeled edges, as in `proves_subtype`. A variable is only in its
+    /// own set if it is on a cycle of such edges.
+    pub fn base_subtype_closure(&self) -> HashMap<String, HashSet<String>> {
+        let pseudo = [self.start, self.end];
+        let roots: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|ind| {
+                let node = &self.graph[*ind];
+                node.base.fields.is_empty()
+                    && node.suffix_variance == Variance::Covariant
+                    && !pseudo.contains(&Some(*ind))
+            })
+            .collect();
+        let mut closure: HashMap<String, HashSet<String>> = roots
+            .iter()
+            .map(|ind| (self.graph[*ind].base.name.to_string(), HashSet::new()))
+            .collect();
+        for &root in &roots {
+            let name = &self.graph[root].base.name;
+            let mut worklist = vec![root];
+            let mut visited = HashSet::new();
+            while let Some(ind) = worklist.pop() {
+                for edge in self.graph.edges(ind) {
+                    if edge.weight() == &EdgeLabel::One && visited.insert(edge.target()) {
+                        worklist.push(edge.target());
+                    }
+                }
+            }
+            for ind in visited {
+                if roots.contains(&ind) {
+                    closure
+                        .get_mut(&*self.graph[ind].base.name)
+                        .unwrap()
+                        .insert(name.to_string());
+                }
+            }
+        }
+        closure
+    }
+    /// The subtyping the graph proves, read back as constraints sorted by
+    /// their text, to compare with the output of another implementation:
+    /// `y <= x` for each base variable `y` in the `base_subtype_closure` of
+    /// `x`, and `a.ℓ <= b.ℓ'` for each 1-labeled edge between covariant
+    /// nodes with fields, which the forget/recall edges tie to their bases.
+    pub fn to_constraints(&self) -> Vec<Constraint> {
+        let mut constraints: Vec<Constraint> = Vec::new();
+        for (sup, subs) in self.base_subtype_closure() {
+            for sub in subs.into_iter().filter(|sub| sub != &sup) {
+                constraints.push(Constraint::new(
+                    DerivedTypeVariable::new(&sub),
+                    DerivedTypeVariable::new(&sup),
+                ));
+            }
+        }
+        for edge in self.graph.edge_references() {
+            let (source, target) = (&self.graph[edge.source()], &self.graph[edge.target()]);
+            if edge.weight() == &EdgeLabel::One
+                && source.suffix_variance == Variance::Covariant
+                && target.suffix_variance == Variance::Covariant
+                && !(source.base.fields.is_empty() && target.base.fields.is_empty())
+            {
+                constraints.push(Constraint::new(source.base.clone(), target.base.clone()));
+            }
+        }
+        constraints.sort_by_cached_key(|c| c.to_string());
+        constraints.dedup_by(|a, b| a.to_string() == b.to_string());
+        constraints
+    }
+    /// Whether a path from `from` spells `word`: each label is read by a
+    /// matching `recall` edge, and any number of 1-labeled edges may come
+    /// before each of them. False if `from` is not in the graph.
+    pub fn accepts(&self, from: &Node, word: &[FieldLabel]) -> bool {
+        let mut current: HashSet<NodeIndex> = match self.graph_node_map.get(from) {
+            Some(ind) => HashSet::from([*ind]),
+            None => return false,
+        };
+        for label in word {
+            // close over the 1-labeled edges
+            let mut worklist: Vec<NodeIndex> = current.iter().cloned().collect();
+            while let Some(ind) = worklist.pop() {
+                for edge in self.graph.edges(ind) {
+                    if edge.weight() == &EdgeLabel::One && current.insert(edge.target()) {
+                        worklist.push(edge.target());
+                    }
+                }
+            }
+            current = current
+                .iter()
+                .flat_map(|ind| self.graph.edges(*ind))
+                .filter(|edge| matches!(edge.weight(), EdgeLabel::Recall { capability } if capability == label))
+                .map(|edge| edge.target())
+                .collect();
+            if current.is_empty() {
+                return false;
+            }
+        }
+        true
+    }
+    /// The nodes with more than one outgoing `recall` edge of the same
+    /// capability, with that capability, sorted.
+    ///
+    /// Reading the graph as a transducer, these are the states where a
+    /// recall has more than one successor. Saturation stays correct, since
+    /// an element reaching such a node adds a 1-labeled edge to every
+    /// target, but `accepts` and `path_expr` must follow all of them.
+    pub fn nondeterministic_nodes(&self) -> Vec<(NodeIndex, FieldLabel)> {
+        let mut found = Vec::new();
+        for ind in self.graph.node_indices() {
+            let mut counts: HashMap<&FieldLabel, usize> = HashMap::new();
+            for edge in self.graph.edges(ind) {
+                if let EdgeLabel::Recall { capability } = edge.weight() {
+                    *counts.entry(capability).or_default() += 1;
+                }
+            }
+            found.extend(
+                counts
+                    .into_iter()
+                    .filter(|(_, count)| *count > 1)
+                    .map(|(cap, _)| (ind, cap.clone())),
+            );
+        }
+        found.sort();
+        found
+    }
+    /// The nodes and edges of `other` that are not in `self`, as added, and
+    /// the ones of `self` not in `other`, as removed.
+    pub fn diff(&self, other: &ConstraintGraph) -> GraphDiff {
+        let nodes =
+            |cg: &ConstraintGraph| -> HashSet<Node> { cg.graph.node_weights().cloned().collect() };
+        let edges = |cg: &ConstraintGraph| -> HashSet<(Node, Node, EdgeLabel)> {
+            cg.graph
+                .edge_references()
+                .map(|e| {
+                    (
+                        cg.graph[e.source()].clone(),
+                        cg.graph[e.target()].clone(),
+                        e.weight().clone(),
+                    )
+                })
+                .collect()
+        };
+        fn sorted<T: Ord + Clone + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> Vec<T> {
+            let mut only: Vec<T> = a.difference(b).cloned().collect();
+            only.sort();
+            only
+        }
+        let (nodes_self, nodes_other) = (nodes(self), nodes(other));
+        let (edges_self, edges_other) = (edges(self), edges(other));
+        GraphDiff {
+            added_nodes: sorted(&nodes_other, &nodes_self),
+            removed_nodes: sorted(&nodes_self, &nodes_other),
+            added_edges: sorted(&edges_other, &edges_self),
+            removed_edges: sorted(&edges_self, &edges_other),
         }
-        // g.pathexpr();
-        g
+    }
+    /// The graph in GraphML format, for graph analysis tools.
+    pub fn to_graphml(&self) -> String {
+        to_graphml(
+            &canonical(&self.graph),
+            |n| n.to_string(),
+            |e| e.to_string(),
+        )
+    }
+    /// The graph in DOT format, with nodes and edges sorted so the output
+    /// does not depend on the order they were added.
+    pub fn to_dot(&self) -> String {
+        format!("{:?}", Dot::new(&canonical(&self.graph)))
     }
     pub fn add_node(&mut self, node: Node) -> NodeIndex {
         if let Some(index) = self.graph_node_map.get(&node) {
@@ -145,6 +728,16 @@ impl ConstraintGraph {
         self.graph_node_map.insert(node.clone(), node_index);
         node_index
     }
+    /// Add an edge unless it is a self edge or already in the graph.
+    /// Returns whether it was added.
+    ///
+    /// A `recall ℓ` edge from `x` to `x.ℓ` is kept even when the graph
+    /// already has the `forget ℓ` edge from `x.ℓ` to `x`, and the other way
+    /// around; the two are not redundant. The forget edge lets what flows
+    /// into `x.ℓ` reach the recalls of `x` and its supertypes, the recall
+    /// edge lets what flows into `x` and its subtypes reach `x.ℓ`. Dropping
+    /// the forget edges loses derivations, e.g. `F.in_stack0.load.σ4@4 ⊑
+    /// close.in_stack0` in the slides example.
     pub fn add_edge(&mut self, from: NodeIndex, to: NodeIndex, label: EdgeLabel) -> bool {
         // self edge is not meaningful
         if from == to {
@@ -161,197 +754,929 @@ impl ConstraintGraph {
         }
     }
 
-    fn add_recalls(&mut self, mut node_ind: NodeIndex) {
-        let node = self.graph.node_weight(node_ind).unwrap().clone();
-        let mut t = node.forget_once();
-        while t.is_some() {
-            let (cap, next) = t.unwrap();
-            let next_ind = self.add_node(next.clone());
-            self.add_edge(next_ind, node_ind, EdgeLabel::Recall { capability: cap });
-            t = next.forget_once();
-            node_ind = next_ind;
+    /// Add the chain of nodes for the prefixes of `node_ind`'s variable,
+    /// with a `recall` edge from each prefix to the longer variable, or a
+    /// `forget` edge the other way. Variables deeper than
+    /// `max_capability_depth` are left without a chain.
+    ///
+    /// Prefixes are shared between variables with the same base, like `x`
+    /// in `x.load <= x.store`. The walk stops at the first edge already in
+    /// the graph, since the chain above it was built along with it.
+    fn add_prefix_chain(&mut self, mut node_ind: NodeIndex, forget: bool) {
+        let mut node = self.graph.node_weight(node_ind).unwrap().clone();
+        if self
+            .max_capability_depth
+            .is_some_and(|depth| node.base.fields.len() > depth)
+        {
+            self.truncated.insert(node_ind);
+            return;
         }
-    }
-
-    fn add_forgets(&mut self, mut node_ind: NodeIndex) {
-        let node = self.graph.node_weight(node_ind).unwrap().clone();
-        let mut t = node.forget_once();
-        while t.is_some() {
-            let (cap, next) = t.unwrap();
+        while let Some((capability, next)) = node.forget_once(&self.variance_table) {
             let next_ind = self.add_node(next.clone());
-            self.add_edge(node_ind, next_ind, EdgeLabel::Forget { capability: cap });
-            t = next.forget_once();
+            let added = if forget {
+                self.add_edge(node_ind, next_ind, EdgeLabel::Forget { capability })
+            } else {
+                self.add_edge(next_ind, node_ind, EdgeLabel::Recall { capability })
+            };
+            if !added {
+                return;
+            }
+            node = next;
             node_ind = next_ind;
         }
     }
 
     /// build the initial graph (Algorithm D.1 Transducer)
-    pub fn build_initial_graph(&mut self, constraints: Vec<&Constraint>) {
-        // add start and end node? TODO
+    ///
+    /// Every node gets its inverse, see `verify_variance_pairs`, including
+    /// the ones added with `add_node` before.
+    pub fn build_initial_graph(
+        &mut self,
+        constraints: Vec<&Constraint>,
+        interesting: &HashSet<String>,
+    ) {
+        self.interesting.extend(interesting.iter().cloned());
         for c in constraints {
-            // 1. add two node and 1-labeled edge
-            // TODO should we add left or right side mark label or not?
-            //    related to the set of interesting variables.
-            let node_l = self.add_node(Node {
-                base: c.left.clone(),
-                suffix_variance: Variance::Covariant,
-                sidemark: SideMark::None, // TODO
-            });
-            let node_r = self.add_node(Node {
-                base: c.right.clone(),
-                suffix_variance: Variance::Covariant,
-                sidemark: SideMark::None, // TODO
-            });
-            // add 1-labeled edge between them
-            self.graph.add_edge(node_l, node_r, EdgeLabel::One);
-            // 2. add each sub var node and edges.
-            // 2.1 left
-            self.add_recalls(node_l);
-            // 2.2 right
-            self.add_forgets(node_r);
-            // TODO add the start and end edge?
-
-            // 3-4 the inverse of the above
-            // 3. inverse node and 1-labeled edge
-            let r_node_l = self.add_node(Node {
-                base: c.left.clone(),
-                suffix_variance: Variance::Contravariant,
-                sidemark: SideMark::None, // TODO
-            });
-            let r_node_r = self.add_node(Node {
-                base: c.right.clone(),
-                suffix_variance: Variance::Contravariant,
-                sidemark: SideMark::None, // TODO
-            });
-            // add 1-labeled edge between them
-            self.graph.add_edge(r_node_r, r_node_l, EdgeLabel::One);
-            // 4.1 inverse left
-            self.add_recalls(r_node_l);
-            // 4.2 inverse right
-            self.add_forgets(r_node_r);
-            // TODO add the start and end edge?
-        }
-    }
-    pub fn saturate(&mut self) {
-        // reaching_set changed or graph changed
-        let mut changed = false;
-        let mut reaching_set = HashMap::<NodeIndex, HashSet<(FieldLabel, NodeIndex)>>::new();
-
-        let add_reaching =
-            |reaching_set: &mut HashMap<NodeIndex, HashSet<(FieldLabel, NodeIndex)>>,
-             dest,
-             elem| {
-                let set = reaching_set.entry(dest).or_default();
-                set.insert(elem)
-            };
-        // 1. add forget edge to reaching set
-        for edge in self.graph.raw_edges() {
-            if let EdgeLabel::Forget { capability } = &edge.weight {
-                changed |= add_reaching(
-                    &mut reaching_set,
-                    edge.target(),
-                    (capability.clone(), edge.source()),
-                );
+            self.add_constraint(c);
+        }
+        // the prefixes of a new inverse may lack theirs in turn.
+        while let Err(missing) = self.verify_variance_pairs() {
+            for node in missing {
+                self.add_node_with_chains(node.inverse());
             }
         }
-        while changed {
-            changed = false;
-            for edge in self.graph.raw_edges() {
-                if let EdgeLabel::One = &edge.weight {
-                    let source = edge.source();
-                    let target = edge.target();
-                    if let Some(set) = reaching_set.get(&source) {
-                        for (cap, node) in set.clone() {
-                            changed |= add_reaching(&mut reaching_set, target, (cap, node));
+    }
+
+    /// The nodes whose inverse, the copy with the opposite variance, is not
+    /// in the graph, sorted. START and END have none. `add_constraint` adds
+    /// both copies of every variable and its prefixes, but a node added on
+    /// its own with `add_node` may lack it.
+    pub fn verify_variance_pairs(&self) -> Result<(), Vec<Node>> {
+        let pseudo = [self.start, self.end];
+        let mut missing: Vec<Node> = self
+            .graph
+            .node_indices()
+            .filter(|ind| !pseudo.contains(&Some(*ind)))
+            .map(|ind| &self.graph[ind])
+            .filter(|node| !self.graph_node_map.contains_key(&node.inverse()))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort();
+        Err(missing)
+    }
+
+    /// Add `node` with its chains of prefixes in both directions, as it
+    /// could be on either side of a constraint.
+    fn add_node_with_chains(&mut self, node: Node) -> NodeIndex {
+        let ind = self.add_node(node);
+        self.add_prefix_chain(ind, false);
+        self.add_prefix_chain(ind, true);
+        ind
+    }
+
+    /// Add the nodes and edges of one constraint. The graph is not
+    /// saturated again until `resaturate` is called.
+    pub fn add_constraint(&mut self, c: &Constraint) {
+        // `x <= x` holds trivially.
+        if c.left == c.right {
+            return;
+        }
+        // an explicit `x.⊖` gives the variance of the first copy.
+        let (left, left_variance) = c.left.split_variance();
+        let (right, right_variance) = c.right.split_variance();
+        // nodes of interesting variables are marked with the side of the
+        // 1-labeled edge they sit on, so that paths only start from the
+        // source side (L) and only end at the target side (R).
+        let left_interesting = self.interesting.contains(&*left.name);
+        let right_interesting = self.interesting.contains(&*right.name);
+        let side = |interesting: bool, mark: SideMark| {
+            if interesting {
+                mark
+            } else {
+                SideMark::None
+            }
+        };
+        // 1. add two node and 1-labeled edge
+        let node_l = self.add_node(Node {
+            base: left.clone(),
+            suffix_variance: left_variance.clone(),
+            sidemark: side(left_interesting, SideMark::Left),
+        });
+        let node_r = self.add_node(Node {
+            base: right.clone(),
+            suffix_variance: right_variance.clone(),
+            sidemark: side(right_interesting, SideMark::Right),
+        });
+        // add 1-labeled edge between them
+        self.add_edge(node_l, node_r, EdgeLabel::One);
+        // 2. add each sub var node and edges.
+        // 2.1 left
+        self.add_prefix_chain(node_l, false);
+        // 2.2 right
+        self.add_prefix_chain(node_r, true);
+
+        // 3-4 the inverse of the above
+        // 3. inverse node and 1-labeled edge, the edge is reversed so
+        //    the sides swap.
+        let r_node_l = self.add_node(Node {
+            base: left.clone(),
+            suffix_variance: left_variance.invert(),
+            sidemark: side(left_interesting, SideMark::Right),
+        });
+        let r_node_r = self.add_node(Node {
+            base: right.clone(),
+            suffix_variance: right_variance.invert(),
+            sidemark: side(right_interesting, SideMark::Left),
+        });
+        // add 1-labeled edge between them
+        self.add_edge(r_node_r, r_node_l, EdgeLabel::One);
+        // 4.1 inverse left
+        self.add_prefix_chain(r_node_l, false);
+        // 4.2 inverse right
+        self.add_prefix_chain(r_node_r, true);
+    }
+
+    /// Saturate the graph (Algorithm D.2).
+    ///
+    /// The reaching set R(n) holds `(ℓ, m)` when there is a path from `m`
+    /// to `n` reading `forget ℓ` followed by `One` edges. Whenever an element
+    /// reaches a node, only that node's outgoing edges are examined: `One`
+    /// edges pass it along and a matching `recall ℓ` edge adds a `One` edge
+    /// from `m`. The pointer rule is applied once the worklist runs dry.
+    pub fn saturate(&mut self) -> Result<SaturationStats, SaturationError> {
+        self.saturate_with(&mut |_, _, _| {})
+    }
+
+    /// `saturate`, calling `on_edge` with the endpoints and label of each
+    /// edge it adds, in the order they are added.
+    pub fn saturate_with(
+        &mut self,
+        on_edge: &mut dyn FnMut(NodeIndex, NodeIndex, &EdgeLabel),
+    ) -> Result<SaturationStats, SaturationError> {
+        self.reaching_set = ReachingSet::default();
+        self.saturated_edges = 0;
+        self.skipped.clear();
+        self.resaturate_with(on_edge)
+    }
+
+    /// Continue the saturation after `add_constraint`, starting from the
+    /// reaching sets of the last one. Only the edges added since are
+    /// examined to seed the worklist.
+    pub fn resaturate(&mut self) -> Result<SaturationStats, SaturationError> {
+        self.resaturate_with(&mut |_, _, _| {})
+    }
+
+    /// `resaturate`, calling `on_edge` like `saturate_with`.
+    pub fn resaturate_with(
+        &mut self,
+        on_edge: &mut dyn FnMut(NodeIndex, NodeIndex, &EdgeLabel),
+    ) -> Result<SaturationStats, SaturationError> {
+        let mut stats = SaturationStats::default();
+        let mut reaching_set = std::mem::take(&mut self.reaching_set);
+        // 1. add forget edge to reaching set, and pass what already reaches
+        //    the source of the other new edges along them again.
+        for edge in &self.graph.raw_edges()[self.saturated_edges..] {
+            match &edge.weight {
+                EdgeLabel::Forget { capability } => {
+                    if reaching_set.insert(edge.target(), (capability.clone(), edge.source())) {
+                        stats.reaching_elems += 1;
+                    }
+                }
+                _ => {
+                    if let Some(set) = reaching_set.sets.get(&edge.source()) {
+                        for elem in set {
+                            reaching_set.worklist.push((edge.source(), elem.clone()));
                         }
                     }
                 }
             }
-            let mut to_add = Vec::new();
-            for edge in self.graph.raw_edges() {
-                if let EdgeLabel::Recall { capability } = &edge.weight {
-                    let source = edge.source();
-                    let target = edge.target();
-                    if let Some(set) = reaching_set.get(&source) {
-                        for (cap, node) in set {
-                            if cap == capability {
-                                log::debug!("Adding edge from {} to {} with {}", self.graph.node_weight(*node).unwrap(), self.graph.node_weight(target).unwrap(), EdgeLabel::One);
-                                to_add.push((node.to_owned(), target, EdgeLabel::One));
+        }
+        loop {
+            if stats.iterations == self.max_iterations {
+                // keep what was derived, so the graph is still usable.
+                self.reaching_set = reaching_set;
+                self.saturated_edges = self.graph.edge_count();
+                return Err(SaturationError::IterationLimit {
+                    iterations: stats.iterations,
+                });
+            }
+            stats.iterations += 1;
+            while let Some((node_ind, (cap, source))) = reaching_set.worklist.pop() {
+                let mut propagate = Vec::new();
+                let mut to_add = Vec::new();
+                for edge in self.graph.edges(node_ind) {
+                    match edge.weight() {
+                        EdgeLabel::One => propagate.push(edge.target()),
+                        EdgeLabel::Recall { capability } if capability == &cap => {
+                            to_add.push(edge.target())
+                        }
+                        _ => {}
+                    }
+                }
+                for target in propagate {
+                    if reaching_set.insert(target, (cap.clone(), source)) {
+                        stats.reaching_elems += 1;
+                    }
+                }
+                for target in to_add {
+                    if let SaturationMode::Approximate { edge_budget } = self.saturation_mode {
+                        if self.graph.edge_count() >= edge_budget {
+                            if self.skipped.insert((source, target)) {
+                                stats.edges_skipped += 1;
+                            }
+                            continue;
+                        }
+                    }
+                    log::debug!(
+                        "Adding edge from {} to {} with {}",
+                        self.graph.node_weight(source).unwrap(),
+                        self.graph.node_weight(target).unwrap(),
+                        EdgeLabel::One
+                    );
+                    if self.add_edge(source, target, EdgeLabel::One) {
+                        on_edge(source, target, &EdgeLabel::One);
+                        stats.edges_added += 1;
+                        self.derived
+                            .insert(EdgeIndex::new(self.graph.edge_count() - 1));
+                        // the new edge carries everything that already reaches its source.
+                        if let Some(set) = reaching_set.sets.get(&source) {
+                            for elem in set.clone() {
+                                if reaching_set.insert(target, elem) {
+                                    stats.reaching_elems += 1;
+                                }
                             }
                         }
                     }
                 }
             }
-            for (source, target, label) in to_add {
-                changed |= self.add_edge(source, target, label);
-            }
-            let mut to_add_invert = Vec::new();
-            for node_ind in self.graph.node_indices() {
-                let node_x = self.graph.node_weight(node_ind).unwrap();
-                if node_x.suffix_variance == Variance::Contravariant {
-                    if let Some(set) = reaching_set.get(&node_ind) {
-                        for (cap, node) in set {
-                            if cap == &FieldLabel::Store {
-                                log::debug!("node {} can reach node {} with {}.", self.graph.node_weight(*node).unwrap(), node_x, cap);
-                                to_add_invert.push((node.to_owned(), FieldLabel::Load, node_ind));
+            // the pointer rule only adds edges along with a missing inverse node.
+            let edges = self.graph.edge_count();
+            let added = self.apply_pointer_rule(&mut reaching_set);
+            for edge in &self.graph.raw_edges()[edges..] {
+                on_edge(edge.source(), edge.target(), &edge.weight);
+            }
+            if added == 0 {
+                break;
+            }
+            stats.pointer_rule_applications += added;
+            stats.reaching_elems += added;
+        }
+        self.reaching_set = reaching_set;
+        self.saturated_edges = self.graph.edge_count();
+        Ok(stats)
+    }
+
+    /// Apply Rule (S-Pointer) to the reaching sets, once for every node.
+    ///
+    /// The rule states `α.store ⊑ α.load`. During saturation (Algorithm
+    /// D.2) it becomes: when `(.store, m)` is in R(n) for a contravariant
+    /// node `n`, add `(.load, m)` to R(n⁻¹), where `n⁻¹` is the node with
+    /// the same variable and the opposite variance; and the same with
+    /// `.load` and `.store` swapped. The two are the labels of
+    /// `pointer_capabilities`. Returns the number of elements added to the
+    /// reaching sets.
+    ///
+    /// `add_constraint` always adds both variance copies of a node, but a
+    /// node added on its own with `add_node` may lack its inverse, which is
+    /// then created here, see `add_inverse_node`.
+    fn apply_pointer_rule(&mut self, reaching_set: &mut ReachingSet) -> usize {
+        let mut to_add_invert = Vec::new();
+        for node_ind in self.graph.node_indices() {
+            let node_x = self.graph.node_weight(node_ind).unwrap();
+            if node_x.suffix_variance == Variance::Contravariant {
+                if let Some(set) = reaching_set.sets.get(&node_ind) {
+                    for (cap, node) in set {
+                        if let Some(inverse) = self.pointer_capabilities.inverse(cap) {
+                            log::debug!(
+                                "node {} can reach node {} with {}.",
+                                self.graph.node_weight(*node).unwrap(),
+                                node_x,
+                                cap
+                            );
+                            to_add_invert.push((node.to_owned(), inverse.clone(), node_ind));
+                        }
+                    }
+                }
+            }
+        }
+        let mut added = 0;
+        for (source, cap, target) in to_add_invert {
+            // find the variance inverted node.
+            let node = self.graph.node_weight(target).unwrap().inverse();
+            log::debug!(
+                "Process: node {} can reach node {} with {}.",
+                self.graph.node_weight(source).unwrap(),
+                node,
+                self.pointer_capabilities.inverse(&cap).unwrap()
+            );
+            // find the target node.
+            log::debug!(
+                "Try to add reaching set elem ({}, {}) to R({})",
+                self.graph.node_weight(source).unwrap(),
+                cap,
+                node
+            );
+            let inv_target = match self.graph_node_map.get(&node) {
+                Some(ind) => *ind,
+                None => self.add_inverse_node(node, reaching_set),
+            };
+            if reaching_set.insert(inv_target, (cap, source)) {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Add `node`, the missing inverse of a node, in the middle of the
+    /// saturation, see `add_node_with_chains`. The new edges are passed to
+    /// the reaching sets like the ones `resaturate` starts from.
+    fn add_inverse_node(&mut self, node: Node, reaching_set: &mut ReachingSet) -> NodeIndex {
+        log::debug!("Adding the missing node {}", node);
+        let edges = self.graph.edge_count();
+        let ind = self.add_node_with_chains(node);
+        for edge in &self.graph.raw_edges()[edges..] {
+            match &edge.weight {
+                EdgeLabel::Forget { capability } => {
+                    reaching_set.insert(edge.target(), (capability.clone(), edge.source()));
+                }
+                _ => {
+                    if let Some(set) = reaching_set.sets.get(&edge.source()) {
+                        for elem in set {
+                            reaching_set.worklist.push((edge.source(), elem.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        ind
+    }
+
+    /// Remove the nodes that are not on a path from a node of an
+    /// `interesting` variable to another one, as they take no part in the
+    /// constraints `simplify` and `path_expr` read off the graph. START and
+    /// END are kept. Returns the number of nodes removed.
+    ///
+    /// Run it after saturation, as the removed nodes may be needed to
+    /// derive edges. The reaching sets are dropped, so a later `resaturate`
+    /// goes over the whole graph again.
+    pub fn prune(&mut self, interesting: &HashSet<String>) -> usize {
+        let reach = |seeds: &[NodeIndex], direction: petgraph::Direction| {
+            let mut visited: HashSet<NodeIndex> = seeds.iter().cloned().collect();
+            let mut worklist = seeds.to_vec();
+            while let Some(ind) = worklist.pop() {
+                for next in self.graph.neighbors_directed(ind, direction) {
+                    if visited.insert(next) {
+                        worklist.push(next);
+                    }
+                }
+            }
+            visited
+        };
+        let seeds: Vec<NodeIndex> = self
+            .graph
+            .node_indices()
+            .filter(|ind| interesting.contains(&*self.graph[*ind].base.name))
+            .collect();
+        let forward = reach(&seeds, petgraph::Direction::Outgoing);
+        let backward = reach(&seeds, petgraph::Direction::Incoming);
+        let pseudo = [self.start, self.end];
+        let keep = |ind: NodeIndex| {
+            (forward.contains(&ind) && backward.contains(&ind)) || pseudo.contains(&Some(ind))
+        };
+
+        // `filter_map` keeps the order of what is left, so the new indices
+        // follow from counting.
+        let mut nodes: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        for ind in self.graph.node_indices().filter(|ind| keep(*ind)) {
+            nodes.insert(ind, NodeIndex::new(nodes.len()));
+        }
+        let mut edges: HashMap<EdgeIndex, EdgeIndex> = HashMap::new();
+        for edge in self.graph.edge_references() {
+            if nodes.contains_key(&edge.source()) && nodes.contains_key(&edge.target()) {
+                edges.insert(edge.id(), EdgeIndex::new(edges.len()));
+            }
+        }
+        let removed = self.graph.node_count() - nodes.len();
+        self.graph = self.graph.filter_map(
+            |ind, node| nodes.contains_key(&ind).then(|| node.clone()),
+            |_, label| Some(label.clone()),
+        );
+        self.graph_node_map = self
+            .graph
+            .node_indices()
+            .map(|ind| (self.graph[ind].clone(), ind))
+            .collect();
+        self.start = self.start.map(|ind| nodes[&ind]);
+        self.end = self.end.map(|ind| nodes[&ind]);
+        self.truncated = self
+            .truncated
+            .iter()
+            .filter_map(|ind| nodes.get(ind).cloned())
+            .collect();
+        self.derived = self
+            .derived
+            .iter()
+            .filter_map(|edge| edges.get(edge).cloned())
+            .collect();
+        self.skipped = self
+            .skipped
+            .iter()
+            .filter_map(|(source, target)| Some((*nodes.get(source)?, *nodes.get(target)?)))
+            .collect();
+        self.reaching_set = ReachingSet::default();
+        self.saturated_edges = 0;
+        removed
+    }
+
+    /// Read the constraints off the paths between interesting nodes.
+    ///
+    /// After saturation, a path is accepted if its labels form a
+    /// `recall* forget*` word, where `One` edges read as the empty word.
+    /// The recalled labels extend the start node's variable and the
+    /// forgotten labels, read backwards, extend the end node's variable.
+    /// The variance of the node where the recalls end decides which side
+    /// of the derived constraint is the subtype.
+    pub fn path_expr(&self, interesting: &HashSet<Node>) -> Vec<Constraint> {
+        let mut nodes: Vec<NodeIndex> = interesting
+            .iter()
+            .filter_map(|n| self.graph_node_map.get(n).cloned())
+            .collect();
+        nodes.sort_by_key(|ind| self.graph.node_weight(*ind).unwrap().to_string());
+        let ends = nodes.iter().cloned().collect();
+        self.paths_between(&nodes, &ends)
+    }
+
+    /// Add the START and END nodes of the transducer (Algorithm D.1).
+    ///
+    /// Each interesting node gets an edge from START and an edge to END, so
+    /// the accepted words are the START→END paths read by
+    /// `start_end_paths`. The two nodes have no fields, so they never take
+    /// part in the recall/forget expansion.
+    pub fn add_start_end(&mut self, interesting: &HashSet<Node>) {
+        let start = self.add_node(Node::start());
+        let end = self.add_node(Node::end());
+        self.start = Some(start);
+        self.end = Some(end);
+        for node in interesting {
+            if let Some(&ind) = self.graph_node_map.get(node) {
+                self.add_edge(start, ind, EdgeLabel::One);
+                self.add_edge(ind, end, EdgeLabel::One);
+            }
+        }
+    }
+
+    /// `path_expr` over the nodes connected to START and END.
+    pub fn start_end_paths(&self) -> Vec<Constraint> {
+        let (Some(start), Some(end)) = (self.start, self.end) else {
+            return Vec::new();
+        };
+        let mut starts: Vec<NodeIndex> = self.graph.neighbors(start).collect();
+        starts.sort_by_key(|ind| self.graph.node_weight(*ind).unwrap().to_string());
+        let ends = self
+            .graph
+            .neighbors_directed(end, petgraph::Direction::Incoming)
+            .collect();
+        self.paths_between(&starts, &ends)
+    }
+
+    /// Simplify the constraints to the ones between the `interesting`
+    /// variables (Algorithm C), so the temporaries are eliminated.
+    ///
+    /// `self` must be saturated, as done by `new`. Each START→END path
+    /// through the base nodes of the interesting variables gives one
+    /// constraint. Then the constraints implied by two others through
+    /// transitivity are dropped one at a time, each for two that are still
+    /// kept, so that a cycle keeps what it implies.
+    pub fn simplify(&self, interesting: &HashSet<String>) -> Vec<Constraint> {
+        let nodes: HashSet<Node> = self
+            .graph_node_map
+            .keys()
+            .filter(|n| n.base.fields.is_empty() && interesting.contains(&*n.base.name))
+            .cloned()
+            .collect();
+        let mut g = self.clone();
+        g.add_start_end(&nodes);
+        let constraints = g.start_end_paths();
+        let mut uppers: HashMap<&DerivedTypeVariable, Vec<&DerivedTypeVariable>> = HashMap::new();
+        for c in &constraints {
+            uppers.entry(&c.left).or_default().push(&c.right);
+        }
+        // a constraint is only dropped for two that are still kept, so the
+        // kept ones always imply the same, even around a cycle.
+        let mut kept: HashSet<&Constraint> = constraints.iter().collect();
+        for c in &constraints {
+            let implied = uppers[&c.left].iter().any(|mid| {
+                *mid != &c.right
+                    && kept.contains(&Constraint::new(c.left.clone(), (*mid).clone()))
+                    && kept.contains(&Constraint::new((*mid).clone(), c.right.clone()))
+            });
+            if implied {
+                kept.remove(c);
+            }
+        }
+        constraints
+            .iter()
+            .filter(|c| kept.contains(c))
+            .cloned()
+            .collect()
+    }
+
+    /// The constraints read off the paths from each of `starts` to `ends`.
+    ///
+    /// The search runs over the states: the nodes with the pending labels.
+    /// Each state is expanded once, and a label is taken at most once from
+    /// a node on one side, so a recursive type is unrolled one time and the
+    /// cost stays linear in the states reached.
+    fn paths_between(&self, starts: &[NodeIndex], ends: &HashSet<NodeIndex>) -> Vec<Constraint> {
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        for &start_ind in starts {
+            let start = self.graph.node_weight(start_ind).unwrap();
+            let initial = PathState {
+                node: start_ind,
+                recalls: Vec::new(),
+                forgets: Vec::new(),
+                middle: None,
+            };
+            let mut visited = HashSet::from([initial.clone()]);
+            let mut worklist = vec![initial];
+            while let Some(state) = worklist.pop() {
+                let node = self.graph.node_weight(state.node).unwrap();
+                if state.node != start_ind && ends.contains(&state.node) {
+                    let mut left = start.base.clone();
+                    left.fields_mut().extend(
+                        state
+                            .recalls
+                            .iter()
+                            .map(|(_, label)| FieldLabelId::new(label.clone())),
+                    );
+                    let mut right = node.base.clone();
+                    right.fields_mut().extend(
+                        state
+                            .forgets
+                            .iter()
+                            .rev()
+                            .map(|(_, label)| FieldLabelId::new(label.clone())),
+                    );
+                    let constraint = match state.middle.as_ref().unwrap_or(&node.suffix_variance) {
+                        Variance::Covariant => Constraint::new(left, right),
+                        Variance::Contravariant => Constraint::new(right, left),
+                    };
+                    if constraint.left != constraint.right && seen.insert(constraint.clone()) {
+                        result.push(constraint);
+                    }
+                    continue;
+                }
+                // pushed in reverse, so the edges are followed in order.
+                let mut next = Vec::new();
+                for edge in self.graph.edges(state.node) {
+                    let target = edge.target();
+                    if target == start_ind || Some(target) == self.end {
+                        continue;
+                    }
+                    let mut succ = state.clone();
+                    succ.node = target;
+                    match edge.weight() {
+                        EdgeLabel::One => {}
+                        EdgeLabel::Recall { capability } => {
+                            // no recall after a forget.
+                            if state.middle.is_some()
+                                || state.recalls.iter().any(|(from, _)| *from == state.node)
+                            {
+                                continue;
+                            }
+                            succ.recalls.push((state.node, capability.clone()));
+                        }
+                        EdgeLabel::Forget { capability } => {
+                            if state.forgets.iter().any(|(from, _)| *from == state.node) {
+                                continue;
                             }
-                            if cap == &FieldLabel::Load {
-                                log::debug!("node {} can reach node {} with {}.", self.graph.node_weight(*node).unwrap(), node_x, cap);
-                                to_add_invert.push((node.to_owned(), FieldLabel::Store, node_ind));
+                            if state.middle.is_none() {
+                                succ.middle = Some(node.suffix_variance.clone());
                             }
+                            succ.forgets.push((state.node, capability.clone()));
                         }
                     }
+                    if visited.insert(succ.clone()) {
+                        next.push(succ);
+                    }
                 }
-            }
-            for (source, cap, target) in to_add_invert {
-                // find the variance inverted node.
-                let mut node = self.graph.node_weight(target).unwrap().clone();
-                log::debug!("Process: node {} can reach node {} with {}.", self.graph.node_weight(source).unwrap(), node, if cap == FieldLabel::Load {"store"} else {"load"} );
-                node.suffix_variance = node.suffix_variance.invert();
-                // find the target node.
-                log::debug!("Try to add reaching set elem ({}, {}) to R({})", self.graph.node_weight(source).unwrap(), cap, node);
-                let inv_target = self.graph_node_map.get(&node).unwrap();
-                changed |= add_reaching(&mut reaching_set, inv_target.clone(), (cap, source));
+                worklist.extend(next.into_iter().rev());
             }
         }
+        result
     }
 }
 
-pub fn infer_proc_types(program: &Program) {
+/// Infer the type scheme of each procedure, callees first, with the pointer
+/// rule applied to the `pointer` labels.
+///
+/// The groups of mutually recursive procedures are ranked by the longest
+/// chain of calls below them, and the groups of one rank, which only use
+/// the schemes of lower ranks, are solved in parallel.
+///
+/// A caller refers to the callee `f` at its `k`-th call site as `f@k`, and
+/// to its only or first one as `f`. Each site gets its own instance of the
+/// scheme of `f`, so the calls don't alias.
+///
+/// Returns the simplified constraints of each procedure keyed by its name,
+/// or the error of the first saturation that did not terminate.
+pub fn infer_proc_types(
+    program: &Program,
+    pointer: &PointerCapabilities,
+    dot_dir: Option<&Path>,
+) -> Result<HashMap<String, Vec<Constraint>>, SaturationError> {
+    infer_proc_types_ranked(program, pointer, dot_dir, DEFAULT_MAX_ITERATIONS, true)
+}
+
+/// Whether the constraints of `scheme` prove every constraint of
+/// `original`, as decided by `ConstraintGraph::proves_subtype` on the
+/// saturated graph of `scheme`. The variables of `original` are added to
+/// the graph first, with their prefix chains in both variances, so that a
+/// field the scheme only reaches through its prefix, like `F.in.load` from
+/// `F.in`, gets its own edges. A constraint over a variable that `scheme`
+/// does not relate is not proven, unless its two sides are the same.
+///
+/// With the arguments the other way around, `entails(original, scheme)`
+/// checks that a simplified scheme only states what the original
+/// constraints prove.
+pub fn entails(scheme: &[Constraint], original: &[Constraint]) -> bool {
+    let mut graph = ConstraintGraph::construct();
+    graph.build_initial_graph(scheme.iter().collect(), &HashSet::new());
+    for dtv in original.iter().flat_map(|c| [&c.left, &c.right]) {
+        for suffix_variance in [Variance::Covariant, Variance::Contravariant] {
+            graph.add_node_with_chains(Node {
+                base: dtv.clone(),
+                suffix_variance,
+                sidemark: SideMark::None,
+            });
+        }
+    }
+    // what a partial saturation proves still holds.
+    if let Err(e) = graph.saturate() {
+        log::error!("{}", e);
+    }
+    original
+        .iter()
+        .all(|c| c.left == c.right || graph.proves_subtype(&c.left, &c.right))
+}
+
+/// `infer_proc_types`, with each saturation limited to `max_iterations`
+/// rounds, and the groups of one rank solved in parallel if `parallel`.
+pub(crate) fn infer_proc_types_ranked(
+    program: &Program,
+    pointer: &PointerCapabilities,
+    dot_dir: Option<&Path>,
+    max_iterations: usize,
+    parallel: bool,
+) -> Result<HashMap<String, Vec<Constraint>>, SaturationError> {
     // type schemes for each function
     let mut type_schemes: HashMap<String, Vec<Constraint>> = std::collections::HashMap::new();
+    let proc_index: HashMap<&String, NodeIndex> = program
+        .call_graph
+        .node_indices()
+        .map(|ind| (program.call_graph.node_weight(ind).unwrap(), ind))
+        .collect();
 
-    // find the scc in the callgraph, and iterate in post order
+    // find the scc in the callgraph, and rank them in post order: an scc
+    // is one above the highest of its callees.
     let sccs = condensation(program.call_graph.clone(), true);
     let topo_sort = toposort(&sccs, None).unwrap();
+    let mut rank: HashMap<NodeIndex, usize> = HashMap::new();
+    let mut ranks: Vec<Vec<&Vec<String>>> = Vec::new();
     for ind in topo_sort.iter().rev() {
-        let mut constraints: Vec<&Constraint> = Vec::new();
-        // collect constraints for the scc:
-        // 1. instantiate type schemes for each call
-        // 1. instantiate constraints for global variable.
-        for proc in sccs.node_weight(*ind).unwrap() {
-            assert!(!type_schemes.contains_key(proc));
-            // TODO for each call outside of SCC, instantiate the type scheme.
-            for c in program.proc_constraints.get(proc).unwrap() {
-                constraints.push(c);
+        let r = sccs
+            .neighbors(*ind)
+            .map(|callee| rank[&callee] + 1)
+            .max()
+            .unwrap_or(0);
+        rank.insert(*ind, r);
+        if ranks.len() <= r {
+            ranks.resize_with(r + 1, Vec::new);
+        }
+        ranks[r].push(sccs.node_weight(*ind).unwrap());
+    }
+    for sccs in ranks {
+        let solve = |scc: &&Vec<String>| {
+            solve_scc(
+                program,
+                pointer,
+                dot_dir,
+                max_iterations,
+                scc,
+                &proc_index,
+                &type_schemes,
+            )
+        };
+        let solved: Vec<Vec<(String, Vec<Constraint>)>> = if parallel {
+            sccs.par_iter().map(solve).collect::<Result<_, _>>()?
+        } else {
+            sccs.iter().map(solve).collect::<Result<_, _>>()?
+        };
+        type_schemes.extend(solved.into_iter().flatten());
+    }
+    Ok(type_schemes)
+}
+
+/// The type schemes of the procedures of one scc, from their constraints and
+/// the `type_schemes` of their callees outside of it.
+fn solve_scc(
+    program: &Program,
+    pointer: &PointerCapabilities,
+    dot_dir: Option<&Path>,
+    max_iterations: usize,
+    scc: &[String],
+    proc_index: &HashMap<&String, NodeIndex>,
+    type_schemes: &HashMap<String, Vec<Constraint>>,
+) -> Result<Vec<(String, Vec<Constraint>)>, SaturationError> {
+    let mut constraints: Vec<Constraint> = Vec::new();
+    // collect constraints for the scc:
+    // 1. instantiate type schemes for each call
+    // 1. instantiate constraints for global variable.
+    for proc in scc {
+        assert!(!type_schemes.contains_key(proc));
+        // for each call outside of SCC, instantiate the type scheme once per
+        // call site, and point the caller's references at the instances.
+        let mut renames: HashMap<String, String> = HashMap::new();
+        for edge in program.call_graph.edges(proc_index[proc]) {
+            let callee = program.call_graph.node_weight(edge.target()).unwrap();
+            if let Some(scheme) = type_schemes.get(callee) {
+                let scheme = callee_scheme(scheme, callee, program);
+                for site in 0..(*edge.weight()).max(1) {
+                    let suffix = format!("@{}_{}", proc, site);
+                    constraints.extend(scheme.instantiate(&suffix));
+                    renames.insert(
+                        format!("{}@{}", callee, site),
+                        format!("{}{}", callee, suffix),
+                    );
+                }
+                renames.insert(callee.clone(), format!("{}@{}_0", callee, proc));
             }
         }
+        if let Some(cs) = program.proc_constraints.get(proc) {
+            let rename = |dtv: &DerivedTypeVariable| {
+                let mut dtv = dtv.clone();
+                if let Some(name) = renames.get(&*dtv.name) {
+                    dtv.name = name.as_str().into();
+                }
+                dtv
+            };
+            constraints.extend(cs.iter().map(|c| Constraint {
+                left: rename(&c.left),
+                right: rename(&c.right),
+                provenance: c.provenance.clone(),
+            }));
+        }
+    }
+
+    // the graphs of each group of procedures go to a directory of its own,
+    // named so that it stays inside `dot_dir`.
+    let scc_dir = dot_dir.map(|dir| {
+        let mut names: Vec<String> = scc.iter().map(|name| sanitize(name)).collect();
+        names.sort();
+        dir.join(names.join("+"))
+    });
+    let mut cg = ConstraintGraph::construct().with_max_iterations(max_iterations);
+    cg.variance_table = program.variance_table.clone();
+    cg.pointer_capabilities = pointer.clone();
+    cg.build_and_saturate(
+        constraints.iter().collect(),
+        &HashSet::new(),
+        scc_dir.as_deref(),
+    )?;
+    // 3. collect the set of interesting vars and run pathexpr on them
+    let interesting: HashSet<Node> = cg
+        .graph_node_map
+        .keys()
+        .filter(|n| {
+            n.base.fields.is_empty()
+                && (scc.iter().any(|p| **p == *n.base.name) || n.base.is_type_constant())
+        })
+        .cloned()
+        .collect();
+    let simplified = cg.path_expr(&interesting);
+    Ok(scc
+        .iter()
+        .map(|proc| {
+            let scheme = simplified
+                .iter()
+                .filter(|c| *c.left.name == **proc || *c.right.name == **proc)
+                .cloned()
+                .collect();
+            (proc.clone(), scheme)
+        })
+        .collect())
+}
+
+/// Write the debug graph `name` into `dir`, if there is one. The contents
+/// are only rendered when they are written, and a graph that cannot be
+/// written is logged and skipped.
+pub fn write_debug_graph(dir: Option<&Path>, name: &str, contents: impl FnOnce() -> String) {
+    if let Some(dir) = dir {
+        let path = dir.join(name);
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(&path, contents())) {
+            log::error!("cannot write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// A copy of `g` with the nodes sorted, and the edges sorted by their
+/// endpoints and weight.
+pub fn canonical<N: Ord + Clone, E: Ord + Clone>(g: &DiGraph<N, E>) -> DiGraph<N, E> {
+    let mut nodes: Vec<NodeIndex> = g.node_indices().collect();
+    nodes.sort_by(|a, b| g[*a].cmp(&g[*b]));
+    let mut sorted = DiGraph::with_capacity(g.node_count(), g.edge_count());
+    let mut index_map = HashMap::new();
+    for ind in nodes {
+        index_map.insert(ind, sorted.add_node(g[ind].clone()));
+    }
+    let mut edges: Vec<(NodeIndex, NodeIndex, &E)> = g
+        .edge_references()
+        .map(|e| (index_map[&e.source()], index_map[&e.target()], e.weight()))
+        .collect();
+    edges.sort();
+    for (source, target, weight) in edges {
+        sorted.add_edge(source, target, weight.clone());
+    }
+    sorted
+}
 
-        let mut cg = ConstraintGraph::new(constraints);
-        // 3. collect the set of interesting vars and run pathexpr on them
-        // 4. create sketches for each function
+/// Write `g` as GraphML, with a `label` attribute on every node and edge.
+pub fn to_graphml<N, E>(
+    g: &DiGraph<N, E>,
+    node_label: impl Fn(&N) -> String,
+    edge_label: impl Fn(&E) -> String,
+) -> String {
+    fn escape(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str(
+        "  <key id=\"node_label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+    );
+    out.push_str(
+        "  <key id=\"edge_label\" for=\"edge\" attr.name=\"label\" attr.type=\"string\"/>\n",
+    );
+    out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+    for ind in g.node_indices() {
+        out.push_str(&format!(
+            "    <node id=\"n{}\"><data key=\"node_label\">{}</data></node>\n",
+            ind.index(),
+            escape(&node_label(&g[ind]))
+        ));
+    }
+    for edge in g.edge_references() {
+        out.push_str(&format!(
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"><data key=\"edge_label\">{}</data></edge>\n",
+            edge.id().index(),
+            edge.source().index(),
+            edge.target().index(),
+            escape(&edge_label(edge.weight()))
+        ));
     }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+/// The simplified constraints of `callee` as a scheme to instantiate at its
+/// call sites. The other procedures they mention are its globals, while
+/// `callee` itself is renamed in each instance.
+fn callee_scheme(constraints: &[Constraint], callee: &str, program: &Program) -> Scheme {
+    let globals = constraints
+        .iter()
+        .flat_map(|c| [&*c.left.name, &*c.right.name])
+        .filter(|name| *name != callee && program.proc_constraints.contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+    Scheme::new(constraints.to_vec()).with_globals(globals)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ConstraintGraph;
+    use super::{
+        entails, infer_proc_types, ConstraintGraph, EdgeLabel, ReachingSet, SaturationError,
+        SaturationMode, DEFAULT_MAX_ITERATIONS,
+    };
     use crate::graph::{Node, SideMark};
     use crate::parser::{parse_constraint, parse_derived_type_variable};
-    use crate::schema::{Constraint, DerivedTypeVariable, Variance};
+    use crate::schema::{
+        Constraint, DerivedTypeVariable, FieldLabel, PointerCapabilities, Program, Variance,
+        VarianceTable,
+    };
     use petgraph::dot::Dot;
+    use petgraph::graph::{DiGraph, NodeIndex};
+    use petgraph::visit::EdgeRef;
+    use std::collections::{HashMap, HashSet};
     use std::fs::{self, File};
-    use std::io::{Read, Write};
+    use std::io::Write;
 
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -361,29 +1686,42 @@ mod tests {
         let mut constraints = Vec::new();
         for c in cons {
             let c = parse_constraint(c).unwrap();
-            assert!(c.0.len() == 0);
+            assert!(c.0.is_empty());
             constraints.push(c.1);
         }
         constraints
     }
 
+    const SLIDES_EXAMPLE: [&str; 9] = [
+        "F.in_stack0 <= 𝛿",
+        "𝛼 <= 𝜑",
+        "𝛿 <= 𝜑",
+        "𝜑.load.σ4@0 <= 𝛼",
+        "𝜑.load.σ4@4 <= 𝛼'",
+        "𝛼' <= close.in_stack0",
+        "close.out_eax <= F.out_eax",
+        "close.in_stack0 <= _FileDescriptor",
+        "_SuccessZ <= close.out_eax",
+    ];
+
+    const SATURATION_EXAMPLE: [&str; 4] = ["y <= p", "p <= x", "_A <= x.store", "y.load <= _B"];
+
+    /// Compare DOT output ignoring the order edges were added in.
+    fn assert_same_dot(dot: &str, path: &str) {
+        let answer = fs::read_to_string(path).expect("Unable to read the dot fixture");
+        let mut dot: Vec<&str> = dot.lines().collect();
+        let mut answer: Vec<&str> = answer.lines().collect();
+        dot.sort();
+        answer.sort();
+        assert_eq!(dot, answer);
+    }
+
     #[test]
     fn test_slides_example() {
-        let constraints = [
-            "F.in_stack0 <= 𝛿",
-            "𝛼 <= 𝜑",
-            "𝛿 <= 𝜑",
-            "𝜑.load.σ4@0 <= 𝛼",
-            "𝜑.load.σ4@4 <= 𝛼'",
-            "𝛼' <= close.in_stack0",
-            "close.out_eax <= F.out_eax",
-            "close.in_stack0 <= _FileDescriptor",
-            "_SuccessZ <= close.out_eax",
-        ];
-        let constraints = parse_constraint_str(&constraints);
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
 
         let mut cg = ConstraintGraph::construct();
-        cg.build_initial_graph(constraints.iter().collect());
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
         let dot = Dot::new(&cg.graph).to_string();
         // let mut file = File::create("slides_example.dot").unwrap();
         // write!(file, "{:?}", Dot::new(&cg.graph)).unwrap();
@@ -395,13 +1733,8 @@ mod tests {
     #[test]
     fn test_saturation() {
         init();
-        let constraints = parse_constraint_str(&[
-            "y <= p",
-            "p <= x",
-            "_A <= x.store",
-            "y.load <= _B"
-        ]);
-        let cg = ConstraintGraph::new(constraints.iter().collect());
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
         
         let mut file = File::create("sat-paper.dot").unwrap();
         write!(file, "{:?}", Dot::new(&cg.graph)).unwrap();
@@ -424,4 +1757,1088 @@ mod tests {
         }
         assert!(has_one, "Cannot infer subtype relation x.store <= y.load !");
     }
+
+    #[test]
+    fn test_proves_subtype() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert!(cg.proves_subtype(&dtv("x.store"), &dtv("y.load")));
+        // through y <= p <= x
+        assert!(cg.proves_subtype(&dtv("y"), &dtv("x")));
+        assert!(!cg.proves_subtype(&dtv("x"), &dtv("y")));
+        assert!(!cg.proves_subtype(&dtv("y.load"), &dtv("x.store")));
+        assert!(!cg.proves_subtype(&dtv("no_such_var"), &dtv("x")));
+    }
+
+    #[test]
+    fn test_accepts() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let node = |name: &str, suffix_variance| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance,
+            sidemark: SideMark::None,
+        };
+        let in_stack0 = [FieldLabel::InPattern("stack0".to_string())];
+        // `close.in_stack0 <= _FileDescriptor` builds the recall chain, in
+        // both variances.
+        assert!(cg.accepts(&node("close", Variance::Covariant), &in_stack0));
+        assert!(cg.accepts(&node("close", Variance::Contravariant), &in_stack0));
+        assert!(!cg.accepts(&node("close", Variance::Covariant), &[FieldLabel::Load]));
+        assert!(cg.accepts(&node("close", Variance::Covariant), &[]));
+        assert!(!cg.accepts(&node("no_such_var", Variance::Covariant), &[]));
+        // 𝛿 <= 𝜑, then recall load and σ4@0 from 𝜑
+        let load_deref: Vec<FieldLabel> = parse_derived_type_variable("x.load.σ4@0")
+            .unwrap()
+            .1
+            .fields
+            .iter()
+            .map(|label| label.get().clone())
+            .collect();
+        assert!(cg.accepts(&node("𝛿", Variance::Covariant), &load_deref));
+        assert!(!cg.accepts(&node("𝛿", Variance::Covariant), &in_stack0));
+    }
+
+    #[test]
+    fn test_nondeterministic_nodes() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        assert!(cg.nondeterministic_nodes().is_empty());
+        let node = |name: &str, sidemark| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance: Variance::Covariant,
+            sidemark,
+        };
+        // recall load from x leads to two copies of x.load.
+        let x = cg.add_node(node("x", SideMark::None));
+        let x_load = cg.add_node(node("x.load", SideMark::None));
+        let x_load_l = cg.add_node(node("x.load", SideMark::Left));
+        let x_store = cg.add_node(node("x.store", SideMark::None));
+        cg.add_edge(
+            x,
+            x_load,
+            EdgeLabel::Recall {
+                capability: FieldLabel::Load,
+            },
+        );
+        cg.add_edge(
+            x,
+            x_load_l,
+            EdgeLabel::Recall {
+                capability: FieldLabel::Load,
+            },
+        );
+        cg.add_edge(
+            x,
+            x_store,
+            EdgeLabel::Recall {
+                capability: FieldLabel::Store,
+            },
+        );
+        assert_eq!(cg.nondeterministic_nodes(), [(x, FieldLabel::Load)]);
+    }
+
+    #[test]
+    fn test_worklist_saturation() {
+        // the saturated graphs produced by the full re-scan fixpoint, with the
+        // nodes numbered in input order.
+        let saturated = |constraints: &[Constraint]| {
+            let mut cg = ConstraintGraph::construct();
+            cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+            cg.saturate().unwrap();
+            cg
+        };
+        // saturation derives nothing new for the slides example.
+        let cg = saturated(&parse_constraint_str(&SLIDES_EXAMPLE));
+        assert_same_dot(
+            &format!("{:?}", Dot::new(&cg.graph)),
+            "tests/slides_example.dot",
+        );
+
+        let cg = saturated(&parse_constraint_str(&SATURATION_EXAMPLE));
+        assert_same_dot(
+            &format!("{:?}", Dot::new(&cg.graph)),
+            "tests/saturation_example.dot",
+        );
+    }
+
+    #[test]
+    fn test_path_expr() {
+        init();
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let mut cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let node = |dtv: &str| Node {
+            base: parse_derived_type_variable(dtv).unwrap().1,
+            suffix_variance: Variance::Covariant,
+            sidemark: SideMark::None,
+        };
+
+        let simplified = cg.path_expr(&HashSet::from([node("_A"), node("_B")]));
+        let expected = parse_constraint_str(&["_A <= _B"]);
+        assert!(simplified.contains(&expected[0]), "{:?}", simplified);
+
+        let simplified = cg.path_expr(&HashSet::from([node("x.store"), node("y.load")]));
+        let expected = parse_constraint_str(&["x.store <= y.load"]);
+        assert_eq!(simplified, expected);
+
+        cg.add_start_end(&HashSet::from([node("x.store"), node("y.load")]));
+        assert_eq!(cg.start_end_paths(), expected);
+    }
+
+    #[test]
+    fn test_explicit_variance() {
+        let constraints = parse_constraint_str(&["x.load.⊖ <= y"]);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let x_load = parse_derived_type_variable("x.load").unwrap().1;
+        let y = parse_derived_type_variable("y").unwrap().1;
+        let node = |base: &DerivedTypeVariable, suffix_variance| Node {
+            base: base.clone(),
+            suffix_variance,
+            sidemark: SideMark::None,
+        };
+        let from = cg.graph_node_map[&node(&x_load, Variance::Contravariant)];
+        let to = cg.graph_node_map[&node(&y, Variance::Covariant)];
+        assert!(cg.graph.contains_edge(from, to), "{}", cg.to_dot());
+        let from = cg.graph_node_map[&node(&y, Variance::Contravariant)];
+        let to = cg.graph_node_map[&node(&x_load, Variance::Covariant)];
+        assert!(cg.graph.contains_edge(from, to), "{}", cg.to_dot());
+    }
+
+    #[test]
+    fn test_no_self_loops() {
+        let constraints = parse_constraint_str(&["x <= x", "x.load <= x.load", "x <= y"]);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        for edge in cg.graph.edge_references() {
+            assert_ne!(edge.source(), edge.target(), "{}", cg.to_dot());
+        }
+        // only `x <= y` adds nodes.
+        assert_eq!(cg.graph.node_count(), 4);
+    }
+
+    #[test]
+    fn test_graphml() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let graphml = cg.to_graphml();
+        let mut reader = quick_xml::Reader::from_str(&graphml);
+        let (mut nodes, mut edges) = (0, 0);
+        loop {
+            match reader.read_event().unwrap() {
+                quick_xml::events::Event::Start(e) if e.name().into_inner() == "node" => nodes += 1,
+                quick_xml::events::Event::Start(e) if e.name().into_inner() == "edge" => edges += 1,
+                quick_xml::events::Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(nodes, cg.graph.node_count());
+        assert_eq!(edges, cg.graph.edge_count());
+        assert!(graphml.contains(">close.in_stack0.⊕<"), "{}", graphml);
+    }
+
+    #[test]
+    fn test_deterministic_dot() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let dots: Vec<String> = (0..2)
+            .map(|_| ConstraintGraph::new(constraints.iter().collect(), &HashSet::new()).to_dot())
+            .collect();
+        assert_eq!(dots[0], dots[1]);
+    }
+
+    #[test]
+    fn test_side_marks() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let interesting = HashSet::from(["F".to_string(), "close".to_string()]);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &interesting);
+        let dot = format!("{:?}", Dot::new(&cg.graph));
+        // F.in_stack0 <= 𝛿
+        assert!(dot.contains("\"L:F.in_stack0.⊕\""), "{}", dot);
+        assert!(dot.contains("\"L:F.⊖\""), "{}", dot);
+        assert!(dot.contains("\"R:F.in_stack0.⊖\""), "{}", dot);
+        // 𝛼' <= close.in_stack0 <= _FileDescriptor
+        assert!(dot.contains("\"R:close.in_stack0.⊕\""), "{}", dot);
+        assert!(dot.contains("\"L:close.in_stack0.⊕\""), "{}", dot);
+        // other variables are not marked
+        assert!(dot.contains("\"𝛿.⊕\""), "{}", dot);
+        assert!(!dot.contains(":𝛿"), "{}", dot);
+    }
+
+    #[test]
+    fn test_pointer_rule() {
+        let mut cg = ConstraintGraph::construct();
+        let node = |name: &str, suffix_variance| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance,
+            sidemark: SideMark::None,
+        };
+        let m = cg.add_node(node("m", Variance::Covariant));
+        let n = cg.add_node(node("n", Variance::Contravariant));
+        let n_inv = cg.add_node(node("n", Variance::Covariant));
+        let mut reaching_set = ReachingSet::default();
+        reaching_set.insert(n, (FieldLabel::Store, m));
+        assert_eq!(cg.apply_pointer_rule(&mut reaching_set), 1);
+        assert!(reaching_set.sets[&n_inv].contains(&(FieldLabel::Load, m)));
+        // nothing new the second time
+        assert_eq!(cg.apply_pointer_rule(&mut reaching_set), 0);
+        // covariant nodes are left alone
+        reaching_set.insert(n_inv, (FieldLabel::Store, m));
+        assert_eq!(cg.apply_pointer_rule(&mut reaching_set), 0);
+        assert!(!reaching_set.sets[&n].contains(&(FieldLabel::Load, m)));
+    }
+
+    #[test]
+    fn test_saturation_stats() {
+        // the slides example needs no new edges.
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        let stats = cg.saturate().unwrap();
+        assert_eq!(stats.edges_added, 0);
+        assert!(stats.reaching_elems > 0);
+        assert!((1..=3).contains(&stats.iterations), "{:?}", stats);
+
+        // x.store <= y.load comes from the pointer rule.
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        let edges = cg.graph.edge_count();
+        let stats = cg.saturate().unwrap();
+        assert!(stats.edges_added > 0);
+        assert_eq!(cg.graph.edge_count(), edges + stats.edges_added);
+        assert!(stats.pointer_rule_applications > 0);
+        assert!((2..=4).contains(&stats.iterations), "{:?}", stats);
+        // nothing is left to do.
+        let again = cg.resaturate().unwrap();
+        assert_eq!(again.edges_added, 0);
+        assert_eq!(again.reaching_elems, 0);
+    }
+
+    #[test]
+    fn test_iteration_limit() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut cg = ConstraintGraph::construct().with_max_iterations(0);
+        let err = cg.build_and_saturate(constraints.iter().collect(), &HashSet::new(), None);
+        assert_eq!(err, Err(SaturationError::IterationLimit { iterations: 0 }));
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "saturation did not terminate after 0 iterations"
+        );
+        // the limit holds for resaturate too, and a generous one is enough.
+        assert!(cg.resaturate().is_err());
+        let mut cg = cg.with_max_iterations(DEFAULT_MAX_ITERATIONS);
+        assert!(cg.saturate().is_ok());
+    }
+
+    #[test]
+    fn test_reaching_set() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let node = |name: &str, suffix_variance| {
+            cg.graph_node_map[&Node {
+                base: parse_derived_type_variable(name).unwrap().1,
+                suffix_variance,
+                sidemark: SideMark::None,
+            }]
+        };
+        // `_A <= x.store`: _A flows into x.store, which forgets store to x.
+        let a = node("_A", Variance::Covariant);
+        let x_store = node("x.store", Variance::Covariant);
+        assert!(cg.graph.contains_edge(a, x_store));
+        let x = node("x", Variance::Contravariant);
+        assert!(cg
+            .reaching_set(x)
+            .unwrap()
+            .contains(&(FieldLabel::Store, x_store)));
+        // and on through x⊖ <= p⊖ <= y⊖, where the pointer rule turns it
+        // into a load for y⊕.
+        let y = node("y", Variance::Contravariant);
+        assert!(cg
+            .reaching_set(y)
+            .unwrap()
+            .contains(&(FieldLabel::Store, x_store)));
+        let y = node("y", Variance::Covariant);
+        assert!(cg
+            .reaching_set(y)
+            .unwrap()
+            .contains(&(FieldLabel::Load, x_store)));
+        assert!(cg.reaching_set(x_store).is_none());
+    }
+
+    #[test]
+    fn test_with_reaching_set() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let full = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let prior = full.reaching_sets();
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        cg.import_reaching_set(prior.clone());
+        let stats = cg.resaturate().unwrap();
+        assert_eq!(stats.edges_added, 0);
+        assert_eq!(stats.reaching_elems, 0);
+        assert!(cg.diff(&full).is_empty(), "{}", cg.diff(&full));
+        assert_eq!(cg.reaching_sets(), prior);
+        // the constructor gives the same graph, and so does an empty prior.
+        let seeded = ConstraintGraph::with_reaching_set(
+            constraints.iter().collect(),
+            &HashSet::new(),
+            prior,
+        );
+        assert!(seeded.diff(&full).is_empty());
+        let unseeded = ConstraintGraph::with_reaching_set(
+            constraints.iter().collect(),
+            &HashSet::new(),
+            HashMap::new(),
+        );
+        assert!(unseeded.diff(&full).is_empty());
+    }
+
+    #[test]
+    fn test_to_constraints() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let text: Vec<String> = cg.to_constraints().iter().map(|c| c.to_string()).collect();
+        for expected in [
+            "_A <= _B",
+            "_A <= x.store",
+            "x.store <= y.load",
+            "y.load <= _B",
+            "p <= x",
+            "y <= p",
+            "y <= x",
+        ] {
+            assert!(text.contains(&expected.to_string()), "{:?}", text);
+        }
+        let mut sorted = text.clone();
+        sorted.sort();
+        assert_eq!(text, sorted);
+        // without saturation, _A and _B are not related.
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        assert!(!cg
+            .to_constraints()
+            .iter()
+            .any(|c| c.to_string() == "_A <= _B"));
+    }
+
+    #[test]
+    fn test_same_base() {
+        let constraints =
+            parse_constraint_str(&["x.load <= x.store", "x.load.σ4@0 <= x.load.σ4@4"]);
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        // x, x.load, x.store, x.load.σ4@0 and x.load.σ4@4, in both variances.
+        assert_eq!(cg.graph.node_count(), 10);
+        for variance in [Variance::Covariant, Variance::Contravariant] {
+            let roots = cg
+                .graph
+                .node_weights()
+                .filter(|n| n.base.to_string() == "x" && n.suffix_variance == variance);
+            assert_eq!(roots.count(), 1);
+        }
+        // each fielded node has the edges of its own prefix chains and no more.
+        for ind in cg.graph.node_indices() {
+            let node = &cg.graph[ind];
+            let mut seen = HashSet::new();
+            for edge in cg.graph.edges(ind) {
+                assert!(
+                    seen.insert((edge.target(), edge.weight().clone())),
+                    "duplicate edge from {}",
+                    node
+                );
+            }
+            let forgets = cg
+                .graph
+                .edges(ind)
+                .filter(|e| matches!(e.weight(), EdgeLabel::Forget { .. }));
+            let recalls = cg
+                .graph
+                .edges_directed(ind, petgraph::Direction::Incoming)
+                .filter(|e| matches!(e.weight(), EdgeLabel::Recall { .. }));
+            let to_prefix = forgets.count() + recalls.count();
+            assert!(to_prefix <= 2, "{}", node);
+            assert_eq!(to_prefix == 0, node.base.fields.is_empty(), "{}", node);
+        }
+        cg.saturate().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert!(cg.proves_subtype(&dtv("x.load"), &dtv("x.store")));
+        assert!(cg.proves_subtype(&dtv("x.load.σ4@0"), &dtv("x.load.σ4@4")));
+    }
+
+    #[test]
+    fn test_approximate_saturation() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let exact = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        assert!(!exact.is_incomplete());
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        let budget = cg.graph.edge_count();
+        let mut cg = cg.with_saturation_mode(SaturationMode::Approximate {
+            edge_budget: budget,
+        });
+        let stats = cg.saturate().unwrap();
+        assert!(cg.is_incomplete());
+        assert_eq!(stats.edges_added, 0);
+        assert_eq!(stats.edges_skipped, cg.skipped_edges().len());
+        assert!(
+            cg.graph.edge_count() <= budget + 2,
+            "{} edges",
+            cg.graph.edge_count()
+        );
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert!(exact.proves_subtype(&dtv("x.store"), &dtv("y.load")));
+        assert!(!cg.proves_subtype(&dtv("x.store"), &dtv("y.load")));
+        // a budget that is never reached gives the exact graph.
+        let mut cg = ConstraintGraph::construct()
+            .with_saturation_mode(SaturationMode::Approximate { edge_budget: 1000 });
+        cg.build_and_saturate(constraints.iter().collect(), &HashSet::new(), None)
+            .unwrap();
+        assert!(!cg.is_incomplete());
+        assert!(cg.diff(&exact).is_empty());
+    }
+
+    #[test]
+    fn test_saturate_with() {
+        // the slides example derives nothing.
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        let mut events = Vec::new();
+        cg.saturate_with(&mut |from, to, label| events.push((from, to, label.clone())))
+            .unwrap();
+        assert!(events.is_empty());
+
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let mut cg = ConstraintGraph::construct();
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        let mut events = Vec::new();
+        let stats = cg
+            .saturate_with(&mut |from, to, label| events.push((from, to, label.clone())))
+            .unwrap();
+        assert_eq!(events.len(), stats.edges_added);
+        let derived: Vec<(NodeIndex, NodeIndex)> =
+            events.iter().map(|(from, to, _)| (*from, *to)).collect();
+        assert_eq!(derived, cg.derived_edges());
+        assert!(events.iter().all(|(_, _, label)| label == &EdgeLabel::One));
+        let node = |dtv: &str| {
+            cg.graph_node_map[&Node {
+                base: parse_derived_type_variable(dtv).unwrap().1,
+                suffix_variance: Variance::Covariant,
+                sidemark: SideMark::None,
+            }]
+        };
+        assert!(
+            derived.contains(&(node("x.store"), node("y.load"))),
+            "{:?}",
+            derived
+        );
+    }
+
+    #[test]
+    fn test_add_constraint() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let full = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        // the first two, then the others one at a time.
+        let mut cg = ConstraintGraph::new(constraints[..2].iter().collect(), &HashSet::new());
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert!(!cg.proves_subtype(&dtv("x.store"), &dtv("y.load")));
+        cg.add_constraint(&constraints[2]);
+        cg.resaturate().unwrap();
+        cg.add_constraint(&constraints[3]);
+        cg.resaturate().unwrap();
+        assert!(cg.proves_subtype(&dtv("x.store"), &dtv("y.load")));
+        assert_eq!(cg.to_dot(), full.to_dot());
+    }
+
+    #[test]
+    fn test_derived_edges() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let node = |dtv: &str| {
+            cg.graph_node_map[&Node {
+                base: parse_derived_type_variable(dtv).unwrap().1,
+                suffix_variance: Variance::Covariant,
+                sidemark: SideMark::None,
+            }]
+        };
+        let derived = cg.derived_edges();
+        assert!(
+            derived.contains(&(node("x.store"), node("y.load"))),
+            "{:?}",
+            derived
+        );
+        // the edges of the constraints are not derived.
+        assert!(!derived.contains(&(node("y"), node("p"))));
+        for (source, target) in derived {
+            assert!(cg
+                .graph
+                .edges_connecting(source, target)
+                .any(|e| e.weight() == &EdgeLabel::One));
+        }
+        let slides = parse_constraint_str(&SLIDES_EXAMPLE);
+        assert!(
+            ConstraintGraph::new(slides.iter().collect(), &HashSet::new())
+                .derived_edges()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_pointer_capabilities() {
+        // the saturation example, reading with σ8@0 and writing with σ8@8.
+        let constraints =
+            parse_constraint_str(&["y <= p", "p <= x", "_A <= x.σ8@8", "y.σ8@0 <= _B"]);
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        let label = |s: &str| dtv(&format!("x.{}", s)).fields[0].get().clone();
+        let mut table = VarianceTable::default();
+        table.set(label("σ8@8"), Variance::Contravariant);
+
+        let cg = ConstraintGraph::with_variance_table(
+            constraints.iter().collect(),
+            &HashSet::new(),
+            table.clone(),
+            None,
+        );
+        assert!(!cg.proves_subtype(&dtv("x.σ8@8"), &dtv("y.σ8@0")));
+
+        let mut cg = ConstraintGraph::construct();
+        cg.variance_table = table;
+        cg.pointer_capabilities = PointerCapabilities {
+            load: label("σ8@0"),
+            store: label("σ8@8"),
+        };
+        cg.build_and_saturate(constraints.iter().collect(), &HashSet::new(), None)
+            .unwrap();
+        assert!(cg.proves_subtype(&dtv("x.σ8@8"), &dtv("y.σ8@0")));
+        assert!(cg.proves_subtype(&dtv("_A"), &dtv("_B")));
+    }
+
+    #[test]
+    fn test_inverse_edges_kept() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut full = ConstraintGraph::construct();
+        full.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        // what add_edge would build if it refused a forget edge once the
+        // recall edge the other way is there.
+        let mut deduped = full.clone();
+        let redundant: Vec<_> = deduped
+            .graph
+            .edge_references()
+            .filter(|e| match e.weight() {
+                EdgeLabel::Forget { capability } => deduped
+                    .graph
+                    .edges_connecting(e.target(), e.source())
+                    .any(|back| {
+                        back.weight()
+                            == &EdgeLabel::Recall {
+                                capability: capability.clone(),
+                            }
+                    }),
+                _ => false,
+            })
+            .map(|e| e.id())
+            .collect();
+        assert!(!redundant.is_empty());
+        // from the last, so the other indices stay valid.
+        for id in redundant.into_iter().rev() {
+            deduped.graph.remove_edge(id);
+        }
+        full.saturate().unwrap();
+        deduped.saturate().unwrap();
+
+        let interesting: HashSet<String> = ["F", "close"].iter().map(|s| s.to_string()).collect();
+        let lost = parse_constraint("F.in_stack0.load.σ4@4 <= close.in_stack0")
+            .unwrap()
+            .1;
+        assert!(full.simplify(&interesting).contains(&lost));
+        assert!(!deduped.simplify(&interesting).contains(&lost));
+    }
+
+    #[test]
+    fn test_base_subtype_closure() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let closure = cg.base_subtype_closure();
+        let names =
+            |names: &[&str]| -> HashSet<String> { names.iter().map(|n| n.to_string()).collect() };
+        assert_eq!(closure["p"], names(&["y"]));
+        assert_eq!(closure["x"], names(&["y", "p"]));
+        assert!(closure["y"].is_empty());
+        let mut bases: Vec<&String> = closure.keys().collect();
+        bases.sort();
+        assert_eq!(bases, ["_A", "_B", "p", "x", "y"]);
+    }
+
+    #[test]
+    fn test_missing_inverse_node() {
+        let mut cg = ConstraintGraph::construct();
+        let node = |name: &str, suffix_variance| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance,
+            sidemark: SideMark::None,
+        };
+        // only the contravariant copy of p.σ4@0, which the pointer rule
+        // needs the inverse of.
+        let p_store = cg.add_node(node("p.σ4@0.store", Variance::Contravariant));
+        let p = cg.add_node(node("p.σ4@0", Variance::Contravariant));
+        cg.add_edge(
+            p_store,
+            p,
+            EdgeLabel::Forget {
+                capability: FieldLabel::Store,
+            },
+        );
+        cg.saturate().unwrap();
+
+        let inverse = cg.graph_node_map[&node("p.σ4@0", Variance::Covariant)];
+        assert!(cg
+            .reaching_set(inverse)
+            .unwrap()
+            .contains(&(FieldLabel::Load, p_store)));
+        // with its prefix chain, which takes part in the saturation too.
+        let prefix = cg.graph_node_map[&node("p", Variance::Covariant)];
+        assert!(cg.graph.contains_edge(prefix, inverse) && cg.graph.contains_edge(inverse, prefix));
+        let field = FieldLabel::DerefPattern {
+            size: 4,
+            offset: 0,
+            bound: None,
+        };
+        assert!(cg.reaching_set(prefix).unwrap().contains(&(field, inverse)));
+    }
+
+    #[test]
+    fn test_verify_variance_pairs() {
+        for example in [&SLIDES_EXAMPLE[..], &SATURATION_EXAMPLE[..]] {
+            let constraints = parse_constraint_str(example);
+            let interesting = HashSet::from(["F".to_string(), "x".to_string()]);
+            let mut cg = ConstraintGraph::new(constraints.iter().collect(), &interesting);
+            assert_eq!(cg.verify_variance_pairs(), Ok(()));
+            cg.add_start_end(&HashSet::new());
+            assert_eq!(cg.verify_variance_pairs(), Ok(()));
+        }
+        // a node added on its own gets its inverse, and so do its prefixes.
+        let node = Node {
+            base: parse_derived_type_variable("p.load.σ4@0").unwrap().1,
+            suffix_variance: Variance::Contravariant,
+            sidemark: SideMark::None,
+        };
+        let mut cg = ConstraintGraph::construct();
+        cg.add_node(node.clone());
+        assert_eq!(cg.verify_variance_pairs(), Err(vec![node.clone()]));
+        cg.build_initial_graph(
+            parse_constraint_str(&SATURATION_EXAMPLE).iter().collect(),
+            &HashSet::new(),
+        );
+        assert_eq!(cg.verify_variance_pairs(), Ok(()));
+        assert!(cg.graph_node_map.contains_key(&node.inverse()));
+        let p = Node {
+            base: DerivedTypeVariable::new("p"),
+            suffix_variance: Variance::Covariant,
+            sidemark: SideMark::None,
+        };
+        assert!(cg.graph_node_map.contains_key(&p) && cg.graph_node_map.contains_key(&p.inverse()));
+    }
+
+    #[test]
+    fn test_diff() {
+        let constraints = parse_constraint_str(&SATURATION_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        assert!(cg.diff(&cg.clone()).is_empty());
+
+        let extra = parse_constraint_str(&["y <= x"]);
+        let changed =
+            ConstraintGraph::new(constraints.iter().chain(&extra).collect(), &HashSet::new());
+        let diff = cg.diff(&changed);
+        let node = |name: &str, suffix_variance| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance,
+            sidemark: SideMark::None,
+        };
+        // y <= p <= x already gave every derived edge, so only the edges of
+        // the constraint itself are new.
+        assert_eq!(
+            diff.added_edges,
+            [
+                (
+                    node("x", Variance::Contravariant),
+                    node("y", Variance::Contravariant),
+                    EdgeLabel::One
+                ),
+                (
+                    node("y", Variance::Covariant),
+                    node("x", Variance::Covariant),
+                    EdgeLabel::One
+                ),
+            ]
+        );
+        assert!(
+            diff.added_nodes.is_empty()
+                && diff.removed_nodes.is_empty()
+                && diff.removed_edges.is_empty()
+        );
+        assert_eq!(changed.diff(&cg).removed_edges, diff.added_edges);
+        assert_eq!(diff.to_string(), "+ x.⊖ -> y.⊖ (_1_)\n+ y.⊕ -> x.⊕ (_1_)\n");
+    }
+
+    #[test]
+    fn test_constraint_order() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let reversed = ConstraintGraph::new(constraints.iter().rev().collect(), &HashSet::new());
+        assert_eq!(
+            format!("{:?}", Dot::new(&cg.graph)),
+            format!("{:?}", Dot::new(&reversed.graph))
+        );
+        assert_eq!(cg.graph_node_map, reversed.graph_node_map);
+    }
+
+    #[test]
+    fn test_max_capability_depth() {
+        let deep = format!("x{} <= y", ".load".repeat(20));
+        let constraints = parse_constraint_str(&[&deep, "a.load.σ4@0 <= b"]);
+        let mut cg = ConstraintGraph::construct();
+        cg.max_capability_depth = Some(5);
+        cg.build_initial_graph(constraints.iter().collect(), &HashSet::new());
+        // the deep variable and y in both variances, and the full chain of
+        // the shallow one.
+        assert_eq!(cg.graph.node_count(), 4 + 6 + 2);
+        let truncated: Vec<String> = cg
+            .graph
+            .node_indices()
+            .filter(|ind| cg.is_truncated(*ind))
+            .map(|ind| cg.graph[ind].base.to_string())
+            .collect();
+        assert_eq!(truncated, [&deep[..deep.len() - 5]; 2]);
+
+        let unbounded = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        assert_eq!(unbounded.graph.node_count(), 21 * 2 + 2 + 6 + 2);
+    }
+
+    #[test]
+    fn test_simplify() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let interesting: HashSet<String> = ["F", "close", "_FileDescriptor", "_SuccessZ"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let simplified = cg.simplify(&interesting);
+        assert!(!simplified.is_empty());
+        for c in &simplified {
+            assert!(interesting.contains(&*c.left.name), "{:?}", simplified);
+            assert!(interesting.contains(&*c.right.name), "{:?}", simplified);
+        }
+        let expected = parse_constraint_str(&[
+            "F.in_stack0.load.σ4@4 <= close.in_stack0",
+            "close.in_stack0 <= _FileDescriptor",
+            "_SuccessZ <= close.out_eax",
+            "close.out_eax <= F.out_eax",
+        ]);
+        for c in &expected {
+            assert!(simplified.contains(c), "{:?}", simplified);
+        }
+        // implied by F.in_stack0.load.σ4@4 <= close.in_stack0 <= _FileDescriptor
+        let implied = parse_constraint("F.in_stack0.load.σ4@4 <= _FileDescriptor")
+            .unwrap()
+            .1;
+        assert!(!simplified.contains(&implied), "{:?}", simplified);
+    }
+
+    #[test]
+    fn test_simplify_cycle() {
+        let constraints = parse_constraint_str(&["a <= t", "b <= t", "t <= c", "a <= b", "b <= a"]);
+        let interesting: HashSet<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let simplified = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new())
+            .simplify(&interesting);
+        for c in parse_constraint_str(&["a <= b", "b <= a"]) {
+            assert!(simplified.contains(&c), "{:?}", simplified);
+        }
+        // one of a <= c and b <= c is implied by the other and the cycle.
+        let to_c = simplified.iter().filter(|c| &*c.right.name == "c").count();
+        assert_eq!(to_c, 1, "{:?}", simplified);
+        assert!(entails(
+            &simplified,
+            &parse_constraint_str(&["a <= c", "b <= c"])
+        ));
+    }
+
+    #[test]
+    fn test_simplify_diamonds() {
+        // a chain of diamonds has 2^n paths from one end to the other, but
+        // only a linear number of states.
+        let n = 40;
+        let mut constraints = Vec::new();
+        for i in 0..n {
+            let (a, next) = (format!("a{}", i), format!("a{}", i + 1));
+            for mid in [format!("b{}", i), format!("c{}", i)] {
+                constraints.push(Constraint::subtype(a.as_str(), mid.as_str()));
+                constraints.push(Constraint::subtype(mid.as_str(), next.as_str()));
+            }
+        }
+        let interesting: HashSet<String> = ["a0".to_string(), format!("a{}", n)].into();
+        let simplified = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new())
+            .simplify(&interesting);
+        assert_eq!(
+            simplified,
+            vec![Constraint::subtype("a0", format!("a{}", n).as_str())]
+        );
+
+        // a long chain is searched without recursing once per edge.
+        let n = 20_000;
+        let constraints: Vec<Constraint> = (0..n)
+            .map(|i| {
+                Constraint::subtype(format!("a{}", i).as_str(), format!("a{}", i + 1).as_str())
+            })
+            .collect();
+        let interesting: HashSet<String> = ["a0".to_string(), format!("a{}", n)].into();
+        let simplified = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new())
+            .simplify(&interesting);
+        assert_eq!(simplified.len(), 1);
+    }
+
+    #[test]
+    fn test_entails() {
+        let original = parse_constraint_str(&[
+            "y <= p",
+            "p <= x",
+            "_A <= x.store",
+            "y.load <= _B",
+            "y <= x",
+            "_A <= _B",
+        ]);
+        // the redundant ones follow from the others, with the pointer rule.
+        let scheme = parse_constraint_str(&SATURATION_EXAMPLE);
+        assert!(entails(&scheme, &original));
+        assert!(entails(&original, &scheme));
+        // without p <= x, nothing flows from y to x.
+        let broken = parse_constraint_str(&["y <= p", "_A <= x.store", "y.load <= _B"]);
+        assert!(!entails(&broken, &original));
+        assert!(entails(&original, &broken));
+        // load and store do not swap.
+        let broken = parse_constraint_str(&["y <= p", "p <= x", "_A <= x.load", "y.store <= _B"]);
+        assert!(!entails(&broken, &original));
+        assert!(entails(&broken, &[]));
+
+        // the simplified slides example is entailed by the original one.
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let interesting: HashSet<String> = ["F", "close"].iter().map(|s| s.to_string()).collect();
+        let simplified = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new())
+            .simplify(&interesting);
+        assert!(entails(&constraints, &simplified), "{:?}", simplified);
+        let reversed = parse_constraint_str(&["close.in_stack0 <= F.in_stack0.load.σ4@4"]);
+        assert!(!entails(&constraints, &reversed));
+    }
+
+    #[test]
+    fn test_prune() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let interesting: HashSet<String> = ["F", "close"].iter().map(|s| s.to_string()).collect();
+        let full = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let mut cg = full.clone();
+        let removed = cg.prune(&interesting);
+        assert_eq!(cg.graph.node_count(), full.graph.node_count() - removed);
+        assert_eq!(cg.graph_node_map.len(), cg.graph.node_count());
+        for (node, ind) in &cg.graph_node_map {
+            assert_eq!(&cg.graph[*ind], node);
+        }
+        // the type constants only hang off close.
+        let names: HashSet<&str> = cg.graph.node_weights().map(|n| &*n.base.name).collect();
+        assert!(
+            !names.contains("_FileDescriptor") && !names.contains("_SuccessZ"),
+            "{:?}",
+            names
+        );
+        assert!(names.contains("𝜑"), "{:?}", names);
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert!(cg.proves_subtype(&dtv("close.out_eax"), &dtv("F.out_eax")));
+        let mut simplified: Vec<String> = cg
+            .simplify(&interesting)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let mut expected: Vec<String> = full
+            .simplify(&interesting)
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        simplified.sort();
+        expected.sort();
+        assert_eq!(simplified, expected);
+        assert!(
+            simplified.contains(&"F.in_stack0.load.σ4@4 <= close.in_stack0".to_string()),
+            "{:?}",
+            simplified
+        );
+        // nothing more to remove.
+        assert_eq!(cg.prune(&interesting), 0);
+    }
+
+    #[test]
+    fn test_start_end() {
+        let constraints = parse_constraint_str(&SLIDES_EXAMPLE);
+        let mut cg = ConstraintGraph::new(constraints.iter().collect(), &HashSet::new());
+        let node = |name: &str| Node {
+            base: parse_derived_type_variable(name).unwrap().1,
+            suffix_variance: Variance::Covariant,
+            sidemark: SideMark::None,
+        };
+        cg.add_start_end(&HashSet::from([node("F"), node("close")]));
+        let start = cg.start.unwrap();
+        let end = cg.end.unwrap();
+        assert_eq!(cg.graph_node_map[&Node::start()], start);
+        assert_eq!(cg.graph_node_map[&Node::end()], end);
+        for name in ["F", "close"] {
+            let ind = cg.graph_node_map[&node(name)];
+            assert!(cg.graph.contains_edge(start, ind));
+            assert!(cg.graph.contains_edge(ind, end));
+        }
+        assert_eq!(cg.graph.neighbors(start).count(), 2);
+        assert!(Node::start()
+            .forget_once(&VarianceTable::default())
+            .is_none());
+    }
+
+    #[test]
+    fn test_callee_scheme_flows_into_caller() {
+        init();
+        let mut call_graph = DiGraph::new();
+        let f = call_graph.add_node("F".to_string());
+        let close = call_graph.add_node("close".to_string());
+        call_graph.add_edge(f, close, 1);
+        let program = Program::new(
+            "x86",
+            HashMap::from([
+                (
+                    "close".to_string(),
+                    parse_constraint_str(&["close.in_0 <= t", "t <= _FileDescriptor"]),
+                ),
+                (
+                    "F".to_string(),
+                    parse_constraint_str(&["F.in_0 <= t", "t <= close.in_0"]),
+                ),
+            ]),
+            call_graph,
+        );
+        let schemes = infer_proc_types(&program, &PointerCapabilities::default(), None).unwrap();
+        assert_eq!(
+            schemes["close"],
+            parse_constraint_str(&["close.in_0 <= _FileDescriptor"])
+        );
+        assert_eq!(
+            schemes["F"],
+            parse_constraint_str(&["F.in_0 <= _FileDescriptor"])
+        );
+    }
+
+    #[test]
+    fn test_call_sites_do_not_alias() {
+        init();
+        let mut call_graph = DiGraph::new();
+        let f = call_graph.add_node("F".to_string());
+        let id = call_graph.add_node("id".to_string());
+        call_graph.add_edge(f, id, 2);
+        let program = Program::new(
+            "x86",
+            HashMap::from([
+                (
+                    "id".to_string(),
+                    parse_constraint_str(&["id.in_0 <= t", "t <= id.out_eax"]),
+                ),
+                (
+                    "F".to_string(),
+                    parse_constraint_str(&[
+                        "_int <= id@0.in_0",
+                        "id@0.out_eax <= F.out_0",
+                        "_float <= id@1.in_0",
+                        "id@1.out_eax <= F.out_1",
+                    ]),
+                ),
+            ]),
+            call_graph,
+        );
+        let schemes = infer_proc_types(&program, &PointerCapabilities::default(), None).unwrap();
+        assert_eq!(
+            schemes["id"],
+            parse_constraint_str(&["id.in_0 <= id.out_eax"])
+        );
+        for c in parse_constraint_str(&["_int <= F.out_0", "_float <= F.out_1"]) {
+            assert!(schemes["F"].contains(&c), "{:?}", schemes["F"]);
+        }
+        for c in parse_constraint_str(&["_float <= F.out_0", "_int <= F.out_1"]) {
+            assert!(!schemes["F"].contains(&c), "{:?}", schemes["F"]);
+        }
+    }
+
+    #[test]
+    fn test_parallel_sccs() {
+        // top calls left and right, which both call bottom.
+        let mut call_graph = DiGraph::new();
+        let procs = ["top", "left", "right", "bottom"].map(|p| call_graph.add_node(p.to_string()));
+        for (caller, callee) in [(0, 1), (0, 2), (1, 3), (2, 3)] {
+            call_graph.add_edge(procs[caller], procs[callee], 1);
+        }
+        let proc_constraints = HashMap::from([
+            (
+                "bottom".to_string(),
+                parse_constraint_str(&["bottom.in_0 <= t", "t <= bottom.out_0"]),
+            ),
+            (
+                "left".to_string(),
+                parse_constraint_str(&["left.in_0 <= bottom.in_0", "bottom.out_0 <= left.out_0"]),
+            ),
+            (
+                "right".to_string(),
+                parse_constraint_str(&["_A <= bottom.in_0", "bottom.out_0 <= right.out_0"]),
+            ),
+            (
+                "top".to_string(),
+                parse_constraint_str(&["left.out_0 <= right.in_0", "right.out_0 <= top.out_0"]),
+            ),
+        ]);
+        let program = Program::new("x86", proc_constraints, call_graph);
+        let sorted = |schemes: HashMap<String, Vec<Constraint>>| {
+            let mut schemes: Vec<(String, Vec<String>)> = schemes
+                .into_iter()
+                .map(|(proc, scheme)| {
+                    let mut scheme: Vec<String> = scheme.iter().map(|c| c.to_string()).collect();
+                    scheme.sort();
+                    (proc, scheme)
+                })
+                .collect();
+            schemes.sort();
+            schemes
+        };
+        let pointer = PointerCapabilities::default();
+        let sequential = sorted(
+            super::infer_proc_types_ranked(&program, &pointer, None, DEFAULT_MAX_ITERATIONS, false)
+                .unwrap(),
+        );
+        assert_eq!(
+            sequential[1],
+            (
+                "left".to_string(),
+                vec!["left.in_0 <= left.out_0".to_string()]
+            )
+        );
+        assert_eq!(
+            sequential[2],
+            ("right".to_string(), vec!["_A <= right.out_0".to_string()])
+        );
+        assert_eq!(
+            sequential[3],
+            ("top".to_string(), vec!["_A <= top.out_0".to_string()])
+        );
+        for _ in 0..5 {
+            assert_eq!(
+                sorted(infer_proc_types(&program, &pointer, None).unwrap()),
+                sequential
+            );
+        }
+        // a group whose saturation does not terminate fails the whole run.
+        for parallel in [false, true] {
+            assert_eq!(
+                super::infer_proc_types_ranked(&program, &pointer, None, 0, parallel).err(),
+                Some(SaturationError::IterationLimit { iterations: 0 })
+            );
+        }
+    }
 }
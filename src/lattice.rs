@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+
+/// A bounded lattice over the atomic types.
+pub trait Lattice {
+    type Element;
+    fn top(&self) -> Self::Element;
+    fn bottom(&self) -> Self::Element;
+    /// least upper bound
+    fn join(&self, a: &Self::Element, b: &Self::Element) -> Self::Element;
+    /// greatest lower bound
+    fn meet(&self, a: &Self::Element, b: &Self::Element) -> Self::Element;
+    fn leq(&self, a: &Self::Element, b: &Self::Element) -> bool;
+}
+
+pub const TOP: &str = "⊤";
+pub const BOTTOM: &str = "⊥";
+
+/// The lattice of C primitive types, elements are the atom names.
+///
+/// The default one is the usual retypd lattice:
+/// ```text
+///            ⊤
+///      /     |     \
+///    num    code   void*
+///   / | \
+/// int uint float
+///  |
+/// char
+///   (all above ⊥)
+/// ```
+/// Names that are not atoms of the lattice are only comparable to
+/// themselves, ⊤ and ⊥.
+#[derive(Clone)]
+pub struct CTypeLattice {
+    /// atoms in declaration order, including ⊤ and ⊥.
+    pub atoms: Vec<String>,
+    /// for each atom, all atoms above it (reflexive, transitive).
+    above: HashMap<String, HashSet<String>>,
+}
+
+impl CTypeLattice {
+    /// Build a lattice from the atoms and `(lower, upper)` pairs. ⊤ and ⊥ are
+    /// always added, and the order is closed under transitivity.
+    pub fn new(atoms: &[&str], order: &[(&str, &str)]) -> Self {
+        let mut all: Vec<String> = vec![TOP.to_string(), BOTTOM.to_string()];
+        for atom in atoms.iter().chain(order.iter().flat_map(|(a, b)| [a, b])) {
+            if !all.iter().any(|a| a == atom) {
+                all.push(atom.to_string());
+            }
+        }
+        let mut above: HashMap<String, HashSet<String>> = all
+            .iter()
+            .map(|a| (a.clone(), HashSet::from([a.clone(), TOP.to_string()])))
+            .collect();
+        above.get_mut(BOTTOM).unwrap().extend(all.iter().cloned());
+        for (lower, upper) in order {
+            above.get_mut(*lower).unwrap().insert(upper.to_string());
+        }
+        // transitive closure
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for atom in &all {
+                let reach: HashSet<String> = above[atom]
+                    .iter()
+                    .flat_map(|b| above[b].iter().cloned())
+                    .collect();
+                if reach.len() > above[atom].len() {
+                    above.insert(atom.clone(), reach);
+                    changed = true;
+                }
+            }
+        }
+        CTypeLattice { atoms: all, above }
+    }
+
+    /// The `(lower, upper)` pairs of distinct atoms, closed under
+    /// transitivity and without the ones through ⊤ and ⊥, in the order of
+    /// the atoms. `new` builds the same lattice back from them.
+    pub fn order(&self) -> Vec<(String, String)> {
+        let inner = |a: &&String| a.as_str() != TOP && a.as_str() != BOTTOM;
+        let mut order = Vec::new();
+        for lower in self.atoms.iter().filter(inner) {
+            for upper in self.atoms.iter().filter(inner) {
+                if lower != upper && self.above[lower].contains(upper) {
+                    order.push((lower.clone(), upper.clone()));
+                }
+            }
+        }
+        order
+    }
+
+    pub fn contains(&self, atom: &str) -> bool {
+        self.above.contains_key(atom)
+    }
+
+    fn upper_bounds(&self, a: &str) -> HashSet<String> {
+        match self.above.get(a) {
+            Some(above) => above.clone(),
+            None => HashSet::from([a.to_string(), TOP.to_string()]),
+        }
+    }
+
+    fn lower_bounds(&self, a: &str) -> HashSet<String> {
+        let mut below: HashSet<String> = self
+            .atoms
+            .iter()
+            .filter(|b| self.leq_str(b, a))
+            .cloned()
+            .collect();
+        below.insert(a.to_string());
+        below
+    }
+
+    fn leq_str(&self, a: &str, b: &str) -> bool {
+        a == b || a == BOTTOM || b == TOP || self.above.get(a).is_some_and(|s| s.contains(b))
+    }
+}
+
+impl Default for CTypeLattice {
+    fn default() -> Self {
+        CTypeLattice::new(
+            &["num", "code", "void*", "int", "uint", "float", "char"],
+            &[
+                ("int", "num"),
+                ("uint", "num"),
+                ("float", "num"),
+                ("char", "int"),
+            ],
+        )
+    }
+}
+
+impl Lattice for CTypeLattice {
+    type Element = String;
+
+    fn top(&self) -> String {
+        TOP.to_string()
+    }
+
+    fn bottom(&self) -> String {
+        BOTTOM.to_string()
+    }
+
+    fn join(&self, a: &String, b: &String) -> String {
+        if self.leq_str(a, b) {
+            return b.clone();
+        } else if self.leq_str(b, a) {
+            return a.clone();
+        }
+        let common: HashSet<String> = self
+            .upper_bounds(a)
+            .intersection(&self.upper_bounds(b))
+            .cloned()
+            .collect();
+        common
+            .iter()
+            .find(|c| common.iter().all(|d| self.leq_str(c, d)))
+            .cloned()
+            .unwrap_or_else(|| self.top())
+    }
+
+    fn meet(&self, a: &String, b: &String) -> String {
+        if self.leq_str(a, b) {
+            return a.clone();
+        } else if self.leq_str(b, a) {
+            return b.clone();
+        }
+        let common: HashSet<String> = self
+            .lower_bounds(a)
+            .intersection(&self.lower_bounds(b))
+            .cloned()
+            .collect();
+        common
+            .iter()
+            .find(|c| common.iter().all(|d| self.leq_str(d, c)))
+            .cloned()
+            .unwrap_or_else(|| self.bottom())
+    }
+
+    fn leq(&self, a: &String, b: &String) -> bool {
+        self.leq_str(a, b)
+    }
+}
+
+/// Two lattices are equal when they order the same atoms the same way,
+/// whatever the order the atoms were declared in.
+impl PartialEq for CTypeLattice {
+    fn eq(&self, other: &Self) -> bool {
+        self.above == other.above
+    }
+}
+
+impl std::fmt::Debug for CTypeLattice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CTypeLattice{:?}", self.atoms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CTypeLattice, Lattice, BOTTOM, TOP};
+
+    fn s(a: &str) -> String {
+        a.to_string()
+    }
+
+    #[test]
+    fn test_c_type_lattice() {
+        let l = CTypeLattice::default();
+        assert_eq!(l.join(&s("int"), &s("uint")), "num");
+        assert_eq!(l.join(&s("int"), &s("code")), TOP);
+        assert_eq!(l.join(&s("char"), &s("int")), "int");
+        assert_eq!(l.meet(&s("int"), &s("uint")), BOTTOM);
+        assert_eq!(l.meet(&s("char"), &s("num")), "char");
+        assert_eq!(l.meet(&s("num"), &s(TOP)), "num");
+        assert!(l.leq(&s("char"), &s("num")));
+        assert!(l.leq(&s(BOTTOM), &s("char")));
+        assert!(!l.leq(&s("num"), &s("int")));
+        // names outside the lattice are incomparable atoms.
+        assert_eq!(l.join(&s("int"), &s("_FileDescriptor")), TOP);
+        assert_eq!(l.meet(&s("_FileDescriptor"), &s(TOP)), "_FileDescriptor");
+
+        let all: Vec<String> = l.atoms.clone();
+        for a in &all {
+            assert_eq!(&l.join(a, a), a);
+            assert_eq!(&l.meet(a, a), a);
+            for b in &all {
+                assert_eq!(l.join(a, b), l.join(b, a));
+                assert_eq!(l.meet(a, b), l.meet(b, a));
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod c_types;
+pub mod graph;
+pub mod lattice;
+pub mod parser;
+pub mod schema;
+pub mod sketches;
+pub mod solver;
@@ -1,20 +1,182 @@
-use clap::{arg, command};
-use parser::constraints_from_json;
+use std::{collections::HashSet, fs::File, io::BufReader};
 
-pub mod parser;
-pub mod schema;
-pub mod solver;
-pub mod sketches;
-pub mod graph;
-
-use solver::Solver;
+use clap::{arg, command, ArgAction, ArgMatches, Command};
+use retypd_rust::parser::{
+    constraints_from_json, constraints_from_json_lenient, dangling_callees,
+    program_from_json_lenient,
+};
+use retypd_rust::schema::PointerCapabilities;
+use retypd_rust::solver::Solver;
+use serde_json::Value;
 
 fn main() {
     env_logger::init();
     let matches = command!()
-        .arg(arg!([json_in] "Path to the constraints json file").default_value("tests/retypd-constrains-simple.json"))
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("solve")
+                .about("Infer the types of the procedures")
+                .arg(arg!([json_in] "Path to the constraints json file").default_value("tests/retypd-constrains-simple.json"))
+                .arg(arg!(--proc <name> "Only solve the constraints of this procedure").action(ArgAction::Append))
+                .arg(arg!(--"list-procs" "Print the procedures in the constraints file and exit"))
+                .arg(arg!(--lenient "Skip the procedures whose constraints cannot be parsed"))
+                .arg(arg!(--strict "Reject unknown languages and derived type variables with ill-formed field sequences"))
+                .arg(arg!(--normalize "Cancel each store right before a load, and the other way around"))
+                .arg(arg!(--report "Print the size of each procedure's constraint graph and exit"))
+                .arg(arg!(--"emit-schemes" <path> "Write the type schemes as a constraints json file"))
+                .arg(arg!(--"dot-dir" <dir> "Write the intermediate graphs into this directory")),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check that a constraints json file is well-formed, without solving it")
+                .arg(arg!(<json_in> "Path to the constraints json file")),
+        )
         .get_matches();
-    let program = constraints_from_json(matches.get_one::<String>("json_in").unwrap()).unwrap();
-    let solver = Solver::new(&program);
-    solver.solve();
+    match matches.subcommand() {
+        Some(("solve", matches)) => solve(matches),
+        Some(("check", matches)) => check(matches),
+        _ => unreachable!(),
+    }
+}
+
+/// Report every constraint that does not parse, call to an unknown function
+/// and ill-formed derived type variable, and exit with 1 if there is any.
+fn check(matches: &ArgMatches) {
+    let json_in = matches.get_one::<String>("json_in").unwrap();
+    let val: Value = File::open(json_in)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("cannot read {}: {}", json_in, e);
+            std::process::exit(1);
+        });
+    let (dangling, (program, errors)) = dangling_callees(&val)
+        .and_then(|dangling| Ok((dangling, program_from_json_lenient(&val)?)))
+        .unwrap_or_else(|e| {
+            eprintln!("cannot load {}: {}", json_in, e);
+            std::process::exit(1);
+        });
+    for e in &dangling {
+        eprintln!("{}", e);
+    }
+    for e in &errors {
+        eprintln!("{}", e);
+    }
+    let ill_formed = program.validate();
+    for (proc, e) in &ill_formed {
+        eprintln!("in {}: {}", proc, e);
+    }
+    // a missing call edge leaves a scheme out, but the program still solves.
+    for w in program.validate_references() {
+        eprintln!("warning: {}", w);
+    }
+    let problems = dangling.len() + errors.len() + ill_formed.len();
+    if problems > 0 {
+        eprintln!(
+            "{}: failed with {} unknown callees, {} unparsable constraints and {} ill-formed variables",
+            json_in,
+            dangling.len(),
+            errors.len(),
+            ill_formed.len()
+        );
+        std::process::exit(1);
+    }
+    let constraints: usize = program.proc_constraints.values().map(Vec::len).sum();
+    println!(
+        "{}: {} procedures, {} constraints, no problems",
+        json_in,
+        program.proc_constraints.len(),
+        constraints
+    );
+}
+
+fn solve(matches: &ArgMatches) {
+    let json_in = matches.get_one::<String>("json_in").unwrap();
+    let loaded = if matches.get_flag("lenient") {
+        constraints_from_json_lenient(json_in).map(|(program, errors)| {
+            for e in errors {
+                eprintln!("skipping {}: {}", e.function, e);
+            }
+            program
+        })
+    } else {
+        constraints_from_json(json_in)
+    };
+    let mut program = loaded.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if matches.get_flag("normalize") {
+        let changed = program.normalize(&PointerCapabilities::default());
+        log::info!("Normalized {} derived type variables", changed);
+    }
+    if matches.get_flag("strict") {
+        if let Err(e) = program.language_enum() {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        let errors = program.validate();
+        for (proc, e) in &errors {
+            eprintln!("in {}: {}", proc, e);
+        }
+        if !errors.is_empty() {
+            std::process::exit(1);
+        }
+    }
+    let dropped = program.dedup_constraints();
+    log::info!("Removed {} duplicate constraints", dropped);
+    if matches.get_flag("list-procs") {
+        let mut procs: Vec<&String> = program.proc_constraints.keys().collect();
+        procs.sort();
+        for proc in procs {
+            println!("{}", proc);
+        }
+        return;
+    }
+    let mut solver = Solver::new(&program);
+    if let Some(dir) = matches.get_one::<String>("dot-dir") {
+        solver = solver.with_dot_dir(dir.into());
+    }
+    if let Some(procs) = matches.get_many::<String>("proc") {
+        let procs: HashSet<String> = procs.cloned().collect();
+        for proc in &procs {
+            if !program.proc_constraints.contains_key(proc) {
+                eprintln!("unknown procedure: {}", proc);
+                std::process::exit(1);
+            }
+        }
+        solver = solver.with_procs(procs);
+    }
+    if matches.get_flag("report") {
+        for r in solver.report() {
+            println!(
+                "{}: {} constraints, {} nodes, {} edges, {} saturation iterations",
+                r.name, r.constraint_count, r.node_count, r.edge_count, r.saturation_iterations
+            );
+        }
+        return;
+    }
+    match solver.infer_shapes() {
+        Ok(shapes) => println!("{} equivalence classes", shapes.class_count()),
+        Err(conflicts) => {
+            for conflict in conflicts {
+                eprintln!("{}", conflict);
+            }
+            std::process::exit(1);
+        }
+    }
+    let solved = match matches.get_one::<String>("emit-schemes") {
+        Some(path) => solver.schemes_to_json().map(|schemes| {
+            let json = serde_json::to_string_pretty(&schemes).unwrap();
+            std::fs::write(path, json).unwrap_or_else(|e| {
+                eprintln!("cannot write {}: {}", path, e);
+                std::process::exit(1);
+            });
+        }),
+        None => solver.solve().map(|_| ()),
+    };
+    if let Err(e) = solved {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
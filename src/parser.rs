@@ -1,67 +1,687 @@
-use crate::schema::{Bound, Constraint, DerivedTypeVariable, FieldLabel, Program};
+use crate::{
+    lattice::{CTypeLattice, BOTTOM, TOP},
+    schema::{
+        Bound, Constraint, DerivedTypeVariable, FieldLabel, FieldLabelId, MergeError, Program,
+        Variance,
+    },
+};
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while1},
+    bytes::complete::tag,
     character::complete::{char, digit1, multispace0},
     combinator::{map, map_res, opt, recognize},
     multi::many0,
     sequence::{delimited, pair, preceded, tuple},
     IResult,
 };
-use petgraph::graph::DiGraph;
-use std::{collections::HashMap, error::Error, fs::File, io::BufReader, str::FromStr};
+use petgraph::{graph::DiGraph, visit::EdgeRef};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    ops::Range,
+    str::FromStr,
+    sync::Arc,
+};
+
+use serde_json::{json, Map, Value};
+use unicode_normalization::UnicodeNormalization;
+
+/// A constraint string in the input that could not be parsed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    /// the function whose constraints contain the string, empty if the
+    /// constraint is not read as part of a function.
+    pub function: String,
+    pub constraint: String,
+    /// byte offset in `constraint` where parsing stopped.
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // point at the failing character below the constraint.
+        let column = self.constraint[..self.offset].chars().count();
+        write!(f, "cannot parse constraint")?;
+        if !self.function.is_empty() {
+            write!(f, " of function {}", self.function)?;
+        }
+        write!(
+            f,
+            " at byte {}:\n    {}\n    {}^",
+            self.offset,
+            self.constraint,
+            " ".repeat(column)
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// A problem found by `parse_constraint_recovering`, covering the bytes
+/// `range` of the input.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+/// What `parse_constraint_recovering` could read of a constraint with
+/// errors: each side, with the fields up to the first one it could not
+/// parse.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PartialConstraint {
+    pub left: Option<DerivedTypeVariable>,
+    pub right: Option<DerivedTypeVariable>,
+}
+
+/// All the constraint strings of the input that could not be parsed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for e in &self.0 {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseErrors {}
+
+/// Why a constraints JSON document could not be loaded.
+pub enum LoadError {
+    Io(std::io::Error),
+    /// the file is not valid JSON.
+    Json(serde_json::Error),
+    /// a required field is absent, e.g. `callgraph`.
+    MissingField {
+        field: String,
+    },
+    /// a field holds the wrong kind of value, e.g. `constraints` is an
+    /// array rather than an object.
+    WrongType {
+        field: String,
+        expected: &'static str,
+    },
+    /// some constraint strings could not be parsed.
+    Parse(ParseErrors),
+    /// no file was given to load.
+    NoInput,
+    /// the files cannot be merged into one program.
+    Merge(MergeError),
+    /// a line of a line-delimited file, counted from 1, could not be
+    /// loaded.
+    Line {
+        line: usize,
+        error: Box<LoadError>,
+    },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Json(e) => write!(f, "invalid JSON: {}", e),
+            LoadError::MissingField { field } => write!(f, "missing field `{}`", field),
+            LoadError::WrongType { field, expected } => {
+                write!(f, "field `{}` is not {}", field, expected)
+            }
+            LoadError::Parse(e) => write!(f, "{}", e),
+            LoadError::NoInput => write!(f, "no constraint files given"),
+            LoadError::Merge(e) => write!(f, "{}", e),
+            LoadError::Line { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl fmt::Debug for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Error for LoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Json(e) => Some(e),
+            LoadError::Parse(e) => Some(e),
+            LoadError::Merge(e) => Some(e),
+            LoadError::Line { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> Self {
+        LoadError::Json(e)
+    }
+}
+
+impl From<MergeError> for LoadError {
+    fn from(e: MergeError) -> Self {
+        LoadError::Merge(e)
+    }
+}
+
+/// `val[field]`, which must be present.
+fn field<'a>(val: &'a Value, field: &str) -> Result<&'a Value, LoadError> {
+    val.get(field).ok_or_else(|| LoadError::MissingField {
+        field: field.to_string(),
+    })
+}
+
+/// `val` as an object, `path` naming it in the error.
+fn as_object<'a>(val: &'a Value, path: &str) -> Result<&'a Map<String, Value>, LoadError> {
+    val.as_object().ok_or_else(|| LoadError::WrongType {
+        field: path.to_string(),
+        expected: "an object",
+    })
+}
+
+/// `val` as an array, `path` naming it in the error.
+fn as_array<'a>(val: &'a Value, path: &str) -> Result<&'a Vec<Value>, LoadError> {
+    val.as_array().ok_or_else(|| LoadError::WrongType {
+        field: path.to_string(),
+        expected: "an array",
+    })
+}
 
-use serde_json::Value;
+pub fn constraints_from_json(json_path: &str) -> Result<Program, LoadError> {
+    let (program, errors) = constraints_from_json_lenient(json_path)?;
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(LoadError::Parse(ParseErrors(errors)))
+    }
+}
+
+/// Load several JSON files and merge them into one program, see
+/// `Program::merge`. All the files must be for the same language.
+pub fn constraints_from_jsons(json_paths: &[&str]) -> Result<Program, LoadError> {
+    let (first, rest) = json_paths.split_first().ok_or(LoadError::NoInput)?;
+    let mut program = constraints_from_json(first)?;
+    for path in rest {
+        program.merge(constraints_from_json(path)?)?;
+    }
+    Ok(program)
+}
 
-pub fn constraints_from_json(json_path: &str) -> Result<Program, Box<dyn Error>> {
+/// Like `constraints_from_json`, but procedures with constraints that cannot
+/// be parsed are left out of the program instead of failing the load. The
+/// errors are returned along with the program.
+pub fn constraints_from_json_lenient(
+    json_path: &str,
+) -> Result<(Program, Vec<ParseError>), LoadError> {
     let file = File::open(json_path)?;
     let reader = BufReader::new(file);
     let val: Value = serde_json::from_reader(reader)?;
+    program_from_json_lenient(&val)
+}
+
+/// Build the program from the raw bytes of a JSON document. This never
+/// panics, whatever the bytes, so it is the entry point for fuzzing: bad
+/// UTF-8 or JSON, missing fields and constraints that do not parse are all
+/// errors.
+pub fn try_parse_program(bytes: &[u8]) -> Result<Program, LoadError> {
+    let val: Value = serde_json::from_slice(bytes)?;
+    program_from_json(&val)
+}
+
+/// Build the program from an already parsed JSON document.
+pub fn program_from_json(val: &Value) -> Result<Program, LoadError> {
+    let (program, errors) = program_from_json_lenient(val)?;
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(LoadError::Parse(ParseErrors(errors)))
+    }
+}
+
+/// `program_from_json`, leaving out the procedures that fail to parse. The
+/// optional `hints` object maps variables to lattice atoms, see
+/// `Program::type_hints`; a hint whose variable does not parse is left out
+/// too. The optional `opaque` array lists the variables of
+/// `Program::opaque`, and the optional `lattice` object the lattice of
+/// `Program::types`, see `lattice_from_json`. A
+/// document without the `language`, `callgraph` or `constraints` fields,
+/// or with values of the wrong kind in them, is still an error.
+pub fn program_from_json_lenient(val: &Value) -> Result<(Program, Vec<ParseError>), LoadError> {
+    let language = field(val, "language")?
+        .as_str()
+        .ok_or_else(|| LoadError::WrongType {
+            field: "language".to_string(),
+            expected: "a string",
+        })?;
+    let graph = call_graph_from_json(field(val, "callgraph")?)?;
+
+    // parse constrains
+    let mut proc_constraints: HashMap<String, Vec<Constraint>> = HashMap::new();
+    let mut errors = Vec::new();
+    let constraints = as_object(field(val, "constraints")?, "constraints")?;
+    for (func_name, constraints) in constraints {
+        let constraints_str = as_array(constraints, &format!("constraints.{}", func_name))?;
+        let mut cs: Vec<Constraint> = Vec::new();
+        let mut failed = false;
+        for constraint in constraints_str {
+            // either the constraint string, or an object with the string
+            // and its source location.
+            let (text, loc) = match constraint.as_str() {
+                Some(text) => (text, None),
+                None => match constraint["constraint"].as_str() {
+                    Some(text) => (text, constraint["loc"].as_str().map(|loc| loc.to_string())),
+                    None => {
+                        errors.push(ParseError {
+                            function: func_name.to_string(),
+                            constraint: constraint.to_string(),
+                            offset: 0,
+                        });
+                        failed = true;
+                        continue;
+                    }
+                },
+            };
+            match parse_function_constraint(func_name, text) {
+                Ok(parsed) => {
+                    for mut c in parsed {
+                        c.provenance = loc.clone();
+                        cs.push(c);
+                    }
+                }
+                Err(e) => {
+                    errors.push(e);
+                    failed = true;
+                }
+            }
+        }
+        // insert to proc constrains
+        if !failed {
+            proc_constraints.insert(normalize_name(func_name), cs);
+        }
+    }
+    let mut program = Program::new(language, proc_constraints, graph);
+    if let Some(hints) = val.get("hints") {
+        for (var, atom) in as_object(hints, "hints")? {
+            let atom = atom.as_str().ok_or_else(|| LoadError::WrongType {
+                field: format!("hints.{}", var),
+                expected: "a string",
+            })?;
+            match parse_derived_type_variable(var) {
+                Ok((rest, dtv)) if rest.trim().is_empty() => {
+                    program.type_hints.insert(dtv, atom.to_string());
+                }
+                Ok((rest, _)) => errors.push(ParseError {
+                    function: String::new(),
+                    constraint: var.clone(),
+                    offset: var.len() - rest.len(),
+                }),
+                Err(_) => errors.push(ParseError {
+                    function: String::new(),
+                    constraint: var.clone(),
+                    offset: 0,
+                }),
+            }
+        }
+    }
+    if let Some(opaque) = val.get("opaque") {
+        for (i, name) in as_array(opaque, "opaque")?.iter().enumerate() {
+            let name = name.as_str().ok_or_else(|| LoadError::WrongType {
+                field: format!("opaque[{}]", i),
+                expected: "a string",
+            })?;
+            program.opaque.insert(name.to_string());
+        }
+    }
+    if let Some(lattice) = val.get("lattice") {
+        program.types = Some(lattice_from_json(lattice)?);
+    }
+    Ok((program, errors))
+}
+
+/// Parse the `lattice` object, `{"atoms": [...], "order": [[lower, upper], ...]}`,
+/// into the lattice `CTypeLattice::new` builds from them. Both fields are
+/// optional, and `top` and `bottom`, in any case, are `⊤` and `⊥`.
+fn lattice_from_json(val: &Value) -> Result<CTypeLattice, LoadError> {
+    let atom = |val: &Value, path: String| {
+        val.as_str().map(lattice_atom).ok_or(LoadError::WrongType {
+            field: path,
+            expected: "a string",
+        })
+    };
+    let empty = Vec::new();
+    let lattice = as_object(val, "lattice")?;
+    let atoms = match lattice.get("atoms") {
+        Some(atoms) => as_array(atoms, "lattice.atoms")?,
+        None => &empty,
+    };
+    let atoms = atoms
+        .iter()
+        .enumerate()
+        .map(|(i, a)| atom(a, format!("lattice.atoms[{}]", i)))
+        .collect::<Result<Vec<String>, LoadError>>()?;
+    let order = match lattice.get("order") {
+        Some(order) => as_array(order, "lattice.order")?,
+        None => &empty,
+    };
+    let order = order
+        .iter()
+        .enumerate()
+        .map(|(i, pair)| {
+            let path = format!("lattice.order[{}]", i);
+            match pair.as_array().map(Vec::as_slice) {
+                Some([lower, upper]) => Ok((atom(lower, path.clone())?, atom(upper, path)?)),
+                _ => Err(LoadError::WrongType {
+                    field: path,
+                    expected: "a `[lower, upper]` pair",
+                }),
+            }
+        })
+        .collect::<Result<Vec<(String, String)>, LoadError>>()?;
+    let atoms: Vec<&str> = atoms.iter().map(String::as_str).collect();
+    let order: Vec<(&str, &str)> = order
+        .iter()
+        .map(|(a, b)| (a.as_str(), b.as_str()))
+        .collect();
+    Ok(CTypeLattice::new(&atoms, &order))
+}
 
-    // parse callgraphs
-    let mut graph = DiGraph::<String, ()>::new();
+/// A lattice atom of the `lattice` object, with `top` and `bottom` spelled
+/// `⊤` and `⊥`.
+fn lattice_atom(name: &str) -> String {
+    if name.eq_ignore_ascii_case("top") {
+        TOP.to_string()
+    } else if name.eq_ignore_ascii_case("bottom") {
+        BOTTOM.to_string()
+    } else {
+        normalize_name(name)
+    }
+}
+
+/// Parse the `callgraph` object, mapping each function to its callees. A
+/// callee is either its name, called once, or `{"callee": name, "sites": n}`
+/// for a function called from `n` call sites. The edges are weighted by the
+/// number of sites. Calls to functions that are not keys of the object are
+/// left out, see `dangling_callees`.
+fn call_graph_from_json(val: &Value) -> Result<DiGraph<String, u32>, LoadError> {
+    let mut graph = DiGraph::<String, u32>::new();
     let mut nodes = HashMap::new();
-    let call_graph = val["callgraph"].as_object().unwrap();
+    let call_graph = as_object(val, "callgraph")?;
 
     // Add nodes to the graph
     for node in call_graph.keys() {
-        let node_index = graph.add_node(node.to_string());
+        let node_index = graph.add_node(normalize_name(node));
         nodes.insert(node.as_str(), node_index);
     }
     // Add edges to the graph
     for (node, edges) in call_graph {
         let &node_index = nodes.get(node.as_str()).expect("Node not found");
 
-        for edge in edges.as_array().unwrap() {
-            let &edge_index = nodes.get(edge.as_str().unwrap()).expect("Edge not found");
-            graph.add_edge(node_index, edge_index, ());
+        for (callee, sites) in callees(node, edges)? {
+            match nodes.get(callee) {
+                Some(&edge_index) => {
+                    graph.add_edge(node_index, edge_index, sites);
+                }
+                None => log::warn!("{} calls {}, which is not in the call graph", node, callee),
+            }
         }
     }
+    Ok(graph)
+}
+
+/// The callees of the function `caller` in the `callgraph` object, with
+/// their number of call sites.
+fn callees<'a>(caller: &str, edges: &'a Value) -> Result<Vec<(&'a str, u32)>, LoadError> {
+    let path = format!("callgraph.{}", caller);
+    as_array(edges, &path)?
+        .iter()
+        .enumerate()
+        .map(|(i, edge)| match edge.as_str() {
+            Some(callee) => Ok((callee, 1)),
+            None => {
+                let callee = edge.get("callee").and_then(Value::as_str).ok_or_else(|| {
+                    LoadError::WrongType {
+                        field: format!("{}[{}]", path, i),
+                        expected: "a function name or an object with a `callee` name",
+                    }
+                })?;
+                Ok((
+                    callee,
+                    edge["sites"].as_u64().map_or(1, |sites| sites as u32),
+                ))
+            }
+        })
+        .collect()
+}
+
+/// A call in the input to a function that is not in its call graph.
+#[derive(PartialEq, Clone)]
+pub struct DanglingCallee {
+    pub caller: String,
+    pub callee: String,
+}
+
+impl fmt::Display for DanglingCallee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} calls {}, which is not in the call graph",
+            self.caller, self.callee
+        )
+    }
+}
 
-    // parse constrains
-    let mut proc_constraints: HashMap<String, Vec<Constraint>> = HashMap::new();
-    let constraints = val["constraints"].as_object().unwrap();
-    for (func_name, constraints) in constraints {
-        let constraints_str = constraints.as_array().unwrap();
-        let mut cs: Vec<Constraint> = Vec::new();
-        for constraint in constraints_str {
-            let constraint = constraint.as_str().unwrap();
-            let (str, constraint) = parse_constraint(constraint).unwrap();
-            assert!(str.len() == 0); // no reaming data
-            cs.push(constraint);
+impl fmt::Debug for DanglingCallee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Error for DanglingCallee {}
+
+/// The calls of the document's `callgraph` whose callee is not one of its
+/// keys. These are dropped when the program is built.
+pub fn dangling_callees(val: &Value) -> Result<Vec<DanglingCallee>, LoadError> {
+    let call_graph = as_object(field(val, "callgraph")?, "callgraph")?;
+    let mut dangling = Vec::new();
+    for (caller, edges) in call_graph {
+        for (callee, _) in callees(caller, edges)? {
+            if !call_graph.contains_key(callee) {
+                dangling.push(DanglingCallee {
+                    caller: caller.clone(),
+                    callee: callee.to_string(),
+                });
+            }
         }
-        // insert to proc constrains
-        proc_constraints.insert(func_name.to_string(), cs);
     }
-    Ok(Program {
-        language: val["language"].as_str().unwrap().to_string(),
-        call_graph: graph,
-        proc_constraints: proc_constraints,
-    })
+    Ok(dangling)
+}
+
+/// Read a line-delimited constraint file without keeping the constraints.
+///
+/// Each line is a JSON object, one of:
+/// - `{"language": "..."}`
+/// - `{"callgraph": {...}}`, in the same form as in `constraints_from_json`
+/// - `{"func": "...", "constraint": "..."}`, optionally with a `"loc"`
+///
+/// `callback` is called with each parsed constraint and its function. The
+/// returned program has the language and call graph, but no constraints.
+pub fn constraints_from_json_streaming(
+    path: &str,
+    mut callback: impl FnMut(&str, Constraint),
+) -> Result<Program, LoadError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut program = Program::new("", HashMap::new(), DiGraph::new());
+    for (i, line) in reader.lines().enumerate() {
+        let at_line = |error: LoadError| LoadError::Line {
+            line: i + 1,
+            error: Box::new(error),
+        };
+        let line = line.map_err(|e| at_line(e.into()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let val: Value = serde_json::from_str(&line).map_err(|e| at_line(e.into()))?;
+        if let Some(language) = val["language"].as_str() {
+            program.language = language.to_string();
+        }
+        if val["callgraph"].is_object() {
+            program.call_graph = call_graph_from_json(&val["callgraph"]).map_err(at_line)?;
+        }
+        if let (Some(func), Some(constraint)) = (val["func"].as_str(), val["constraint"].as_str()) {
+            let parsed = parse_function_constraint(func, constraint)
+                .map_err(|e| at_line(LoadError::Parse(ParseErrors(vec![e]))))?;
+            for mut c in parsed {
+                c.provenance = val["loc"].as_str().map(|loc| loc.to_string());
+                callback(func, c);
+            }
+        }
+    }
+    Ok(program)
+}
+
+/// The inverse of `program_from_json`. Each constraint is written with its
+/// `Display` form, in an object with its location if it has one, and the callees of each function in the order the edges
+/// were added.
+pub fn program_to_json(program: &Program) -> Value {
+    let g = &program.call_graph;
+    let mut call_graph = Map::new();
+    for ind in g.node_indices() {
+        call_graph.insert(
+            g.node_weight(ind).unwrap().clone(),
+            Value::Array(Vec::new()),
+        );
+    }
+    for edge in g.edge_references() {
+        let callee = g.node_weight(edge.target()).unwrap().clone();
+        let callee = match edge.weight() {
+            1 => Value::String(callee),
+            sites => json!({"callee": callee, "sites": sites}),
+        };
+        if let Some(Value::Array(callees)) =
+            call_graph.get_mut(g.node_weight(edge.source()).unwrap())
+        {
+            callees.push(callee);
+        }
+    }
+    let mut constraints = Map::new();
+    for (func_name, cs) in &program.proc_constraints {
+        let cs = cs
+            .iter()
+            .map(|c| match &c.provenance {
+                Some(loc) => json!({"constraint": c.to_string(), "loc": loc}),
+                None => Value::String(c.to_string()),
+            })
+            .collect();
+        constraints.insert(func_name.clone(), Value::Array(cs));
+    }
+    let mut json = json!({
+        "language": program.language,
+        "callgraph": call_graph,
+        "constraints": constraints,
+    });
+    if !program.type_hints.is_empty() {
+        let hints: Map<String, Value> = program
+            .type_hints
+            .iter()
+            .map(|(dtv, atom)| (dtv.to_string(), Value::String(atom.clone())))
+            .collect();
+        json["hints"] = Value::Object(hints);
+    }
+    if !program.opaque.is_empty() {
+        let mut opaque: Vec<&String> = program.opaque.iter().collect();
+        opaque.sort();
+        json["opaque"] = json!(opaque);
+    }
+    if let Some(lattice) = &program.types {
+        let atoms: Vec<&String> = lattice
+            .atoms
+            .iter()
+            .filter(|a| *a != TOP && *a != BOTTOM)
+            .collect();
+        json["lattice"] = json!({"atoms": atoms, "order": lattice.order()});
+    }
+    json
+}
+
+/// Parse one constraint string of `function`, which must be consumed entirely.
+/// A string may pack several constraints separated by `;`, none of them
+/// empty.
+fn parse_function_constraint(
+    function: &str,
+    constraint: &str,
+) -> Result<Vec<Constraint>, ParseError> {
+    let error = |offset: usize| ParseError {
+        function: function.to_string(),
+        constraint: constraint.to_string(),
+        offset,
+    };
+    let mut constraints = Vec::new();
+    let mut start = 0;
+    for segment in constraint.split(';') {
+        // offset of the trimmed segment in `constraint`
+        let offset = start + (segment.len() - segment.trim_start().len());
+        start += segment.len() + 1;
+        let segment = segment.trim_start();
+        if segment.is_empty() {
+            return Err(error(offset));
+        }
+        let end = |rest: &str| offset + segment.len() - rest.len();
+        match parse_constraints_line(segment) {
+            Ok((rest, cs)) if rest.trim_end().is_empty() => constraints.extend(cs),
+            Ok((rest, _)) => return Err(error(end(rest))),
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => return Err(error(end(e.input))),
+            Err(nom::Err::Incomplete(_)) => return Err(error(end(""))),
+        }
+    }
+    Ok(constraints)
+}
+
+/// Parse a text file of constraints, one per line (equalities allowed, see
+/// `parse_constraints_line`). Blank lines are skipped, and `//` or `#`
+/// start a comment that runs to the end of the line when they begin the
+/// line or follow a space.
+pub fn parse_constraints_file(text: &str) -> Result<Vec<Constraint>, ParseError> {
+    let mut constraints = Vec::new();
+    for line in text.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        constraints.extend(parse_function_constraint("", line)?);
+    }
+    Ok(constraints)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut prev_space = true;
+    for (i, c) in line.char_indices() {
+        if prev_space && (c == '#' || line[i..].starts_with("//")) {
+            return &line[..i];
+        }
+        prev_space = c.is_whitespace();
+    }
+    line
 }
 
 // this is a rust parser to parse the following language:
 // constraint = DerivedTypeVariable ("<=" | '⊑') DerivedTypeVariable
+// constraints_line = constraint | DerivedTypeVariable ("==" | '≡') DerivedTypeVariable
 // DerivedTypeVariable = Identifier ( '.' FieldLabel )* | Identifier
 // FieldLabel = in_pattern | out_pattern | deref_pattern | 'load' | 'store'
 // in_pattern = re.compile("in_([0-9]+)")
@@ -79,21 +699,47 @@ fn parse_i32(input: &str) -> IResult<&str, i32> {
     Ok((i, number))
 }
 
-fn is_not_seperator(c: char) -> bool {
-    !c.is_whitespace() && c != '.' && c != '<' && c != '=' && c != '⊑'
+/// The glyphs of the operators and variance markers, which are never part
+/// of a name.
+const RESERVED: [char; 5] = ['⊑', '⊒', '≡', '⊕', '⊖'];
+
+/// `name` in Unicode normalization form C, so that a name reads the same
+/// whether a frontend wrote its accents precomposed or not.
+fn normalize_name(name: &str) -> String {
+    name.nfc().collect()
 }
 
+/// An identifier runs up to whitespace, a `.`, a glyph of `RESERVED`, or a
+/// relational operator (`<=`, `>=`, `==`). A `<`, `>` or `=` that does not
+/// start an operator is part of the name, as in `vector<int>`. The name is
+/// normalized, see `normalize_name`.
 fn parse_identifier(input: &str) -> IResult<&str, String> {
-    map(take_while1(is_not_seperator), |s: &str| {
-        s.to_string()
-    })(input)
+    let end = input
+        .char_indices()
+        .find(|(i, c)| {
+            c.is_whitespace()
+                || *c == '.'
+                || RESERVED.contains(c)
+                || (matches!(c, '<' | '>' | '=') && input[i + 1..].starts_with('='))
+        })
+        .map_or(input.len(), |(i, _)| i);
+    if end == 0 {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::TakeWhile1,
+        )));
+    }
+    Ok((&input[end..], normalize_name(&input[..end])))
 }
 
 fn parse_in_pattern(input: &str) -> IResult<&str, FieldLabel> {
-    map(
-        preceded(tag("in_"), parse_identifier),
-        FieldLabel::InPattern,
-    )(input)
+    alt((
+        map(
+            preceded(tag("in_"), parse_identifier),
+            FieldLabel::InPattern,
+        ),
+        map(tag("in"), |_| FieldLabel::InPattern("".to_string())),
+    ))(input)
 }
 
 fn parse_out_pattern(input: &str) -> IResult<&str, FieldLabel> {
@@ -147,28 +793,986 @@ fn parse_field_label(input: &str) -> IResult<&str, FieldLabel> {
     ))(input)
 }
 
+/// `Top` and `Bottom` are spelled `⊤` and `⊥`, so that they are the lattice
+/// endpoints.
+fn lattice_endpoint(name: String) -> String {
+    match name.as_str() {
+        "Top" => TOP.to_string(),
+        "Bottom" => BOTTOM.to_string(),
+        _ => name,
+    }
+}
+
+fn parse_variance(input: &str) -> IResult<&str, Variance> {
+    alt((
+        map(tag("⊕"), |_| Variance::Covariant),
+        map(tag("⊖"), |_| Variance::Contravariant),
+    ))(input)
+}
+
 pub fn parse_derived_type_variable(input: &str) -> IResult<&str, DerivedTypeVariable> {
     map(
-        pair(
+        tuple((
             parse_identifier,
             many0(preceded(char('.'), parse_field_label)),
-        ),
-        |(identifier, fields)| DerivedTypeVariable {
-            name: identifier,
-            fields,
+            opt(preceded(char('.'), parse_variance)),
+        )),
+        |(identifier, fields, variance)| DerivedTypeVariable {
+            name: lattice_endpoint(identifier).into(),
+            fields: Arc::new(fields.into_iter().map(FieldLabelId::new).collect()),
+            variance,
         },
     )(input)
 }
 
+/// Parse `left <= right`, or `right >= left` for the frontends that write
+/// the supertype first, into the constraint `left <= right`. `⊑` and `⊒`
+/// may be used for the operators.
 pub fn parse_constraint(input: &str) -> IResult<&str, Constraint> {
     map(
         tuple((
             parse_derived_type_variable,
-            preceded(
-                delimited(multispace0, alt((tag("<="), tag("⊑"))), multispace0),
-                parse_derived_type_variable,
+            delimited(
+                multispace0,
+                alt((
+                    map(alt((tag("<="), tag("⊑"))), |_| false),
+                    map(alt((tag(">="), tag("⊒"))), |_| true),
+                )),
+                multispace0,
             ),
+            parse_derived_type_variable,
         )),
-        |(left, right)| Constraint { left, right },
+        |(left, reversed, right)| {
+            if reversed {
+                Constraint::new(right, left)
+            } else {
+                Constraint::new(left, right)
+            }
+        },
     )(input)
 }
+
+/// Parse a constraint like `parse_constraint`, but read past the errors
+/// instead of stopping at the first one, for editors that highlight them.
+/// The constraint is returned if the input has no errors, and what could be
+/// read of it otherwise, with a diagnostic for every error.
+pub fn parse_constraint_recovering(
+    input: &str,
+) -> (Result<Constraint, PartialConstraint>, Vec<Diagnostic>) {
+    let offset = |rest: &str| input.len() - rest.len();
+    let mut diagnostics = Vec::new();
+    let rest = input.trim_start();
+    let (rest, left) = recover_derived_type_variable(input, rest, &mut diagnostics);
+    let rest = rest.trim_start();
+    let operator = ["<=", "⊑", ">=", "⊒"]
+        .into_iter()
+        .find(|op| rest.starts_with(op));
+    let reversed = operator.is_some_and(|op| op == ">=" || op == "⊒");
+    let right = match operator {
+        Some(op) => {
+            let (rest, right) = recover_derived_type_variable(
+                input,
+                rest[op.len()..].trim_start(),
+                &mut diagnostics,
+            );
+            let rest = rest.trim();
+            if !rest.is_empty() {
+                diagnostics.push(Diagnostic {
+                    range: offset(rest)..input.trim_end().len(),
+                    message: "unexpected input after the constraint".to_string(),
+                });
+            }
+            right
+        }
+        None => {
+            let end = parse_identifier(rest).map_or(rest, |(after, _)| after);
+            diagnostics.push(Diagnostic {
+                range: offset(rest)..offset(end),
+                message: "expected `<=`, `⊑`, `>=` or `⊒`".to_string(),
+            });
+            None
+        }
+    };
+    let (left, right) = if reversed {
+        (right, left)
+    } else {
+        (left, right)
+    };
+    match (left, right) {
+        (Some(left), Some(right)) if diagnostics.is_empty() => {
+            (Ok(Constraint::new(left, right)), diagnostics)
+        }
+        (left, right) => (Err(PartialConstraint { left, right }), diagnostics),
+    }
+}
+
+/// Read one side of a constraint starting at `rest`, a suffix of `input`.
+/// The fields after one that does not parse are dropped, and the side is
+/// `None` if it has no name.
+fn recover_derived_type_variable<'a>(
+    input: &str,
+    rest: &'a str,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> (&'a str, Option<DerivedTypeVariable>) {
+    let offset = |rest: &str| input.len() - rest.len();
+    let (mut rest, name) = match parse_identifier(rest) {
+        Ok((rest, name)) => (rest, Some(name)),
+        Err(_) => {
+            diagnostics.push(Diagnostic {
+                range: offset(rest)..offset(rest),
+                message: "expected a variable name".to_string(),
+            });
+            (rest, None)
+        }
+    };
+    let mut fields: Vec<FieldLabelId> = Vec::new();
+    let mut variance = None;
+    let mut failed = false;
+    while let Some(after_dot) = rest.strip_prefix('.') {
+        if let Ok((after, v)) = parse_variance(after_dot) {
+            rest = after;
+            variance = Some(v);
+            continue;
+        }
+        // a label runs up to the next `.`, like a name.
+        let (after, label) = parse_identifier(after_dot).unwrap_or((after_dot, String::new()));
+        rest = after;
+        match parse_field_label(&label) {
+            Ok(("", field)) if !failed => fields.push(field.into()),
+            Ok(("", _)) => {}
+            _ => {
+                failed = true;
+                diagnostics.push(Diagnostic {
+                    range: offset(after_dot)..offset(after),
+                    message: format!("unknown field label `{}`", label),
+                });
+            }
+        }
+    }
+    let dtv = name.map(|name| DerivedTypeVariable {
+        name: lattice_endpoint(name).into(),
+        fields: Arc::new(fields),
+        variance,
+    });
+    (rest, dtv)
+}
+
+/// Parse a line holding one constraint, where an equality `a == b` (or
+/// `a ≡ b`) stands for the two constraints `a <= b` and `b <= a`.
+pub fn parse_constraints_line(input: &str) -> IResult<&str, Vec<Constraint>> {
+    alt((
+        map(
+            pair(
+                parse_derived_type_variable,
+                preceded(
+                    delimited(multispace0, alt((tag("=="), tag("≡"))), multispace0),
+                    parse_derived_type_variable,
+                ),
+            ),
+            |(left, right)| {
+                vec![
+                    Constraint::new(left.clone(), right.clone()),
+                    Constraint::new(right, left),
+                ]
+            },
+        ),
+        map(parse_constraint, |c| vec![c]),
+    ))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        constraints_from_json, constraints_from_json_streaming, constraints_from_jsons,
+        parse_constraint, parse_constraint_recovering, parse_constraints_file,
+        parse_constraints_line, parse_derived_type_variable, program_from_json,
+        program_from_json_lenient, program_to_json, try_parse_program, Diagnostic, LoadError,
+        ParseErrors, PartialConstraint,
+    };
+    use crate::lattice::{Lattice, BOTTOM, TOP};
+    use crate::schema::{Bound, Constraint, DerivedTypeVariable, FieldLabel, MergeError, Variance};
+    use petgraph::visit::EdgeRef;
+    use std::{fs::File, io::Write};
+
+    /// A linear congruential generator with a fixed `seed`, so failures
+    /// reproduce.
+    fn lcg(seed: u64) -> impl FnMut() -> u32 {
+        let mut state = seed;
+        move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 32) as u32
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let reparsed = program_from_json(&program_to_json(&program)).unwrap();
+        assert_eq!(reparsed.language, program.language);
+        assert_eq!(reparsed.proc_constraints, program.proc_constraints);
+        let edges = |p: &super::Program| {
+            let g = &p.call_graph;
+            let mut edges: Vec<(String, String)> = g
+                .raw_edges()
+                .iter()
+                .map(|e| (g[e.source()].clone(), g[e.target()].clone()))
+                .collect();
+            edges.sort();
+            edges
+        };
+        assert_eq!(edges(&reparsed), edges(&program));
+        // the bare `out` label is written back as `out`.
+        assert!(program.proc_constraints["main"]
+            .iter()
+            .any(|c| c.to_string() == "v_14 <= main.out"));
+    }
+
+    #[test]
+    fn test_constraint_provenance() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": []},
+            "constraints": {"f": [
+                "x <= y",
+                {"constraint": "y == z", "loc": "0x401000"},
+            ]},
+        });
+        let program = program_from_json(&val).unwrap();
+        let cs = &program.proc_constraints["f"];
+        assert_eq!(cs.len(), 3);
+        assert_eq!(cs[0].provenance, None);
+        assert_eq!(cs[1].provenance.as_deref(), Some("0x401000"));
+        assert_eq!(cs[2].provenance.as_deref(), Some("0x401000"));
+        assert_eq!(cs[1], parse_constraint("y <= z").unwrap().1);
+
+        let reparsed = program_from_json(&program_to_json(&program)).unwrap();
+        let provenance: Vec<_> = reparsed.proc_constraints["f"]
+            .iter()
+            .map(|c| c.provenance.clone())
+            .collect();
+        assert_eq!(
+            provenance,
+            cs.iter().map(|c| c.provenance.clone()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_streaming_reader() {
+        let path =
+            std::env::temp_dir().join(format!("retypd-streaming-{}.jsonl", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, r#"{{"language": "x86"}}"#).unwrap();
+        writeln!(file, r#"{{"callgraph": {{"f": ["g"], "g": []}}}}"#).unwrap();
+        for i in 0..50_000 {
+            let func = if i % 2 == 0 { "f" } else { "g" };
+            writeln!(
+                file,
+                r#"{{"func": "{}", "constraint": "v_{} <= v_{}.load"}}"#,
+                func,
+                i,
+                i + 1
+            )
+            .unwrap();
+        }
+        drop(file);
+
+        let mut count = 0;
+        let mut in_f = 0;
+        let program = constraints_from_json_streaming(path.to_str().unwrap(), |func, _| {
+            count += 1;
+            if func == "f" {
+                in_f += 1;
+            }
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count, 50_000);
+        assert_eq!(in_f, 25_000);
+        assert_eq!(program.language, "x86");
+        assert_eq!(program.call_graph.node_count(), 2);
+        assert_eq!(program.call_graph.edge_count(), 1);
+
+        // errors name the line they are on.
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, r#"{{"language": "x86"}}"#).unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"func": "f", "constraint": "v_0 <= "}}"#).unwrap();
+        writeln!(file, "not json").unwrap();
+        drop(file);
+        let err = constraints_from_json_streaming(path.to_str().unwrap(), |_, _| {})
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, LoadError::Line { line: 3, error } if matches!(**error, LoadError::Parse(_))),
+            "{}",
+            err
+        );
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, r#"{{"callgraph": {{"f": 1}}}}"#).unwrap();
+        writeln!(file, "not json").unwrap();
+        drop(file);
+        let err = constraints_from_json_streaming(path.to_str().unwrap(), |_, _| {})
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, LoadError::Line { line: 1, error } if matches!(**error, LoadError::WrongType { .. })),
+            "{}",
+            err
+        );
+        std::fs::write(&path, "{}\nnot json\n").unwrap();
+        let err = constraints_from_json_streaming(path.to_str().unwrap(), |_, _| {})
+            .err()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(&err, LoadError::Line { line: 2, error } if matches!(**error, LoadError::Json(_))),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_bare_in_out() {
+        let c = parse_constraint("f.in <= x").unwrap().1;
+        assert_eq!(*c.left.fields, vec![FieldLabel::InPattern("".to_string())]);
+        assert_eq!(c.left.fields[0].index(), Some(0));
+        assert_eq!(c.to_string(), "f.in <= x");
+        let dtv = parse_derived_type_variable("f.out").unwrap().1;
+        assert_eq!(*dtv.fields, vec![FieldLabel::OutPattern("".to_string())]);
+        assert_eq!(dtv.fields[0].index(), Some(0));
+        assert_eq!(dtv.to_string(), "f.out");
+        // the explicit form is kept as written.
+        let dtv = parse_derived_type_variable("f.out_0").unwrap().1;
+        assert_eq!(dtv.fields[0].index(), Some(0));
+        assert_eq!(dtv.to_string(), "f.out_0");
+        let dtv = parse_derived_type_variable("f.in_stack0").unwrap().1;
+        assert_eq!(dtv.fields[0].index(), None);
+    }
+
+    #[test]
+    fn test_parse_numbered_pattern() {
+        let dtv = parse_derived_type_variable("f.in_3").unwrap().1;
+        assert_eq!(*dtv.fields, vec![FieldLabel::InPattern("3".to_string())]);
+        assert_eq!(dtv.fields[0].index(), Some(3));
+        assert_eq!(
+            parse_derived_type_variable(&dtv.to_string()).unwrap().1,
+            dtv
+        );
+        let dtv = parse_derived_type_variable("f.out_1").unwrap().1;
+        assert_eq!(dtv.fields[0].index(), Some(1));
+        assert_eq!(dtv.to_string(), "f.out_1");
+        for name in ["f.in_03", "f.in_+3", "f.in_4294967296"] {
+            let dtv = parse_derived_type_variable(name).unwrap().1;
+            assert_eq!(dtv.fields[0].index(), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_parse_top_bottom() {
+        for input in ["⊥ <= x", "Bottom <= x"] {
+            let c = parse_constraint(input).unwrap().1;
+            assert!(c.left.is_bottom());
+            assert!(!c.right.is_bottom() && !c.right.is_top());
+        }
+        for input in ["x <= ⊤", "x <= Top"] {
+            let c = parse_constraint(input).unwrap().1;
+            assert!(c.right.is_top());
+            assert_eq!(c.to_string(), "x <= ⊤");
+        }
+        // a field of ⊤ is not ⊤ itself.
+        assert!(!parse_derived_type_variable("⊤.load").unwrap().1.is_top());
+    }
+
+    #[test]
+    fn test_parse_variance_marker() {
+        for (input, variance) in [
+            ("foo.load.⊖", Variance::Contravariant),
+            ("foo.⊕", Variance::Covariant),
+        ] {
+            let (rest, dtv) = parse_derived_type_variable(input).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(dtv.variance, Some(variance));
+            assert_eq!(dtv.to_string(), input);
+            assert_eq!(
+                parse_derived_type_variable(&dtv.to_string()).unwrap().1,
+                dtv
+            );
+        }
+        let dtv = parse_derived_type_variable("foo.load.⊖").unwrap().1;
+        assert_eq!(*dtv.fields, vec![FieldLabel::Load]);
+        assert_eq!(
+            parse_derived_type_variable("foo.load").unwrap().1.variance,
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_operators_in_names() {
+        for line in ["a<=b", "a <= b", "a ⊑ b", "a⊑b"] {
+            let (rest, c) = parse_constraint(line).unwrap();
+            assert!(rest.is_empty(), "{}", line);
+            assert_eq!(c.to_string(), "a <= b");
+        }
+        let (rest, c) = parse_constraint("v10x.load<=a2b3").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(&*c.left.name, "v10x");
+        assert_eq!(&*c.right.name, "a2b3");
+        // a `<` or `=` that is no operator stays in the name.
+        let (rest, c) = parse_constraint("vector<int> <= a=b").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(&*c.left.name, "vector<int>");
+        assert_eq!(&*c.right.name, "a=b");
+        let (rest, cs) = parse_constraints_line("x<y==z").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(cs[0].to_string(), "x<y <= z");
+    }
+
+    #[test]
+    fn test_reserved_glyphs() {
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // σ is fine in a deref pattern, and so are other scripts in names.
+        let c = parse_constraint("𝜑.load.σ4@0 <= 洧.σ8@-4").unwrap();
+        assert_eq!(c.0, "");
+        assert_eq!(c.1.to_string(), "𝜑.load.σ4@0 <= 洧.σ8@-4");
+        // a variance marker or an operator glyph ends a name.
+        assert_eq!(
+            parse_derived_type_variable("a⊕b").unwrap(),
+            ("⊕b", dtv("a"))
+        );
+        assert_eq!(
+            parse_derived_type_variable("a.⊕").unwrap().1.variance,
+            Some(Variance::Covariant)
+        );
+        for input in ["a⊕b <= c", "⊕ <= c", "a <= b⊖", "a≡b <= c"] {
+            assert!(!matches!(parse_constraint(input), Ok(("", _))), "{}", input);
+        }
+        let (c, diagnostics) = parse_constraint_recovering("a.load.⊖ <= b⊕");
+        assert_eq!(c.err().unwrap().left, Some(dtv("a.load.⊖")));
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    }
+
+    #[test]
+    fn test_normalize_names() {
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // `é` as one code point, and as `e` with a combining accent.
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(composed, decomposed);
+        assert_eq!(
+            dtv(&format!("{}.in_{}", decomposed, decomposed)),
+            dtv(&format!("{}.in_{}", composed, composed))
+        );
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {decomposed: []},
+            "constraints": {decomposed: [format!("{}.out <= x", composed)]},
+        });
+        let program = program_from_json(&val).unwrap();
+        assert!(program.proc_constraints.contains_key(composed));
+        assert_eq!(
+            program.call_graph.node_weights().collect::<Vec<_>>(),
+            [composed]
+        );
+    }
+
+    #[test]
+    fn test_parse_supertype_constraint() {
+        for line in ["a >= b", "a>=b", "a ⊒ b", "a⊒b"] {
+            let (rest, c) = parse_constraint(line).unwrap();
+            assert!(rest.is_empty(), "{}", line);
+            assert_eq!(
+                c,
+                Constraint::new(DerivedTypeVariable::new("b"), DerivedTypeVariable::new("a")),
+                "{}",
+                line
+            );
+        }
+        let (rest, c) = parse_constraint("vector<int> >= x.load").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(c.to_string(), "x.load <= vector<int>");
+        assert_eq!(&*parse_constraint("a>b <= c").unwrap().1.left.name, "a>b");
+
+        let (result, diagnostics) = parse_constraint_recovering("a >= b.bogus");
+        assert_eq!(
+            result.unwrap_err().left,
+            Some(DerivedTypeVariable::new("b"))
+        );
+        assert_eq!(diagnostics.len(), 1);
+
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": []},
+            "constraints": {"f": ["f.out >= x"]},
+        });
+        let program = program_from_json(&val).unwrap();
+        assert_eq!(program.proc_constraints["f"][0].to_string(), "x <= f.out");
+    }
+
+    #[test]
+    fn test_parse_equality() {
+        for line in ["x == y", "x ≡ y", "x==y"] {
+            let (rest, constraints) = parse_constraints_line(line).unwrap();
+            assert!(rest.is_empty());
+            let expected = vec![
+                parse_constraint("x <= y").unwrap().1,
+                parse_constraint("y <= x").unwrap().1,
+            ];
+            assert_eq!(constraints, expected);
+        }
+
+        let (rest, constraints) = parse_constraints_line("x.load <= y").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            constraints,
+            vec![parse_constraint("x.load <= y").unwrap().1]
+        );
+
+        assert!(parse_constraints_line("x ==").is_err());
+        assert!(parse_constraints_line("== y").is_err());
+    }
+
+    #[test]
+    fn test_parse_constraints_file() {
+        let text = "
+            // the saturation example
+            y <= p
+            p <= x   # y <= x
+
+            # pointers
+            _A <= x.store // stored
+            y.load <= _B
+            a == b
+        ";
+        let constraints = parse_constraints_file(text).unwrap();
+        assert_eq!(constraints.len(), 6);
+        assert_eq!(constraints[1].to_string(), "p <= x");
+        assert_eq!(constraints[2].to_string(), "_A <= x.store");
+
+        let err = parse_constraints_file("x <= y\nx <= # y").unwrap_err();
+        assert_eq!(err.function, "");
+        assert_eq!(err.constraint, "x <=");
+        assert!(
+            err.to_string()
+                .starts_with("cannot parse constraint at byte 4"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_parse_constraint_recovering() {
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        let (c, diagnostics) = parse_constraint_recovering("x.bogus <= y");
+        assert_eq!(
+            c,
+            Err(PartialConstraint {
+                left: Some(dtv("x")),
+                right: Some(dtv("y")),
+            })
+        );
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                range: 2..7,
+                message: "unknown field label `bogus`".to_string(),
+            }]
+        );
+
+        // the fields before the bad one are kept.
+        let (c, diagnostics) =
+            parse_constraint_recovering("x.load.bogus.store.⊖ <= y.out_eax extra");
+        let c = c.unwrap_err();
+        assert_eq!(c.left, Some(dtv("x.load.⊖")));
+        assert_eq!(c.right, Some(dtv("y.out_eax")));
+        let ranges: Vec<_> = diagnostics.iter().map(|d| d.range.clone()).collect();
+        assert_eq!(ranges, [7..12, 36..41]);
+
+        let (c, diagnostics) = parse_constraint_recovering("x.load y");
+        assert_eq!(c.unwrap_err().right, None);
+        assert_eq!(diagnostics[0].range, 7..8);
+
+        let ok = "F.in_stack0 <= close.in_stack0";
+        assert_eq!(
+            parse_constraint_recovering(ok),
+            (Ok(parse_constraint(ok).unwrap().1), Vec::new())
+        );
+    }
+
+    #[test]
+    fn test_constraints_from_jsons() {
+        let simple = "tests/retypd-constrains-simple.json";
+        let extra = "tests/retypd-constrains-extra.json";
+        let program = constraints_from_jsons(&[simple, extra]).unwrap();
+        // main is in both files.
+        assert_eq!(program.proc_constraints["main"].len(), 15 + 2);
+        assert_eq!(program.proc_constraints["helper"].len(), 1);
+        let total: usize = program.proc_constraints.values().map(Vec::len).sum();
+        assert_eq!(total, 19 + 3);
+        let g = &program.call_graph;
+        assert_eq!(g.node_count(), 6);
+        assert!(g
+            .edge_references()
+            .any(|e| g[e.source()] == "main" && g[e.target()] == "helper"));
+
+        let mut other = constraints_from_json(extra).unwrap();
+        other.language = "x86".to_string();
+        let mut program = constraints_from_json(simple).unwrap();
+        let err = program.merge(other).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot merge a program for x86 into one for ARM32"
+        );
+        assert!(matches!(
+            constraints_from_jsons(&[]),
+            Err(LoadError::NoInput)
+        ));
+        let path = std::env::temp_dir().join(format!("retypd-merge-{}.json", std::process::id()));
+        let mut x86 = constraints_from_json(extra).unwrap();
+        x86.language = "x86".to_string();
+        std::fs::write(&path, program_to_json(&x86).to_string()).unwrap();
+        let err = constraints_from_jsons(&[simple, path.to_str().unwrap()])
+            .err()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            matches!(err, LoadError::Merge(MergeError::Language { .. })),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_deref_round_trip() {
+        let mut next = lcg(0x2545_f491_4f6c_dd1d);
+        let mut labels = Vec::new();
+        for (size, offset) in [(0, 0), (u32::MAX, i32::MIN), (1, i32::MAX), (4, -8)] {
+            labels.push(FieldLabel::DerefPattern {
+                size,
+                offset,
+                bound: Some(Bound::Fixed(16)),
+            });
+        }
+        for _ in 0..1000 {
+            let bound = match next() % 4 {
+                0 => None,
+                1 => Some(Bound::NullTerm),
+                2 => Some(Bound::NoBound),
+                _ => Some(Bound::Fixed(next())),
+            };
+            // small ones often, as real offsets are.
+            let (size, offset) = match next() % 2 {
+                0 => (next(), next() as i32),
+                _ => (next() % 16, (next() % 64) as i32 - 32),
+            };
+            labels.push(FieldLabel::DerefPattern {
+                size,
+                offset,
+                bound,
+            });
+        }
+        for label in labels {
+            let text = format!("x.{}.load.⊖", label);
+            let (rest, dtv) = parse_derived_type_variable(&text).unwrap();
+            assert_eq!(rest, "", "{}", text);
+            assert_eq!(dtv.fields[0], label, "{}", text);
+            assert_eq!(dtv.to_string(), text);
+        }
+        let (_, dtv) = parse_derived_type_variable("x.σ4@-8*[16]").unwrap();
+        assert_eq!(dtv.to_string(), "x.σ4@-8*[16]");
+        // other spellings of the numbers are written the usual way.
+        for (text, written) in [("x.σ04@-0", "x.σ4@0"), ("x.σ4@008*[016]", "x.σ4@8*[16]")] {
+            let (_, dtv) = parse_derived_type_variable(text).unwrap();
+            assert_eq!(dtv.to_string(), written);
+            assert_eq!(parse_derived_type_variable(written).unwrap().1, dtv);
+        }
+    }
+
+    #[test]
+    fn test_call_sites() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": ["g", {"callee": "h", "sites": 3}], "g": [{"callee": "h"}], "h": []},
+            "constraints": {"f": [], "g": [], "h": []},
+        });
+        let program = program_from_json(&val).unwrap();
+        let g = &program.call_graph;
+        let mut sites: Vec<(&str, &str, u32)> = g
+            .edge_references()
+            .map(|e| (g[e.source()].as_str(), g[e.target()].as_str(), *e.weight()))
+            .collect();
+        sites.sort();
+        assert_eq!(sites, [("f", "g", 1), ("f", "h", 3), ("g", "h", 1)]);
+        // a single site is written back as the plain name.
+        let json = program_to_json(&program);
+        assert_eq!(
+            json["callgraph"]["f"],
+            serde_json::json!(["g", {"callee": "h", "sites": 3}])
+        );
+        assert_eq!(json["callgraph"]["g"], serde_json::json!(["h"]));
+    }
+
+    #[test]
+    fn test_type_hints() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"close": []},
+            "constraints": {"close": []},
+            "hints": {"close.in_stack0": "_FileDescriptor", "close.load <=": "int"},
+        });
+        let (program, errors) = program_from_json_lenient(&val).unwrap();
+        let dtv = parse_derived_type_variable("close.in_stack0").unwrap().1;
+        assert_eq!(program.type_hints.len(), 1);
+        assert_eq!(program.type_hints[&dtv], "_FileDescriptor");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].constraint, "close.load <=");
+
+        let json = program_to_json(&program);
+        assert_eq!(
+            json["hints"],
+            serde_json::json!({"close.in_stack0": "_FileDescriptor"})
+        );
+        assert_eq!(
+            program_from_json(&json).unwrap().type_hints,
+            program.type_hints
+        );
+
+        let val = serde_json::json!({"language": "x86", "callgraph": {}, "constraints": {}, "hints": {"x": 1}});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "hints.x")
+        );
+    }
+
+    #[test]
+    fn test_try_parse_program() {
+        let valid = std::fs::read("tests/retypd-constrains-simple.json").unwrap();
+        let program = try_parse_program(&valid).unwrap();
+        assert_eq!(program.proc_constraints.len(), 5);
+
+        let mut next = lcg(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..1000 {
+            let len = (next() % 64) as usize;
+            let bytes: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            assert!(try_parse_program(&bytes).is_err(), "{:?}", bytes);
+        }
+        let end = valid.trim_ascii_end().len();
+        for len in 0..end {
+            assert!(try_parse_program(&valid[..len]).is_err(), "{}", len);
+        }
+        // change bytes of the valid document, which may break the UTF-8 or
+        // the JSON, or give names and labels odd characters. Only checks
+        // that nothing panics.
+        let weird = [
+            "⊑", "⊒", "σ", "@", "*[", r"\u0000", ".", "<=", "==", ";", r"\ud800", "👾", r#"\""#,
+        ];
+        for _ in 0..1000 {
+            let mut bytes = valid.clone();
+            for _ in 0..1 + next() % 4 {
+                let at = next() as usize % bytes.len();
+                if next().is_multiple_of(2) {
+                    bytes[at] = next() as u8;
+                } else {
+                    let insert = weird[next() as usize % weird.len()].as_bytes();
+                    bytes.splice(at..at, insert.iter().cloned());
+                }
+            }
+            let _ = try_parse_program(&bytes).map_err(|e| e.to_string());
+        }
+    }
+
+    #[test]
+    fn test_opaque() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": []},
+            "constraints": {"f": []},
+            "opaque": ["y", "x"],
+        });
+        let program = program_from_json(&val).unwrap();
+        assert!(program.opaque.contains("x") && program.opaque.contains("y"));
+        assert_eq!(
+            program_to_json(&program)["opaque"],
+            serde_json::json!(["x", "y"])
+        );
+        let val = serde_json::json!({"language": "x86", "callgraph": {}, "constraints": {}, "opaque": ["x", 2]});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "opaque[1]")
+        );
+    }
+
+    #[test]
+    fn test_lattice() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": []},
+            "constraints": {"f": []},
+            "lattice": {
+                "atoms": ["num", "int", "uint", "_fd"],
+                "order": [["int", "num"], ["uint", "num"], ["_fd", "int"], ["num", "Top"], ["bottom", "_fd"]],
+            },
+        });
+        let program = program_from_json(&val).unwrap();
+        let lattice = program.types.as_ref().unwrap();
+        let s = |a: &str| a.to_string();
+        assert!(lattice.leq(&s("_fd"), &s("num")));
+        assert!(lattice.leq(&s(BOTTOM), &s("uint")) && lattice.leq(&s("uint"), &s(TOP)));
+        assert!(!lattice.leq(&s("num"), &s("int")));
+        assert!(!lattice.leq(&s("_fd"), &s("uint")));
+        assert_eq!(lattice.join(&s("int"), &s("uint")), "num");
+        assert!(!lattice.contains("top"));
+
+        let json = program_to_json(&program);
+        let reloaded = program_from_json(&json).unwrap().types.unwrap();
+        assert_eq!(reloaded.atoms, lattice.atoms);
+        assert_eq!(reloaded.order(), lattice.order());
+        assert!(program_from_json(
+            &serde_json::json!({"language": "x86", "callgraph": {}, "constraints": {}})
+        )
+        .unwrap()
+        .types
+        .is_none());
+
+        let val = serde_json::json!({"language": "x86", "callgraph": {}, "constraints": {}, "lattice": {"order": [["int"]]}});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "lattice.order[0]")
+        );
+        let val = serde_json::json!({"language": "x86", "callgraph": {}, "constraints": {}, "lattice": {"atoms": [1]}});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "lattice.atoms[0]")
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        use serde_json::json;
+        let err = program_from_json(&json!({"language": "x86", "constraints": {}}))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, LoadError::MissingField { field } if field == "callgraph"),
+            "{}",
+            err
+        );
+        assert_eq!(err.to_string(), "missing field `callgraph`");
+
+        let err = program_from_json(&json!({"callgraph": {}, "constraints": {}}))
+            .err()
+            .unwrap();
+        assert!(
+            matches!(&err, LoadError::MissingField { field } if field == "language"),
+            "{}",
+            err
+        );
+
+        let err =
+            program_from_json(&json!({"language": "x86", "callgraph": {}, "constraints": []}))
+                .err()
+                .unwrap();
+        assert!(
+            matches!(&err, LoadError::WrongType { field, .. } if field == "constraints"),
+            "{}",
+            err
+        );
+        assert_eq!(err.to_string(), "field `constraints` is not an object");
+
+        // deeper values are checked too.
+        let val = json!({"language": "x86", "callgraph": {"f": "g"}, "constraints": {}});
+        let err = program_from_json(&val).err().unwrap();
+        assert!(
+            matches!(&err, LoadError::WrongType { field, .. } if field == "callgraph.f"),
+            "{}",
+            err
+        );
+        let val = json!({"language": "x86", "callgraph": {"f": [{"sites": 2}]}, "constraints": {}});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "callgraph.f[0]")
+        );
+        let val = json!({"language": "x86", "callgraph": {}, "constraints": {"f": "x <= y"}});
+        assert!(
+            matches!(program_from_json(&val), Err(LoadError::WrongType { field, .. }) if field == "constraints.f")
+        );
+
+        assert!(matches!(
+            constraints_from_json("tests/no-such-file.json"),
+            Err(LoadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_packed_constraints() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": []},
+            "constraints": {"f": ["x <= y ;y <= z", "a == b"]},
+        });
+        let program = program_from_json(&val).unwrap();
+        let cs: Vec<String> = program.proc_constraints["f"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(cs, ["x <= y", "y <= z", "a <= b", "b <= a"]);
+
+        for (packed, offset) in [
+            ("x <= y;; y <= z", 7),
+            ("x <= y; ", 8),
+            ("x <= y; y <=", 12),
+        ] {
+            let val = serde_json::json!({
+                "language": "x86",
+                "callgraph": {"f": []},
+                "constraints": {"f": [packed]},
+            });
+            let err = program_from_json(&val).err().unwrap();
+            let LoadError::Parse(ParseErrors(errors)) = &err else {
+                panic!("{}", err)
+            };
+            assert_eq!(errors[0].offset, offset, "{}", packed);
+        }
+    }
+
+    #[test]
+    fn test_all_parse_errors() {
+        let val = serde_json::json!({
+            "language": "x86",
+            "callgraph": {"f": [], "g": [], "h": []},
+            "constraints": {
+                "f": ["x <= y", "x <="],
+                "g": ["<= y"],
+                "h": ["x <= y"],
+            },
+        });
+        let err = program_from_json(&val).err().unwrap();
+        let LoadError::Parse(ParseErrors(errors)) = &err else {
+            panic!("{}", err)
+        };
+        let mut functions: Vec<&str> = errors.iter().map(|e| e.function.as_str()).collect();
+        functions.sort();
+        assert_eq!(functions, ["f", "g"]);
+        assert!(err.to_string().contains("x <="), "{}", err);
+        assert!(err.to_string().contains("<= y"), "{}", err);
+
+        let (program, errors) = program_from_json_lenient(&val).unwrap();
+        assert_eq!(errors.len(), 2);
+        let procs: Vec<&String> = program.proc_constraints.keys().collect();
+        assert_eq!(procs, ["h"]);
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let err = constraints_from_json("tests/retypd-constrains-broken.json")
+            .err()
+            .unwrap();
+        let LoadError::Parse(errors) = err else {
+            panic!("{}", err)
+        };
+        assert_eq!(errors.0.len(), 1);
+        let err = &errors.0[0];
+        assert_eq!(err.function, "F");
+        assert_eq!(err.constraint, "F.in_stack0 <= ");
+        assert_eq!(err.offset, "F.in_stack0 <= ".len());
+
+        let err = super::parse_function_constraint("F", "x <= y z").unwrap_err();
+        assert_eq!(err.offset, "x <= y".len());
+    }
+}
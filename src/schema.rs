@@ -1,9 +1,23 @@
 use core::fmt;
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    str::FromStr,
+    sync::{Arc, LazyLock, RwLock},
+};
 
-use petgraph::graph::DiGraph;
+use petgraph::{
+    algo::condensation,
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+};
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+use crate::lattice::{CTypeLattice, BOTTOM, TOP};
+
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum Variance {
     Covariant,
     Contravariant,
@@ -18,6 +32,12 @@ impl fmt::Display for Variance {
     }
 }
 
+impl Debug for Variance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 impl Variance {
     pub fn invert(&self) -> Variance {
         match self {
@@ -50,6 +70,9 @@ pub enum FieldLabel {
 impl fmt::Display for FieldLabel {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            // the bare `in` and `out` have no name.
+            FieldLabel::InPattern(i) if i.is_empty() => write!(f, "in"),
+            FieldLabel::OutPattern(i) if i.is_empty() => write!(f, "out"),
             FieldLabel::InPattern(i) => write!(f, "in_{}", i),
             FieldLabel::OutPattern(i) => write!(f, "out_{}", i),
             FieldLabel::DerefPattern {
@@ -76,23 +99,325 @@ impl Debug for FieldLabel {
     }
 }
 
+/// `InPattern < OutPattern < DerefPattern < Load < Store`. Patterns of the
+/// same kind are ordered by name with numbers compared by value, so
+/// `in_stack4 < in_stack12`, and deref patterns by offset.
+impl Ord for FieldLabel {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (FieldLabel::InPattern(a), FieldLabel::InPattern(b))
+            | (FieldLabel::OutPattern(a), FieldLabel::OutPattern(b)) => natural_cmp(a, b),
+            (
+                FieldLabel::DerefPattern {
+                    size,
+                    offset,
+                    bound,
+                },
+                FieldLabel::DerefPattern {
+                    size: size2,
+                    offset: offset2,
+                    bound: bound2,
+                },
+            ) => (offset, size, bound).cmp(&(offset2, size2, bound2)),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for FieldLabel {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare the names by their part before the trailing number, then by the
+/// number's value.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn split(s: &str) -> (&str, Option<u64>) {
+        let prefix = s.trim_end_matches(|c: char| c.is_ascii_digit());
+        (prefix, s[prefix.len()..].parse().ok())
+    }
+    split(a).cmp(&split(b)).then_with(|| a.cmp(b))
+}
+
+/// Variances of labels that override `FieldLabel::variance`, e.g. to make
+/// some stack slots of a calling convention contravariant. Deref patterns
+/// match whatever their bound.
+#[derive(Default, Clone)]
+pub struct VarianceTable {
+    overrides: Vec<(FieldLabel, Variance)>,
+}
+
+impl VarianceTable {
+    pub fn set(&mut self, label: FieldLabel, variance: Variance) {
+        self.overrides.retain(|(l, _)| !l.same_capability(&label));
+        self.overrides.push((label, variance));
+    }
+    pub fn get(&self, label: &FieldLabel) -> Option<&Variance> {
+        self.overrides
+            .iter()
+            .find(|(l, _)| l.same_capability(label))
+            .map(|(_, v)| v)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.overrides.is_empty()
+    }
+}
+
+/// Two tables are equal when they override the same labels the same way,
+/// whatever the order the overrides were set in.
+impl PartialEq for VarianceTable {
+    fn eq(&self, other: &Self) -> bool {
+        self.overrides.len() == other.overrides.len()
+            && self
+                .overrides
+                .iter()
+                .all(|(label, variance)| other.get(label) == Some(variance))
+    }
+}
+
+/// The labels that read and write through a pointer, which Rule (S-Pointer)
+/// relates as `α.store ⊑ α.load`, and shape inference unifies. Some IRs have
+/// capabilities of their own for these; deref patterns match whatever their
+/// bound.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointerCapabilities {
+    pub load: FieldLabel,
+    pub store: FieldLabel,
+}
+
+impl Default for PointerCapabilities {
+    fn default() -> Self {
+        PointerCapabilities {
+            load: FieldLabel::Load,
+            store: FieldLabel::Store,
+        }
+    }
+}
+
+impl PointerCapabilities {
+    /// The store for a load and the load for a store, `None` for the other
+    /// labels.
+    pub fn inverse(&self, label: &FieldLabel) -> Option<&FieldLabel> {
+        if label.same_capability(&self.load) {
+            Some(&self.store)
+        } else if label.same_capability(&self.store) {
+            Some(&self.load)
+        } else {
+            None
+        }
+    }
+}
+
 impl FieldLabel {
+    /// The variance from `table`, or the default one.
+    pub fn variance_with(&self, table: &VarianceTable) -> Variance {
+        table.get(self).cloned().unwrap_or_else(|| self.variance())
+    }
+    /// The position of an `in`/`out` pattern when it is numbered, like
+    /// `in_3`. The bare `in` and `out` are the position 0. Named ones, like
+    /// `in_stack0` or `out_eax`, have none, and so do the numbers not
+    /// written in the shortest form, like `in_03`, so that two labels never
+    /// share a position.
+    pub fn index(&self) -> Option<u32> {
+        match self {
+            FieldLabel::InPattern(name) | FieldLabel::OutPattern(name) if name.is_empty() => {
+                Some(0)
+            }
+            FieldLabel::InPattern(name) | FieldLabel::OutPattern(name)
+                if name.bytes().all(|b| b.is_ascii_digit())
+                    && (name == "0" || !name.starts_with('0')) =>
+            {
+                name.parse().ok()
+            }
+            _ => None,
+        }
+    }
+    /// Whether the two labels are the same capability, ignoring the bound
+    /// of deref patterns.
+    pub fn same_capability(&self, other: &FieldLabel) -> bool {
+        match (self, other) {
+            (
+                FieldLabel::DerefPattern { size, offset, .. },
+                FieldLabel::DerefPattern {
+                    size: size2,
+                    offset: offset2,
+                    ..
+                },
+            ) => size == size2 && offset == offset2,
+            _ => self == other,
+        }
+    }
+    /// `self` with its bound combined with the one of `other`, see
+    /// `Bound::meet`.
+    pub fn meet_bound(&self, other: &FieldLabel) -> FieldLabel {
+        match (self, other) {
+            (
+                FieldLabel::DerefPattern {
+                    size,
+                    offset,
+                    bound,
+                },
+                FieldLabel::DerefPattern { bound: bound2, .. },
+            ) => FieldLabel::DerefPattern {
+                size: *size,
+                offset: *offset,
+                bound: Bound::meet(bound, bound2),
+            },
+            _ => self.clone(),
+        }
+    }
+    /// `load` or `store`: an access to the value behind a pointer.
+    pub fn is_access(&self) -> bool {
+        matches!(self, FieldLabel::Load | FieldLabel::Store)
+    }
+    /// `σN@k`: a field of the value itself.
+    pub fn is_field(&self) -> bool {
+        matches!(self, FieldLabel::DerefPattern { .. })
+    }
+    /// `in` or `out`: a parameter or a return value of a function.
+    pub fn is_io(&self) -> bool {
+        matches!(self, FieldLabel::InPattern(_) | FieldLabel::OutPattern(_))
+    }
+    fn rank(&self) -> u8 {
+        match self {
+            FieldLabel::InPattern(_) => 0,
+            FieldLabel::OutPattern(_) => 1,
+            FieldLabel::DerefPattern { .. } => 2,
+            FieldLabel::Load => 3,
+            FieldLabel::Store => 4,
+        }
+    }
     pub fn variance(&self) -> Variance {
         match self {
             FieldLabel::InPattern(_) => Variance::Contravariant,
             FieldLabel::OutPattern(_) => Variance::Covariant,
-            FieldLabel::DerefPattern {
-                size: _,
-                offset: _,
-                bound: _,
-            } => Variance::Covariant,
-            FieldLabel::Load => Variance::Covariant,
+            FieldLabel::DerefPattern { .. } | FieldLabel::Load => Variance::Covariant,
             FieldLabel::Store => Variance::Contravariant,
         }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+/// A field label interned in the `FieldLabelPool`. Equal labels get the
+/// same id, so ids compare and hash by identity, in a pointer's time and
+/// space. They order like their labels.
+#[derive(Clone, Copy)]
+pub struct FieldLabelId(&'static FieldLabel);
+
+impl FieldLabelId {
+    /// The id of `label`, interned first if the pool does not have it yet.
+    pub fn new(label: FieldLabel) -> Self {
+        if let Some(id) = FieldLabelPool::global().read().unwrap().get(&label) {
+            return id;
+        }
+        FieldLabelPool::global().write().unwrap().intern(label)
+    }
+    pub fn get(self) -> &'static FieldLabel {
+        self.0
+    }
+}
+
+impl Deref for FieldLabelId {
+    type Target = FieldLabel;
+    fn deref(&self) -> &FieldLabel {
+        self.0
+    }
+}
+
+impl From<FieldLabel> for FieldLabelId {
+    fn from(label: FieldLabel) -> Self {
+        FieldLabelId::new(label)
+    }
+}
+
+impl PartialEq for FieldLabelId {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for FieldLabelId {}
+
+impl PartialEq<FieldLabel> for FieldLabelId {
+    fn eq(&self, other: &FieldLabel) -> bool {
+        self.0 == other
+    }
+}
+
+impl Hash for FieldLabelId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+impl Ord for FieldLabelId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if self == other {
+            Ordering::Equal
+        } else {
+            self.0.cmp(other.0)
+        }
+    }
+}
+
+impl PartialOrd for FieldLabelId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for FieldLabelId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Debug for FieldLabelId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The arena of the distinct field labels of the process. Large programs
+/// repeat the same few labels millions of times, so variables hold their
+/// ids instead of copies. A label is never freed once interned.
+///
+/// There is only the global pool, so that two ids are equal exactly when
+/// their labels are.
+pub struct FieldLabelPool {
+    ids: HashMap<FieldLabel, FieldLabelId>,
+}
+
+impl FieldLabelPool {
+    pub fn global() -> &'static RwLock<FieldLabelPool> {
+        static POOL: LazyLock<RwLock<FieldLabelPool>> = LazyLock::new(|| {
+            RwLock::new(FieldLabelPool {
+                ids: HashMap::new(),
+            })
+        });
+        &POOL
+    }
+    pub fn intern(&mut self, label: FieldLabel) -> FieldLabelId {
+        if let Some(id) = self.ids.get(&label) {
+            return *id;
+        }
+        let id = FieldLabelId(Box::leak(Box::new(label.clone())));
+        self.ids.insert(label, id);
+        id
+    }
+    /// The id of `label`, if it was interned.
+    pub fn get(&self, label: &FieldLabel) -> Option<FieldLabelId> {
+        self.ids.get(label).copied()
+    }
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub enum Bound {
     Fixed(u32),
     NullTerm,
@@ -103,8 +428,29 @@ impl fmt::Display for Bound {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Bound::Fixed(i) => write!(f, "*[{}]", i),
-            Bound::NullTerm => write!(f, "nullterm"),
-            Bound::NoBound => write!(f, "nobound"),
+            Bound::NullTerm => write!(f, "*[nullterm]"),
+            Bound::NoBound => write!(f, "*[nobound]"),
+        }
+    }
+}
+
+impl Bound {
+    /// Combine two bounds known for the same field, keeping the more
+    /// precise one: a fixed size over a null terminator over no bound.
+    /// Of two fixed sizes the larger one is kept.
+    pub fn meet(a: &Option<Bound>, b: &Option<Bound>) -> Option<Bound> {
+        fn precision(b: &Option<Bound>) -> u8 {
+            match b {
+                None => 0,
+                Some(Bound::NoBound) => 1,
+                Some(Bound::NullTerm) => 2,
+                Some(Bound::Fixed(_)) => 3,
+            }
+        }
+        match (a, b) {
+            (Some(Bound::Fixed(x)), Some(Bound::Fixed(y))) => Some(Bound::Fixed(*x.max(y))),
+            _ if precision(a) >= precision(b) => a.clone(),
+            _ => b.clone(),
         }
     }
 }
@@ -115,19 +461,27 @@ impl Debug for Bound {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+/// The name and the fields are shared between clones, which only bump a
+/// reference count. Changing the fields of a shared variable copies them
+/// first, see `fields_mut`.
+#[derive(PartialEq, Eq, Hash, Clone, PartialOrd, Ord)]
 pub struct DerivedTypeVariable {
-    pub name: String,
-    // TODO refactor to a Field label pool
-    pub fields: Vec<FieldLabel>,
+    pub name: Arc<str>,
+    /// the labels, interned in the `FieldLabelPool`.
+    pub fields: Arc<Vec<FieldLabelId>>,
+    /// the variance of the graph node, when written explicitly as `x.⊖`.
+    pub variance: Option<Variance>,
 }
 
 impl fmt::Display for DerivedTypeVariable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.name)?;
-        for field in &self.fields {
+        for field in self.fields.iter() {
             write!(f, ".{}", field)?;
         }
+        if let Some(variance) = &self.variance {
+            write!(f, ".{}", variance)?;
+        }
         Ok(())
     }
 }
@@ -138,26 +492,296 @@ impl Debug for DerivedTypeVariable {
     }
 }
 
+/// Short name for building variables in code:
+/// `Dtv::new("x").field(FieldLabel::Load)` is `x.load`.
+pub type Dtv = DerivedTypeVariable;
+
 impl DerivedTypeVariable {
+    /// The base variable `name`, without fields.
+    pub fn new(name: &str) -> Self {
+        DerivedTypeVariable {
+            name: name.into(),
+            fields: Arc::default(),
+            variance: None,
+        }
+    }
+    /// Append `label` to the fields.
+    pub fn field(mut self, label: impl Into<FieldLabelId>) -> Self {
+        self.fields_mut().push(label.into());
+        self
+    }
+    /// The fields to change, copied first if another clone shares them.
+    pub fn fields_mut(&mut self) -> &mut Vec<FieldLabelId> {
+        Arc::make_mut(&mut self.fields)
+    }
     pub fn get_sub_dtv(&self, index: usize) -> DerivedTypeVariable {
         DerivedTypeVariable {
             name: self.name.clone(),
-            fields: self.fields[..index].to_vec(),
+            fields: Arc::new(self.fields[..index].to_vec()),
+            variance: None,
         }
     }
-    pub fn path_variance(&self) -> Variance {
+    /// The top element of the type lattice, `⊤`.
+    pub fn is_top(&self) -> bool {
+        self.fields.is_empty() && &*self.name == TOP
+    }
+    /// The bottom element of the type lattice, `⊥`.
+    pub fn is_bottom(&self) -> bool {
+        self.fields.is_empty() && &*self.name == BOTTOM
+    }
+    /// Whether the base is a type constant, a fixed element of the type
+    /// lattice rather than a variable to infer. Constants are named with a
+    /// leading `_`, like `_FileDescriptor`.
+    pub fn is_type_constant(&self) -> bool {
+        self.name.starts_with('_')
+    }
+    /// The variable without its variance marker, and the marked variance,
+    /// covariant when there is none.
+    pub fn split_variance(&self) -> (DerivedTypeVariable, Variance) {
+        let mut dtv = self.clone();
+        let variance = dtv.variance.take().unwrap_or(Variance::Covariant);
+        (dtv, variance)
+    }
+    pub fn path_variance(&self, table: &VarianceTable) -> Variance {
         let mut variance = Variance::Covariant;
-        for field in &self.fields {
-            variance = variance.combine(&field.variance());
+        for field in self.fields.iter() {
+            variance = variance.combine(&field.variance_with(table));
         }
         variance
     }
+    /// The variance of the field path with the default label variances,
+    /// covariant when there are no fields.
+    pub fn suffix_variance(&self) -> Variance {
+        self.path_variance(&VarianceTable::default())
+    }
+    /// The variable one label longer, without a variance marker.
+    pub fn with_field(&self, label: impl Into<FieldLabelId>) -> DerivedTypeVariable {
+        let mut fields = Vec::with_capacity(self.fields.len() + 1);
+        fields.extend(self.fields.iter().copied());
+        fields.push(label.into());
+        DerivedTypeVariable {
+            name: self.name.clone(),
+            fields: Arc::new(fields),
+            variance: None,
+        }
+    }
+    /// The variable with the labels after `prefix`, or `None` if the fields
+    /// do not start with `prefix`.
+    pub fn strip_prefix(&self, prefix: &[FieldLabelId]) -> Option<DerivedTypeVariable> {
+        let rest = self.fields.strip_prefix(prefix)?;
+        Some(DerivedTypeVariable {
+            name: self.name.clone(),
+            fields: Arc::new(rest.to_vec()),
+            variance: self.variance.clone(),
+        })
+    }
+    /// The variable without the bounds of its deref labels, to index
+    /// variables that only differ in them under one key. See
+    /// `FieldLabel::same_capability`.
+    pub fn capability_key(&self) -> DerivedTypeVariable {
+        let mut key = self.clone();
+        if !key
+            .fields
+            .iter()
+            .any(|label| matches!(**label, FieldLabel::DerefPattern { bound: Some(_), .. }))
+        {
+            return key;
+        }
+        for label in key.fields_mut() {
+            if let FieldLabel::DerefPattern { size, offset, .. } = **label {
+                *label = FieldLabelId::new(FieldLabel::DerefPattern {
+                    size,
+                    offset,
+                    bound: None,
+                });
+            }
+        }
+        key
+    }
+    /// The variable with each `store` right before a `load`, or the other
+    /// way around, removed: what is written through a pointer is what is
+    /// read back. See `normalize_with`.
+    pub fn normalize(&self) -> DerivedTypeVariable {
+        self.normalize_with(&PointerCapabilities::default(), &VarianceTable::default())
+    }
+    /// `normalize` with the pointer labels of `pointer`. A pair is only
+    /// cancelled when its two labels have opposite variances in `table`, so
+    /// that the read undoes the write. Pairs exposed by a cancellation, as in
+    /// `store.store.load.load`, are cancelled too.
+    pub fn normalize_with(
+        &self,
+        pointer: &PointerCapabilities,
+        table: &VarianceTable,
+    ) -> DerivedTypeVariable {
+        let mut fields: Vec<FieldLabelId> = Vec::new();
+        for label in self.fields.iter() {
+            let cancels = fields.last().is_some_and(|last| {
+                pointer
+                    .inverse(last)
+                    .is_some_and(|inverse| inverse.same_capability(label))
+                    && last.variance_with(table) != label.variance_with(table)
+            });
+            if cancels {
+                fields.pop();
+            } else {
+                fields.push(*label);
+            }
+        }
+        DerivedTypeVariable {
+            name: self.name.clone(),
+            fields: Arc::new(fields),
+            variance: self.variance.clone(),
+        }
+    }
+    /// Check the field sequence against the capability grammar: the value
+    /// behind a pointer is only reached through a `σN@k` field of it, so
+    /// `.load` and `.store` are last or followed by a deref label.
+    pub fn validate(&self) -> Result<(), WellFormednessError> {
+        for (i, pair) in self.fields.windows(2).enumerate() {
+            if !pair[0].is_access() || pair[1].is_field() {
+                continue;
+            }
+            let rule = if pair[1].is_io() {
+                FieldRule::CallAfterPointer
+            } else {
+                FieldRule::PointerAfterPointer
+            };
+            return Err(WellFormednessError {
+                dtv: self.clone(),
+                position: i + 1,
+                rule,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A rule of the capability grammar, see `DerivedTypeVariable::validate`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum FieldRule {
+    /// `in`/`out` right after `load`/`store`.
+    CallAfterPointer,
+    /// `load`/`store` right after `load`/`store`.
+    PointerAfterPointer,
+}
+
+impl fmt::Display for FieldRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldRule::CallAfterPointer => write!(
+                f,
+                "a pointed-to value must be read by a σ field before it is called"
+            ),
+            FieldRule::PointerAfterPointer => write!(
+                f,
+                "a pointed-to value must be read by a σ field before it is dereferenced"
+            ),
+        }
+    }
+}
+
+/// A derived type variable whose fields break a rule of the grammar.
+#[derive(PartialEq, Clone)]
+pub struct WellFormednessError {
+    pub dtv: DerivedTypeVariable,
+    /// index in `dtv.fields` of the label that breaks the rule.
+    pub position: usize,
+    pub rule: FieldRule,
+}
+
+impl fmt::Display for WellFormednessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ill-formed {}: field {} `{}` follows `{}`, {}",
+            self.dtv,
+            self.position,
+            self.dtv.fields[self.position],
+            self.dtv.fields[self.position - 1],
+            self.rule
+        )
+    }
+}
+
+impl Debug for WellFormednessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for WellFormednessError {}
+
+/// A procedure whose constraints mention another one that it does not call
+/// in the call graph, so the callee's scheme is never instantiated for it.
+#[derive(PartialEq, Eq, Clone, PartialOrd, Ord)]
+pub struct MissingCallEdge {
+    pub caller: String,
+    pub callee: String,
+}
+
+impl fmt::Display for MissingCallEdge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the constraints of {} mention {}, but there is no call edge from {} to {}",
+            self.caller, self.callee, self.caller, self.callee
+        )
+    }
 }
 
-#[derive(PartialEq)]
+impl Debug for MissingCallEdge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+#[derive(Clone)]
 pub struct Constraint {
     pub left: DerivedTypeVariable,
     pub right: DerivedTypeVariable,
+    /// where the constraint comes from in the analyzed program, like the
+    /// address of an instruction.
+    pub provenance: Option<String>,
+}
+
+impl Constraint {
+    pub fn new(left: DerivedTypeVariable, right: DerivedTypeVariable) -> Self {
+        Constraint {
+            left,
+            right,
+            provenance: None,
+        }
+    }
+    /// The constraint `left <= right`.
+    pub fn subtype(
+        left: impl Into<DerivedTypeVariable>,
+        right: impl Into<DerivedTypeVariable>,
+    ) -> Self {
+        Constraint::new(left.into(), right.into())
+    }
+}
+
+impl From<&str> for DerivedTypeVariable {
+    /// The base variable `name`, see `DerivedTypeVariable::new`.
+    fn from(name: &str) -> Self {
+        DerivedTypeVariable::new(name)
+    }
+}
+
+/// The provenance is not part of what the constraint says.
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Self) -> bool {
+        self.left == other.left && self.right == other.right
+    }
+}
+
+impl Eq for Constraint {}
+
+impl Hash for Constraint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.left.hash(state);
+        self.right.hash(state);
+    }
 }
 
 impl fmt::Display for Constraint {
@@ -172,13 +796,1053 @@ impl Debug for Constraint {
     }
 }
 
+/// The type scheme of a procedure: its simplified constraints, over its own
+/// variables, the type constants and the `globals`.
+#[derive(Default, PartialEq, Clone, Debug)]
+pub struct Scheme {
+    pub constraints: Vec<Constraint>,
+    /// the names every instance shares, like the procedure itself.
+    pub globals: HashSet<String>,
+}
+
+impl Scheme {
+    pub fn new(constraints: Vec<Constraint>) -> Self {
+        Scheme {
+            constraints,
+            globals: HashSet::new(),
+        }
+    }
+    pub fn with_globals(mut self, globals: HashSet<String>) -> Self {
+        self.globals = globals;
+        self
+    }
+    /// A copy of the constraints for one use of the scheme, such as a call
+    /// site, with `suffix` appended to every name but the type constants
+    /// and the globals, so that two instances do not alias.
+    pub fn instantiate(&self, suffix: &str) -> Vec<Constraint> {
+        let freshen = |dtv: &DerivedTypeVariable| {
+            let mut dtv = dtv.clone();
+            if !dtv.is_type_constant() && !self.globals.contains(&*dtv.name) {
+                dtv.name = format!("{}{}", dtv.name, suffix).into();
+            }
+            dtv
+        };
+        self.constraints
+            .iter()
+            .map(|c| Constraint {
+                left: freshen(&c.left),
+                right: freshen(&c.right),
+                provenance: c.provenance.clone(),
+            })
+            .collect()
+    }
+}
+
+/// The architectures whose programs are known, see `Program::language_enum`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Language {
+    X86,
+    X86_64,
+    Arm32,
+    Arm64,
+    Mips,
+    PowerPc,
+    Wasm,
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Language::X86 => "x86",
+            Language::X86_64 => "x86-64",
+            Language::Arm32 => "ARM32",
+            Language::Arm64 => "ARM64",
+            Language::Mips => "MIPS",
+            Language::PowerPc => "PowerPC",
+            Language::Wasm => "wasm",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Language {
+    type Err = UnknownLanguage;
+
+    /// The name is case insensitive, and the usual aliases are accepted,
+    /// like `amd64` or `aarch64`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "x86" | "x86-32" | "x86_32" | "i386" | "i686" => Ok(Language::X86),
+            "x86-64" | "x86_64" | "x64" | "amd64" => Ok(Language::X86_64),
+            "arm" | "arm32" => Ok(Language::Arm32),
+            "arm64" | "aarch64" => Ok(Language::Arm64),
+            "mips" | "mips32" => Ok(Language::Mips),
+            "powerpc" | "ppc" => Ok(Language::PowerPc),
+            "wasm" | "wasm32" => Ok(Language::Wasm),
+            _ => Err(UnknownLanguage(s.to_string())),
+        }
+    }
+}
+
+/// A `language` that is not one of `Language`.
+#[derive(PartialEq, Clone)]
+pub struct UnknownLanguage(pub String);
+
+impl fmt::Display for UnknownLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown language: {}", self.0)
+    }
+}
+
+impl Debug for UnknownLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for UnknownLanguage {}
+
+/// Why two programs cannot be merged, see `Program::merge`.
+#[derive(PartialEq, Clone)]
+pub enum MergeError {
+    /// they are for different languages.
+    Language { expected: String, found: String },
+    /// both have a lattice of atomic types, and the lattices differ.
+    Lattice,
+    /// both override the variance of some labels, and differently.
+    VarianceTable,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergeError::Language { expected, found } => {
+                write!(
+                    f,
+                    "cannot merge a program for {} into one for {}",
+                    found, expected
+                )
+            }
+            MergeError::Lattice => write!(f, "cannot merge programs with different type lattices"),
+            MergeError::VarianceTable => {
+                write!(f, "cannot merge programs with different variance tables")
+            }
+        }
+    }
+}
+
+impl Debug for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for MergeError {}
+
 pub struct Program {
     pub language: String,
-    // types: Lattice[DerivedTypeVariable],
+    /// the lattice of atomic types, if one is known.
+    pub types: Option<CTypeLattice>,
     /// types for global variables
     // global_vars: Iterable[MaybeVar],
     // TODO: save function name string space
     // initial constraints for each function.
     pub proc_constraints: HashMap<String, Vec<Constraint>>,
-    pub call_graph: DiGraph<String, ()>,
+    /// caller -> callee, weighted by the number of call sites.
+    pub call_graph: DiGraph<String, u32>,
+    /// the variance of the labels, where it differs from the default.
+    pub variance_table: VarianceTable,
+    /// lattice atoms known for some variables from outside the constraints,
+    /// e.g. from the signature of an API function.
+    pub type_hints: HashMap<DerivedTypeVariable, String>,
+    /// variables that shape inference must keep apart from each other, e.g.
+    /// distinct ABI slots, even where the constraints would unify them.
+    pub opaque: HashSet<String>,
+}
+
+impl Program {
+    pub fn new(
+        language: &str,
+        proc_constraints: HashMap<String, Vec<Constraint>>,
+        call_graph: DiGraph<String, u32>,
+    ) -> Self {
+        Program {
+            language: language.to_string(),
+            types: None,
+            proc_constraints,
+            call_graph,
+            variance_table: VarianceTable::default(),
+            type_hints: HashMap::new(),
+            opaque: HashSet::new(),
+        }
+    }
+    /// The language parsed as a `Language`, which not every frontend's name
+    /// is.
+    pub fn language_enum(&self) -> Result<Language, UnknownLanguage> {
+        self.language.parse()
+    }
+    /// Add the procedures and calls of `other`. The constraints of a
+    /// procedure in both are concatenated, and the call sites of a call in
+    /// both are added up. The lattice and the variance table of `other` are
+    /// taken when `self` has none, and must be the same otherwise. The hints
+    /// of `self` are kept for variables hinted in both.
+    pub fn merge(&mut self, other: Program) -> Result<(), MergeError> {
+        if self.language != other.language {
+            return Err(MergeError::Language {
+                expected: self.language.clone(),
+                found: other.language,
+            });
+        }
+        match (&self.types, other.types) {
+            (None, types) => self.types = types,
+            (Some(ours), Some(theirs)) if *ours != theirs => return Err(MergeError::Lattice),
+            _ => {}
+        }
+        if self.variance_table.is_empty() {
+            self.variance_table = other.variance_table;
+        } else if !other.variance_table.is_empty() && self.variance_table != other.variance_table {
+            return Err(MergeError::VarianceTable);
+        }
+        for (proc, constraints) in other.proc_constraints {
+            self.proc_constraints
+                .entry(proc)
+                .or_default()
+                .extend(constraints);
+        }
+        for (dtv, atom) in other.type_hints {
+            self.type_hints.entry(dtv).or_insert(atom);
+        }
+        self.opaque.extend(other.opaque);
+        let mut nodes: HashMap<String, NodeIndex> = self
+            .call_graph
+            .node_indices()
+            .map(|ind| (self.call_graph[ind].clone(), ind))
+            .collect();
+        let mut node = |graph: &mut DiGraph<String, u32>, name: &String| {
+            *nodes
+                .entry(name.clone())
+                .or_insert_with(|| graph.add_node(name.clone()))
+        };
+        for ind in other.call_graph.node_indices() {
+            node(&mut self.call_graph, &other.call_graph[ind]);
+        }
+        for edge in other.call_graph.edge_references() {
+            let caller = node(&mut self.call_graph, &other.call_graph[edge.source()]);
+            let callee = node(&mut self.call_graph, &other.call_graph[edge.target()]);
+            match self.call_graph.find_edge(caller, callee) {
+                Some(existing) => self.call_graph[existing] += *edge.weight(),
+                None => {
+                    self.call_graph.add_edge(caller, callee, *edge.weight());
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Normalize both sides of every constraint, see
+    /// `DerivedTypeVariable::normalize_with`, with the program's variance
+    /// table. Returns the number of variables changed.
+    pub fn normalize(&mut self, pointer: &PointerCapabilities) -> usize {
+        let mut changed = 0;
+        for c in self.proc_constraints.values_mut().flatten() {
+            for dtv in [&mut c.left, &mut c.right] {
+                let normalized = dtv.normalize_with(pointer, &self.variance_table);
+                if normalized != *dtv {
+                    *dtv = normalized;
+                    changed += 1;
+                }
+            }
+        }
+        changed
+    }
+    /// Rename the base variables in the constraints, the type hints, the
+    /// opaque variables, the procedures and the call graph nodes by
+    /// `mapping`, keeping the fields. Names not in the
+    /// mapping are kept.
+    pub fn rename_vars(&mut self, mapping: &HashMap<String, String>) {
+        let rename = |name: &mut String| {
+            if let Some(new) = mapping.get(name) {
+                *name = new.clone();
+            }
+        };
+        let rename_base = |name: &mut Arc<str>| {
+            if let Some(new) = mapping.get(&**name) {
+                *name = new.as_str().into();
+            }
+        };
+        self.proc_constraints = std::mem::take(&mut self.proc_constraints)
+            .into_iter()
+            .map(|(mut proc, mut constraints)| {
+                rename(&mut proc);
+                for c in &mut constraints {
+                    rename_base(&mut c.left.name);
+                    rename_base(&mut c.right.name);
+                }
+                (proc, constraints)
+            })
+            .collect();
+        self.type_hints = std::mem::take(&mut self.type_hints)
+            .into_iter()
+            .map(|(mut dtv, atom)| {
+                rename_base(&mut dtv.name);
+                (dtv, atom)
+            })
+            .collect();
+        self.opaque = std::mem::take(&mut self.opaque)
+            .into_iter()
+            .map(|mut name| {
+                rename(&mut name);
+                name
+            })
+            .collect();
+        for name in self.call_graph.node_weights_mut() {
+            rename(name);
+        }
+    }
+    /// Remove repeated constraints of each procedure, keeping the first
+    /// occurrence. Returns the number of constraints removed.
+    pub fn dedup_constraints(&mut self) -> usize {
+        let mut dropped = 0;
+        for cons in self.proc_constraints.values_mut() {
+            let mut seen = HashSet::new();
+            let before = cons.len();
+            cons.retain(|c| seen.insert(c.clone()));
+            dropped += before - cons.len();
+        }
+        dropped
+    }
+    /// The ill-formed variables in the constraints of each procedure, see
+    /// `DerivedTypeVariable::validate`, sorted by procedure.
+    pub fn validate(&self) -> Vec<(String, WellFormednessError)> {
+        let mut errors: Vec<(String, WellFormednessError)> = self
+            .proc_constraints
+            .iter()
+            .flat_map(|(proc, cs)| {
+                cs.iter()
+                    .flat_map(|c| [&c.left, &c.right])
+                    .filter_map(|dtv| dtv.validate().err())
+                    .map(move |e| (proc.clone(), e))
+            })
+            .collect();
+        errors.sort_by(|a, b| (&a.0, &a.1.dtv).cmp(&(&b.0, &b.1.dtv)));
+        errors
+    }
+    /// The procedures whose constraints mention another procedure, one with
+    /// constraints or in the call graph, without calling it, sorted.
+    pub fn validate_references(&self) -> Vec<MissingCallEdge> {
+        let procs: HashMap<&str, NodeIndex> = self
+            .call_graph
+            .node_indices()
+            .map(|ind| (self.call_graph[ind].as_str(), ind))
+            .collect();
+        let calls = |caller: &str, callee: &str| match (procs.get(caller), procs.get(callee)) {
+            (Some(caller), Some(callee)) => self.call_graph.contains_edge(*caller, *callee),
+            _ => false,
+        };
+        let mut missing = Vec::new();
+        for (caller, cs) in &self.proc_constraints {
+            for callee in cs.iter().flat_map(|c| [&c.left.name, &c.right.name]) {
+                let is_proc =
+                    self.proc_constraints.contains_key(&**callee) || procs.contains_key(&**callee);
+                if **callee != **caller && is_proc && !calls(caller, callee) {
+                    missing.push(MissingCallEdge {
+                        caller: caller.clone(),
+                        callee: callee.to_string(),
+                    });
+                }
+            }
+        }
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+    /// Every field label used in the constraints of the program. Deref labels
+    /// are listed without their bound, so labels that differ only in the
+    /// bound appear once.
+    pub fn capabilities(&self) -> HashSet<FieldLabel> {
+        self.proc_constraints
+            .values()
+            .flatten()
+            .flat_map(|c| c.left.fields.iter().chain(c.right.fields.iter()))
+            .map(|label| match label.get() {
+                FieldLabel::DerefPattern { size, offset, .. } => FieldLabel::DerefPattern {
+                    size: *size,
+                    offset: *offset,
+                    bound: None,
+                },
+                label => label.clone(),
+            })
+            .collect()
+    }
+    /// The groups of mutually recursive procedures: the strongly connected
+    /// components of the call graph with more than one procedure, or with
+    /// one that calls itself. Each group is sorted, and so is the list.
+    pub fn recursive_groups(&self) -> Vec<Vec<String>> {
+        let calls_itself: HashSet<&String> = self
+            .call_graph
+            .node_indices()
+            .filter(|ind| self.call_graph.contains_edge(*ind, *ind))
+            .map(|ind| &self.call_graph[ind])
+            .collect();
+        let sccs = condensation(self.call_graph.clone(), true);
+        let mut groups: Vec<Vec<String>> = sccs
+            .node_weights()
+            .filter(|scc| scc.len() > 1 || calls_itself.contains(&scc[0]))
+            .map(|scc| {
+                let mut scc = scc.clone();
+                scc.sort();
+                scc
+            })
+            .collect();
+        groups.sort();
+        groups
+    }
+    /// The base variables of `proc` that its type scheme should be expressed
+    /// over: the procedure itself (its parameters and return value), the
+    /// procedures it calls or is called by, and the names that other
+    /// procedures' constraints mention too, which are the globals and the
+    /// type constants.
+    pub fn interesting_vars(&self, proc: &str) -> HashSet<String> {
+        let bases = |cs: &Vec<Constraint>| -> HashSet<String> {
+            cs.iter()
+                .flat_map(|c| [c.left.name.to_string(), c.right.name.to_string()])
+                .collect()
+        };
+        let own = match self.proc_constraints.get(proc) {
+            Some(cs) => bases(cs),
+            None => return HashSet::new(),
+        };
+        let mut shared: HashSet<String> = HashSet::new();
+        if let Some(ind) = self
+            .call_graph
+            .node_indices()
+            .find(|ind| self.call_graph[*ind] == proc)
+        {
+            shared.extend(
+                self.call_graph
+                    .neighbors_undirected(ind)
+                    .map(|n| self.call_graph[n].clone()),
+            );
+        }
+        for (other, cs) in &self.proc_constraints {
+            if other != proc {
+                shared.extend(bases(cs));
+            }
+        }
+        own.into_iter()
+            .filter(|name| name == proc || shared.contains(name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Bound, Constraint, DerivedTypeVariable, Dtv, FieldLabel, FieldLabelId, FieldLabelPool,
+        FieldRule, Language, MergeError, MissingCallEdge, PointerCapabilities, Program, Scheme,
+        UnknownLanguage, Variance, VarianceTable,
+    };
+    use crate::lattice::{CTypeLattice, Lattice};
+    use crate::parser::{constraints_from_json, parse_constraint, parse_derived_type_variable};
+    use petgraph::{graph::DiGraph, visit::EdgeRef};
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
+
+    fn dtv(s: &str) -> DerivedTypeVariable {
+        parse_derived_type_variable(s).unwrap().1
+    }
+
+    #[test]
+    fn test_variance_table() {
+        let x = dtv("x.load.σ4@8");
+        let mut table = VarianceTable::default();
+        assert_eq!(x.path_variance(&table), Variance::Covariant);
+        table.set(
+            dtv("x.σ4@8").fields[0].get().clone(),
+            Variance::Contravariant,
+        );
+        assert_eq!(x.path_variance(&table), Variance::Contravariant);
+        // the bound does not matter, other offsets are not affected.
+        assert_eq!(
+            dtv("x.load.σ4@8*[4]").path_variance(&table),
+            Variance::Contravariant
+        );
+        assert_eq!(
+            dtv("x.load.σ4@4").path_variance(&table),
+            Variance::Covariant
+        );
+        // in.σ4@8 is flipped twice
+        assert_eq!(
+            dtv("x.in_0.σ4@8").path_variance(&table),
+            Variance::Covariant
+        );
+    }
+
+    #[test]
+    fn test_dtv_field_ops() {
+        let x = dtv("x.load.σ4@8");
+        assert_eq!(dtv("x").with_field(FieldLabel::Load), dtv("x.load"));
+        assert_eq!(dtv("x.⊖").with_field(FieldLabel::Load), dtv("x.load"));
+        assert_eq!(x.with_field(FieldLabel::Store), dtv("x.load.σ4@8.store"));
+
+        assert_eq!(x.strip_prefix(&[]), Some(x.clone()));
+        assert_eq!(x.strip_prefix(&dtv("y.load").fields), Some(dtv("x.σ4@8")));
+        assert_eq!(x.strip_prefix(&x.fields), Some(dtv("x")));
+        assert_eq!(x.strip_prefix(&dtv("x.store").fields), None);
+        assert_eq!(dtv("x").strip_prefix(&dtv("x.load").fields), None);
+
+        assert_eq!(dtv("x").suffix_variance(), Variance::Covariant);
+        assert_eq!(x.suffix_variance(), Variance::Covariant);
+        assert_eq!(dtv("x.store").suffix_variance(), Variance::Contravariant);
+        assert_eq!(dtv("x.in_0.store").suffix_variance(), Variance::Covariant);
+    }
+
+    #[test]
+    fn test_dtv_sharing() {
+        let x = dtv("x.load.σ4@8");
+        let mut y = x.clone();
+        assert!(Arc::ptr_eq(&x.name, &y.name));
+        assert!(Arc::ptr_eq(&x.fields, &y.fields));
+        assert_eq!(
+            HashSet::from([x.clone()]).len(),
+            HashSet::from([x.clone(), y.clone()]).len()
+        );
+
+        // changing a shared variable copies its fields and leaves the original alone.
+        y.fields_mut().push(FieldLabel::Store.into());
+        assert!(!Arc::ptr_eq(&x.fields, &y.fields));
+        assert_eq!(x, dtv("x.load.σ4@8"));
+        assert_eq!(y, dtv("x.load.σ4@8.store"));
+        assert_eq!(y.get_sub_dtv(2), x);
+    }
+
+    #[test]
+    fn test_field_label_pool() {
+        let load = FieldLabelId::new(FieldLabel::Load);
+        assert_eq!(FieldLabelId::from(FieldLabel::Load), load);
+        assert_ne!(FieldLabelId::new(FieldLabel::Store), load);
+        assert_eq!(load, FieldLabel::Load);
+        assert_eq!(load.to_string(), "load");
+        assert_eq!(
+            FieldLabelPool::global()
+                .read()
+                .unwrap()
+                .get(&FieldLabel::Load),
+            Some(load)
+        );
+
+        // variables parsed apart share the ids of their labels.
+        let x = dtv("x.load.σ4@8.store");
+        let y = dtv("y.load.σ4@8");
+        assert_eq!(x.fields[..2], y.fields[..]);
+        assert!(std::ptr::eq(x.fields[1].get(), y.fields[1].get()));
+        assert_eq!(x.get_sub_dtv(2).to_string(), "x.load.σ4@8");
+        assert_eq!(
+            Dtv::new("y")
+                .field(FieldLabel::Load)
+                .with_field(y.fields[1]),
+            y
+        );
+
+        // the labels interned from several threads get the same id.
+        let ids: Vec<FieldLabelId> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope
+                        .spawn(|| FieldLabelId::new(FieldLabel::InPattern("pool_test".to_string())))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(ids.iter().all(|id| *id == ids[0]));
+    }
+
+    #[test]
+    fn test_capability_key() {
+        let bounded = dtv("p.σ4@0*[8].load.σ1@2*[nullterm]");
+        assert_eq!(bounded.capability_key(), dtv("p.σ4@0.load.σ1@2"));
+        assert_eq!(bounded.to_string(), "p.σ4@0*[8].load.σ1@2*[nullterm]");
+        assert_eq!(
+            dtv("p.σ4@0").capability_key(),
+            dtv("p.σ4@0*[nobound]").capability_key()
+        );
+        assert_ne!(
+            dtv("p.σ4@0").capability_key(),
+            dtv("p.σ4@4").capability_key()
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(dtv("p.store.load").normalize(), dtv("p"));
+        assert_eq!(dtv("p.load.store.σ4@0").normalize(), dtv("p.σ4@0"));
+        assert_eq!(
+            dtv("p.in_0.store.store.load.load.⊖").normalize(),
+            dtv("p.in_0.⊖")
+        );
+        // nothing to cancel.
+        for s in [
+            "p.load.load",
+            "p.store.σ4@0.load",
+            "p.load.σ4@0.store",
+            "p.in_0.out_0",
+        ] {
+            assert_eq!(dtv(s).normalize(), dtv(s));
+        }
+        // nor when the two have the same variance.
+        let mut table = VarianceTable::default();
+        table.set(FieldLabel::Store, Variance::Covariant);
+        let pointer = PointerCapabilities::default();
+        assert_eq!(
+            dtv("p.store.load").normalize_with(&pointer, &table),
+            dtv("p.store.load")
+        );
+
+        let c = parse_constraint("p.store.load <= q.load.store").unwrap().1;
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), vec![c])]),
+            DiGraph::new(),
+        );
+        assert_eq!(program.normalize(&pointer), 2);
+        assert_eq!(
+            program.proc_constraints["f"],
+            [parse_constraint("p <= q").unwrap().1]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(dtv("x").validate().is_ok());
+        assert!(dtv("f.in_0.load.σ4@0.store.σ4@8").validate().is_ok());
+        assert!(dtv("f.out.in_0").validate().is_ok());
+        let err = dtv("x.load.in_0").validate().unwrap_err();
+        assert_eq!(err.position, 1);
+        assert_eq!(err.rule, FieldRule::CallAfterPointer);
+        assert!(
+            err.to_string()
+                .starts_with("ill-formed x.load.in_0: field 1 `in_0` follows `load`"),
+            "{}",
+            err
+        );
+        let err = dtv("x.in_0.store.load").validate().unwrap_err();
+        assert_eq!(
+            (err.position, err.rule),
+            (2, FieldRule::PointerAfterPointer)
+        );
+
+        let program = constraints_from_json("tests/retypd-constrains.json").unwrap();
+        assert!(program.validate().is_empty());
+        let cs = ["x.load.in_0 <= y", "y <= z"]
+            .iter()
+            .map(|c| parse_constraint(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), cs)]),
+            DiGraph::new(),
+        );
+        let errors = program.validate();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, "f");
+    }
+
+    #[test]
+    fn test_builder() {
+        let deref = FieldLabel::DerefPattern {
+            size: 4,
+            offset: -8,
+            bound: Some(Bound::NullTerm),
+        };
+        let x = Dtv::new("x").field(FieldLabel::Load).field(deref);
+        assert_eq!(x, dtv("x.load.σ4@-8*[nullterm]"));
+        assert_eq!(x.to_string(), "x.load.σ4@-8*[nullterm]");
+        let f = Dtv::new("f").field(FieldLabel::InPattern("stack0".to_string()));
+        let c = Constraint::subtype(f, "y");
+        assert_eq!(c, parse_constraint("f.in_stack0 <= y").unwrap().1);
+        assert_eq!(
+            Constraint::subtype("_A", x.clone()).to_string(),
+            format!("_A <= {}", x)
+        );
+        assert_eq!(Dtv::new("x"), dtv("x"));
+    }
+
+    #[test]
+    fn test_dedup_constraints() {
+        let cs = ["y <= p", "p <= x", "y <= p", "y <= p"]
+            .iter()
+            .map(|c| parse_constraint(c).unwrap().1)
+            .collect();
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), cs)]),
+            DiGraph::new(),
+        );
+        assert_eq!(program.dedup_constraints(), 2);
+        let cs: Vec<String> = program.proc_constraints["f"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(cs, ["y <= p", "p <= x"]);
+        assert_eq!(program.dedup_constraints(), 0);
+    }
+
+    #[test]
+    fn test_interesting_vars() {
+        let cs = |cs: &[&str]| -> Vec<Constraint> {
+            cs.iter().map(|c| parse_constraint(c).unwrap().1).collect()
+        };
+        let f = cs(&[
+            "F.in_stack0 <= 𝛿",
+            "𝛿 <= 𝜑",
+            "𝜑.load.σ4@4 <= 𝛼'",
+            "𝛼' <= close.in_stack0",
+            "close.out_eax <= F.out_eax",
+            "g <= 𝜑",
+        ]);
+        let close = cs(&["close.in_stack0 <= _FileDescriptor", "g <= x"]);
+        let mut call_graph = DiGraph::new();
+        let f_ind = call_graph.add_node("F".to_string());
+        let close_ind = call_graph.add_node("close".to_string());
+        call_graph.add_edge(f_ind, close_ind, 1);
+        let program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), f), ("close".to_string(), close)]),
+            call_graph,
+        );
+        let expected =
+            |names: &[&str]| -> HashSet<String> { names.iter().map(|n| n.to_string()).collect() };
+        assert_eq!(
+            program.interesting_vars("F"),
+            expected(&["F", "close", "g"])
+        );
+        assert_eq!(program.interesting_vars("close"), expected(&["close", "g"]));
+        assert!(program.interesting_vars("no_such_proc").is_empty());
+    }
+
+    /// A program for x86 where `caller` calls `callee` from `sites` sites.
+    fn calling(caller: &str, callee: &str, sites: u32) -> Program {
+        let mut call_graph = DiGraph::new();
+        let caller = call_graph.add_node(caller.to_string());
+        let callee = call_graph.add_node(callee.to_string());
+        call_graph.add_edge(caller, callee, sites);
+        Program::new("x86", HashMap::new(), call_graph)
+    }
+
+    #[test]
+    fn test_merge_lattice() {
+        let lattice = CTypeLattice::new(&["int", "num"], &[("int", "num")]);
+        let mut program = calling("f", "g", 1);
+        let mut other = calling("f", "g", 1);
+        other.types = Some(lattice.clone());
+        program.merge(other).unwrap();
+        assert!(program
+            .types
+            .as_ref()
+            .unwrap()
+            .leq(&"int".to_string(), &"num".to_string()));
+
+        // the same lattice, declared in another order, merges.
+        let mut other = calling("f", "g", 1);
+        other.types = Some(CTypeLattice::new(&["num", "int"], &[("int", "num")]));
+        program.merge(other).unwrap();
+        let mut other = calling("f", "g", 1);
+        other.types = Some(CTypeLattice::new(&["int", "num"], &[]));
+        assert!(matches!(program.merge(other), Err(MergeError::Lattice)));
+        // a program without a lattice keeps the one merged in.
+        program.merge(calling("f", "g", 1)).unwrap();
+        assert!(program.types == Some(lattice));
+    }
+
+    #[test]
+    fn test_merge_variance_table() {
+        let mut table = VarianceTable::default();
+        table.set(
+            FieldLabel::InPattern("stack4".to_string()),
+            Variance::Covariant,
+        );
+        let mut program = calling("f", "g", 1);
+        let mut other = calling("f", "g", 1);
+        other.variance_table = table.clone();
+        program.merge(other).unwrap();
+        assert!(program.variance_table == table);
+
+        let mut other = calling("f", "g", 1);
+        other.variance_table.set(
+            FieldLabel::InPattern("stack4".to_string()),
+            Variance::Contravariant,
+        );
+        assert!(matches!(
+            program.merge(other),
+            Err(MergeError::VarianceTable)
+        ));
+    }
+
+    #[test]
+    fn test_merge_call_sites() {
+        let mut program = calling("f", "g", 2);
+        program.merge(calling("f", "g", 3)).unwrap();
+        program.merge(calling("g", "f", 1)).unwrap();
+        let g = &program.call_graph;
+        assert_eq!(g.node_count(), 2);
+        let mut sites: Vec<(&str, &str, u32)> = g
+            .edge_references()
+            .map(|e| (g[e.source()].as_str(), g[e.target()].as_str(), *e.weight()))
+            .collect();
+        sites.sort();
+        assert_eq!(sites, [("f", "g", 5), ("g", "f", 1)]);
+    }
+
+    #[test]
+    fn test_rename_vars() {
+        let cs = |cs: &[&str]| -> Vec<Constraint> {
+            cs.iter().map(|c| parse_constraint(c).unwrap().1).collect()
+        };
+        let f = cs(&[
+            "F.in_stack0 <= 𝛿",
+            "𝛿.load.σ4@4 <= close.in_stack0",
+            "close.out_eax <= F.out_eax",
+        ]);
+        let close = cs(&["close.in_stack0 <= _FileDescriptor"]);
+        let mut call_graph = DiGraph::new();
+        let f_ind = call_graph.add_node("F".to_string());
+        let close_ind = call_graph.add_node("close".to_string());
+        call_graph.add_edge(f_ind, close_ind, 1);
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), f), ("close".to_string(), close)]),
+            call_graph,
+        );
+        program.rename_vars(&HashMap::from([("F".to_string(), "main".to_string())]));
+
+        let mut procs: Vec<&String> = program.proc_constraints.keys().collect();
+        procs.sort();
+        assert_eq!(procs, ["close", "main"]);
+        let main: Vec<String> = program.proc_constraints["main"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            main,
+            [
+                "main.in_stack0 <= 𝛿",
+                "𝛿.load.σ4@4 <= close.in_stack0",
+                "close.out_eax <= main.out_eax",
+            ]
+        );
+        assert_eq!(
+            program.proc_constraints["close"][0].to_string(),
+            "close.in_stack0 <= _FileDescriptor"
+        );
+        let calls: Vec<(&str, &str)> = program
+            .call_graph
+            .edge_references()
+            .map(|e| {
+                (
+                    &program.call_graph[e.source()][..],
+                    &program.call_graph[e.target()][..],
+                )
+            })
+            .collect();
+        assert_eq!(calls, [("main", "close")]);
+        assert!(program
+            .call_graph
+            .node_weights()
+            .all(|proc| program.proc_constraints.contains_key(proc)));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let mut caps: Vec<String> = program
+            .capabilities()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        caps.sort();
+        assert_eq!(
+            caps,
+            [
+                "in_0", "in_1", "load", "out", "store", "σ4@-12", "σ4@-16", "σ4@-4", "σ4@-8",
+                "σ4@0"
+            ]
+        );
+
+        let cs = ["x.σ4@0*[8] <= y", "x.σ4@0 <= z.σ4@0*[nullterm]"]
+            .iter()
+            .map(|c| parse_constraint(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), cs)]),
+            DiGraph::new(),
+        );
+        assert_eq!(
+            program.capabilities(),
+            HashSet::from([dtv("x.σ4@0").fields[0].get().clone()])
+        );
+    }
+
+    #[test]
+    fn test_recursive_groups() {
+        let mut call_graph = DiGraph::new();
+        let [f, g, h, r] = ["f", "g", "h", "r"].map(|p| call_graph.add_node(p.to_string()));
+        call_graph.add_edge(f, g, 1);
+        call_graph.add_edge(g, f, 1);
+        call_graph.add_edge(g, h, 1);
+        let program = Program::new("x86", HashMap::new(), call_graph.clone());
+        assert_eq!(program.recursive_groups(), [["f", "g"]]);
+        // a procedure calling itself is a group of its own.
+        call_graph.add_edge(r, r, 1);
+        let program = Program::new("x86", HashMap::new(), call_graph);
+        assert_eq!(program.recursive_groups(), [vec!["f", "g"], vec!["r"]]);
+    }
+
+    #[test]
+    fn test_scheme_instantiate() {
+        let scheme = Scheme::new(vec![
+            Constraint::new(dtv("f.in_0"), dtv("x")),
+            Constraint::new(dtv("x"), dtv("f.out")),
+            Constraint::new(dtv("x.load"), dtv("_FileDescriptor")),
+            Constraint::new(dtv("g.out"), dtv("x")),
+        ])
+        .with_globals(HashSet::from(["g".to_string()]));
+        let names = |constraints: &[Constraint]| -> HashSet<String> {
+            constraints
+                .iter()
+                .flat_map(|c| [c.left.name.to_string(), c.right.name.to_string()])
+                .collect()
+        };
+        let first = scheme.instantiate("@1");
+        let second = scheme.instantiate("@2");
+        assert_eq!(first[2].to_string(), "x@1.load <= _FileDescriptor");
+        assert_eq!(first[0].to_string(), "f@1.in_0 <= x@1");
+        let shared: HashSet<String> = names(&first)
+            .intersection(&names(&second))
+            .cloned()
+            .collect();
+        assert_eq!(
+            shared,
+            HashSet::from(["_FileDescriptor".to_string(), "g".to_string()])
+        );
+        assert_eq!(first.len(), scheme.constraints.len());
+    }
+
+    #[test]
+    fn test_validate_references() {
+        let constraints = |cs: &[(&str, &str)]| {
+            cs.iter()
+                .map(|(l, r)| Constraint::new(dtv(l), dtv(r)))
+                .collect()
+        };
+        let procs = HashMap::from([
+            (
+                "F".to_string(),
+                constraints(&[
+                    ("F.in_stack0", "x"),
+                    ("x", "close.in_stack0"),
+                    ("close.out_eax", "F.out"),
+                ]),
+            ),
+            (
+                "close".to_string(),
+                constraints(&[("close.in_stack0", "_FileDescriptor")]),
+            ),
+        ]);
+        let mut call_graph = DiGraph::new();
+        let f = call_graph.add_node("F".to_string());
+        let close = call_graph.add_node("close".to_string());
+        let program = Program::new("x86", procs.clone(), call_graph.clone());
+        let missing = program.validate_references();
+        assert_eq!(
+            missing,
+            [MissingCallEdge {
+                caller: "F".to_string(),
+                callee: "close".to_string(),
+            }]
+        );
+        assert_eq!(
+            missing[0].to_string(),
+            "the constraints of F mention close, but there is no call edge from F to close"
+        );
+        call_graph.add_edge(f, close, 1);
+        assert!(Program::new("x86", procs, call_graph)
+            .validate_references()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_language() {
+        assert_eq!("ARM32".parse(), Ok(Language::Arm32));
+        assert_eq!("x86_64".parse(), Ok(Language::X86_64));
+        assert_eq!("AArch64".parse(), Ok(Language::Arm64));
+        for language in [
+            Language::X86,
+            Language::X86_64,
+            Language::Arm32,
+            Language::Mips,
+            Language::Wasm,
+        ] {
+            assert_eq!(language.to_string().parse(), Ok(language));
+        }
+        let mut program = Program::new("x86", HashMap::new(), DiGraph::new());
+        assert_eq!(program.language_enum(), Ok(Language::X86));
+        program.language = "z80".to_string();
+        let err = program.language_enum().unwrap_err();
+        assert_eq!(err, UnknownLanguage("z80".to_string()));
+        assert_eq!(err.to_string(), "unknown language: z80");
+    }
+
+    #[test]
+    fn test_field_label_predicates() {
+        let labels = [
+            (FieldLabel::InPattern("0".to_string()), (false, false, true)),
+            (
+                FieldLabel::OutPattern("eax".to_string()),
+                (false, false, true),
+            ),
+            (
+                FieldLabel::DerefPattern {
+                    size: 4,
+                    offset: 0,
+                    bound: None,
+                },
+                (false, true, false),
+            ),
+            (FieldLabel::Load, (true, false, false)),
+            (FieldLabel::Store, (true, false, false)),
+        ];
+        for (label, expected) in labels {
+            assert_eq!(
+                (label.is_access(), label.is_field(), label.is_io()),
+                expected,
+                "{}",
+                label
+            );
+        }
+    }
+
+    #[test]
+    fn test_field_label_order() {
+        let labels: Vec<FieldLabelId> = dtv("x.in_stack4.in_stack12.out_eax.σ4@8.σ4@-4.load.store")
+            .fields
+            .to_vec();
+        let mut sorted = labels.clone();
+        sorted.reverse();
+        sorted.sort();
+        let sorted: Vec<String> = sorted.iter().map(|l| l.to_string()).collect();
+        assert_eq!(
+            sorted,
+            [
+                "in_stack4",
+                "in_stack12",
+                "out_eax",
+                "σ4@-4",
+                "σ4@8",
+                "load",
+                "store"
+            ]
+        );
+    }
 }
@@ -1,16 +1,611 @@
-use crate::schema::DerivedTypeVariable;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
+use petgraph::{
+    dot::Dot,
+    graph::{DiGraph, EdgeIndex, NodeIndex},
+    visit::EdgeRef,
+};
 
-struct SketchNode {
-    dtv: DerivedTypeVariable,
-    // these two bound is attached auxillary data.
-    lower_bound: DerivedTypeVariable,
-    upper_bound: DerivedTypeVariable,
+use crate::{
+    c_types::{field_name, sanitize, scalar_name, CType},
+    lattice::{CTypeLattice, Lattice, BOTTOM, TOP},
+    schema::{Constraint, DerivedTypeVariable, FieldLabel, Variance},
+    solver::QuotientGraph,
+};
+
+pub struct SketchNode {
+    pub dtv: DerivedTypeVariable,
+    // these two bound is attached auxillary data, elements of the type
+    // lattice. see `Solver::compute_sketch_bounds`.
+    pub lower_bound: String,
+    pub upper_bound: String,
+}
+
+impl std::fmt::Debug for SketchNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self.dtv)
+    }
 }
 
-struct Sketch {
-    // directed graph
-    // node lookup map from dtv to node index
-    // root node
-    // reference to type lattice
-}
\ No newline at end of file
+/// The sketches of a procedure's parameters and return value.
+#[derive(Default)]
+pub struct FunctionSignature {
+    /// the parameters numbered like `in_0`, by position.
+    pub params: BTreeMap<u32, Sketch>,
+    /// the parameters with a name, like `in_stack0`, by name.
+    pub named_params: BTreeMap<String, Sketch>,
+    /// the return value, `out` or the first of the `out_` labels.
+    pub ret: Option<Sketch>,
+}
+
+/// The shape of one type variable: the part of the quotient graph from shape
+/// inference that is reachable from it. Each node is named by the shortest
+/// path from the root that reaches it.
+pub struct Sketch {
+    pub graph: DiGraph<SketchNode, FieldLabel>,
+    pub node_map: HashMap<DerivedTypeVariable, NodeIndex>,
+    pub root: NodeIndex,
+    /// the edges back to a node on the path from the root to their source,
+    /// where a recursive type refers to itself.
+    pub back_edges: HashSet<EdgeIndex>,
+}
+
+impl Sketch {
+    /// Copy the subgraph of `g_quotient` reachable from the class of `root`.
+    /// Returns `None` if `root` is in no class.
+    pub fn from_quotient(quotient: &QuotientGraph, root: &DerivedTypeVariable) -> Option<Sketch> {
+        let class = quotient.classes().position(|class| class.contains(root))?;
+        Some(Sketch::from_class(quotient, class, root))
+    }
+
+    /// Copy the subgraph of `quotient` reachable from `class`, with `root`
+    /// as the name of the root, whether or not the class holds it.
+    pub fn from_class(
+        quotient: &QuotientGraph,
+        class: usize,
+        root: &DerivedTypeVariable,
+    ) -> Sketch {
+        let g_quotient = &quotient.graph;
+        let q_root = NodeIndex::new(class);
+
+        let mut graph = DiGraph::new();
+        let mut node_map = HashMap::new();
+        // map from node in g_quotient to node in the sketch
+        let mut copied: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        let new_node = |graph: &mut DiGraph<SketchNode, FieldLabel>, dtv: DerivedTypeVariable| {
+            graph.add_node(SketchNode {
+                dtv,
+                lower_bound: BOTTOM.to_string(),
+                upper_bound: TOP.to_string(),
+            })
+        };
+
+        let sk_root = new_node(&mut graph, root.clone());
+        node_map.insert(root.clone(), sk_root);
+        copied.insert(q_root, sk_root);
+        // breadth first, so that each node gets the shortest path.
+        let mut queue = VecDeque::from([q_root]);
+        while let Some(q_ind) = queue.pop_front() {
+            let source = copied[&q_ind];
+            for edge in g_quotient.edges(q_ind) {
+                let target = match copied.get(&edge.target()) {
+                    Some(target) => *target,
+                    None => {
+                        let dtv = graph
+                            .node_weight(source)
+                            .unwrap()
+                            .dtv
+                            .with_field(edge.weight().clone());
+                        let target = new_node(&mut graph, dtv.clone());
+                        node_map.insert(dtv, target);
+                        copied.insert(edge.target(), target);
+                        queue.push_back(edge.target());
+                        target
+                    }
+                };
+                if !graph
+                    .edges_connecting(source, target)
+                    .any(|e| e.weight() == edge.weight())
+                {
+                    graph.add_edge(source, target, edge.weight().clone());
+                }
+            }
+        }
+        let back_edges = back_edges(&graph, sk_root);
+        Sketch {
+            graph,
+            node_map,
+            root: sk_root,
+            back_edges,
+        }
+    }
+
+    /// The labels leaving the root, with the node each one leads to.
+    pub fn fields(&self) -> impl Iterator<Item = (&FieldLabel, NodeIndex)> {
+        self.graph
+            .edges(self.root)
+            .map(|edge| (edge.weight(), edge.target()))
+    }
+
+    /// Narrow the bounds of the nodes with `bounds`, constraints between
+    /// the sketch's variables and the atoms of `lattice`: each atom below a
+    /// node is joined into its lower bound, and each atom above it is met
+    /// into its upper bound.
+    pub fn add_bounds(&mut self, bounds: &[Constraint], lattice: &CTypeLattice) {
+        let is_atom =
+            |dtv: &DerivedTypeVariable| dtv.fields.is_empty() && lattice.contains(&dtv.name);
+        for c in bounds {
+            if is_atom(&c.left) {
+                if let Some(ind) = self.node_map.get(&c.right) {
+                    let node = self.graph.node_weight_mut(*ind).unwrap();
+                    node.lower_bound = lattice.join(&node.lower_bound, &c.left.name.to_string());
+                }
+            }
+            if is_atom(&c.right) {
+                if let Some(ind) = self.node_map.get(&c.left) {
+                    let node = self.graph.node_weight_mut(*ind).unwrap();
+                    node.upper_bound = lattice.meet(&node.upper_bound, &c.right.name.to_string());
+                }
+            }
+        }
+    }
+
+    /// Whether the two sketches have the same shape and bounds: their roots
+    /// match, where two nodes match if their bounds are equal and they have
+    /// the same outgoing labels, each leading to matching nodes. The names
+    /// of the nodes do not matter.
+    pub fn structurally_eq(&self, other: &Sketch) -> bool {
+        let mut visited: HashSet<(NodeIndex, NodeIndex)> = HashSet::new();
+        let mut worklist = vec![(self.root, other.root)];
+        while let Some((a, b)) = worklist.pop() {
+            // a pair already being compared is assumed to match, so cycles end.
+            if !visited.insert((a, b)) {
+                continue;
+            }
+            let node_a = self.graph.node_weight(a).unwrap();
+            let node_b = other.graph.node_weight(b).unwrap();
+            if node_a.lower_bound != node_b.lower_bound || node_a.upper_bound != node_b.upper_bound
+            {
+                return false;
+            }
+            let edges_a = self.edges_sorted(a);
+            let edges_b = other.edges_sorted(b);
+            if edges_a.len() != edges_b.len() {
+                return false;
+            }
+            for ((label_a, target_a), (label_b, target_b)) in edges_a.into_iter().zip(edges_b) {
+                if label_a != label_b {
+                    return false;
+                }
+                worklist.push((target_a, target_b));
+            }
+        }
+        true
+    }
+
+    /// The least upper bound of the two sketches, the type both of them
+    /// are subtypes of. Only the paths the two have in common are kept. The
+    /// bounds of a node are joined in covariant positions and met in
+    /// contravariant ones. The nodes are named after `self`'s root.
+    pub fn join(&self, other: &Sketch, lattice: &CTypeLattice) -> Sketch {
+        self.combine(other, lattice, true)
+    }
+
+    /// The greatest lower bound of the two sketches: the paths of either of
+    /// them, with the bounds met in covariant positions and joined in
+    /// contravariant ones.
+    pub fn meet(&self, other: &Sketch, lattice: &CTypeLattice) -> Sketch {
+        self.combine(other, lattice, false)
+    }
+
+    /// Walk the two sketches together from their roots. A node of the result
+    /// stands for a pair of nodes, one of which may be missing for `meet`.
+    fn combine(&self, other: &Sketch, lattice: &CTypeLattice, join: bool) -> Sketch {
+        type Pair = (Option<NodeIndex>, Option<NodeIndex>);
+        let mut graph: DiGraph<SketchNode, FieldLabel> = DiGraph::new();
+        let mut node_map = HashMap::new();
+        let mut pairs: HashMap<Pair, NodeIndex> = HashMap::new();
+        let mut new_node = |graph: &mut DiGraph<SketchNode, FieldLabel>,
+                            (a, b): Pair,
+                            dtv: DerivedTypeVariable,
+                            variance: &Variance| {
+            let a = a.map(|a| self.graph.node_weight(a).unwrap());
+            let b = b.map(|b| other.graph.node_weight(b).unwrap());
+            let (lower_bound, upper_bound) = match (a, b) {
+                (Some(a), Some(b)) => {
+                    // joining covariant bounds, or meeting contravariant ones,
+                    // gives the more general type.
+                    let combine = |x: &String, y: &String| {
+                        if join == (variance == &Variance::Covariant) {
+                            lattice.join(x, y)
+                        } else {
+                            lattice.meet(x, y)
+                        }
+                    };
+                    (
+                        combine(&a.lower_bound, &b.lower_bound),
+                        combine(&a.upper_bound, &b.upper_bound),
+                    )
+                }
+                (Some(n), None) | (None, Some(n)) => (n.lower_bound.clone(), n.upper_bound.clone()),
+                (None, None) => unreachable!(),
+            };
+            let ind = graph.add_node(SketchNode {
+                dtv: dtv.clone(),
+                lower_bound,
+                upper_bound,
+            });
+            node_map.insert(dtv, ind);
+            ind
+        };
+
+        let root_pair = (Some(self.root), Some(other.root));
+        let root_dtv = self.graph.node_weight(self.root).unwrap().dtv.clone();
+        let root = new_node(&mut graph, root_pair, root_dtv, &Variance::Covariant);
+        pairs.insert(root_pair, root);
+        // breadth first, so that each node is named by its shortest path and
+        // gets the variance of that path.
+        let mut queue = VecDeque::from([(root_pair, Variance::Covariant)]);
+        while let Some(((a, b), variance)) = queue.pop_front() {
+            let source = pairs[&(a, b)];
+            let edges_a = a.map(|a| self.edges_sorted(a)).unwrap_or_default();
+            let edges_b = b.map(|b| other.edges_sorted(b)).unwrap_or_default();
+            let mut labels: Vec<&FieldLabel> = edges_a
+                .iter()
+                .chain(&edges_b)
+                .map(|(label, _)| *label)
+                .collect();
+            labels.sort();
+            labels.dedup();
+            for label in labels {
+                let find = |edges: &[(&FieldLabel, NodeIndex)]| {
+                    edges
+                        .iter()
+                        .find(|(l, _)| *l == label)
+                        .map(|(_, target)| *target)
+                };
+                let pair = (find(&edges_a), find(&edges_b));
+                if join && (pair.0.is_none() || pair.1.is_none()) {
+                    continue;
+                }
+                let target = match pairs.get(&pair) {
+                    Some(target) => *target,
+                    None => {
+                        let dtv = graph
+                            .node_weight(source)
+                            .unwrap()
+                            .dtv
+                            .with_field(label.clone());
+                        let target_variance = variance.combine(&label.variance());
+                        let target = new_node(&mut graph, pair, dtv, &target_variance);
+                        pairs.insert(pair, target);
+                        queue.push_back((pair, target_variance));
+                        target
+                    }
+                };
+                graph.add_edge(source, target, label.clone());
+            }
+        }
+        let back_edges = back_edges(&graph, root);
+        Sketch {
+            graph,
+            node_map,
+            root,
+            back_edges,
+        }
+    }
+
+    /// The sketch in DOT format. Each node shows its variable and its
+    /// `[lower, upper]` bounds, and the root is drawn bold.
+    pub fn to_dot(&self) -> String {
+        let g = self.graph.map(
+            |_, node| format!("{}\n[{}, {}]", node.dtv, node.lower_bound, node.upper_bound),
+            |_, label| label.clone(),
+        );
+        let root = self.root;
+        let node_attributes = |_, (ind, _)| {
+            if ind == root {
+                "style=bold, peripheries=2".to_string()
+            } else {
+                String::new()
+            }
+        };
+        format!(
+            "{}",
+            Dot::with_attr_getters(&g, &[], &|_, _| String::new(), &node_attributes)
+        )
+    }
+
+    /// Render the sketch as a C type, e.g. `int (*)(FileDescriptor)`.
+    ///
+    /// - `InPattern`/`OutPattern` edges make a pointer to a function.
+    /// - `Load`/`Store` edges make a pointer to the target.
+    /// - `DerefPattern` edges make a struct, which is written out in full
+    ///   the first time it is reached and by name after that.
+    /// - Leaves are named by their lattice bound, the lower one in
+    ///   covariant positions and the upper one in contravariant positions.
+    pub fn to_c_type(&self, lattice: &CTypeLattice) -> String {
+        let mut defined = Vec::new();
+        self.type_of(self.root, None, lattice, &mut Vec::new(), &mut defined)
+            .declare("")
+    }
+
+    fn edges_sorted(&self, ind: NodeIndex) -> Vec<(&FieldLabel, NodeIndex)> {
+        let mut edges: Vec<(&FieldLabel, NodeIndex)> = self
+            .graph
+            .edges(ind)
+            .map(|edge| (edge.weight(), edge.target()))
+            .collect();
+        edges.sort();
+        edges
+    }
+
+    fn type_of(
+        &self,
+        ind: NodeIndex,
+        size: Option<u32>,
+        lattice: &CTypeLattice,
+        visiting: &mut Vec<NodeIndex>,
+        defined: &mut Vec<NodeIndex>,
+    ) -> CType {
+        let edges = self.edges_sorted(ind);
+        let node = self.graph.node_weight(ind).unwrap();
+        let is_struct = edges.iter().any(|(label, _)| label.is_field());
+        if is_struct {
+            let name = format!("struct {}", sanitize(&node.dtv.to_string()));
+            if defined.contains(&ind) {
+                return CType::simple(&name);
+            }
+            defined.push(ind);
+            let mut body = Vec::new();
+            for (label, target) in &edges {
+                if let FieldLabel::DerefPattern { size, offset, .. } = label {
+                    let ty = self.type_of(*target, Some(*size), lattice, visiting, defined);
+                    body.push(format!("{};", ty.declare(&field_name(*offset))));
+                }
+            }
+            return CType::simple(&format!("{} {{ {} }}", name, body.join(" ")));
+        }
+        let pointee = edges
+            .iter()
+            .find(|(label, _)| label.is_access())
+            .map(|(_, target)| *target);
+        let is_function = edges.iter().any(|(label, _)| label.is_io());
+        // a cycle that does not go through a struct has no C spelling.
+        if visiting.contains(&ind) && pointee.is_none_or(|p| !defined.contains(&p)) {
+            return CType::simple("void");
+        }
+        visiting.push(ind);
+        let ty = if let Some(pointee) = pointee {
+            self.type_of(pointee, None, lattice, visiting, defined)
+                .pointer()
+        } else if is_function {
+            let ret = match edges
+                .iter()
+                .find(|(label, _)| matches!(label, FieldLabel::OutPattern(_)))
+            {
+                Some((_, target)) => self.type_of(*target, None, lattice, visiting, defined),
+                None => CType::simple("void"),
+            };
+            let params = edges
+                .iter()
+                .filter(|(label, _)| matches!(label, FieldLabel::InPattern(_)))
+                .map(|(_, target)| {
+                    self.type_of(*target, None, lattice, visiting, defined)
+                        .declare("")
+                })
+                .collect();
+            CType::function(ret, params).pointer()
+        } else {
+            CType::simple(&self.leaf_name(ind, size, lattice))
+        };
+        visiting.pop();
+        ty
+    }
+
+    fn leaf_name(&self, ind: NodeIndex, size: Option<u32>, lattice: &CTypeLattice) -> String {
+        let node = self.graph.node_weight(ind).unwrap();
+        let known = |bound: &String| bound != TOP && bound != BOTTOM && lattice.contains(bound);
+        let (first, second) = match node.dtv.suffix_variance() {
+            Variance::Covariant => (&node.lower_bound, &node.upper_bound),
+            Variance::Contravariant => (&node.upper_bound, &node.lower_bound),
+        };
+        match [first, second].into_iter().find(|b| known(b)) {
+            // type constants are written without their `_`.
+            Some(bound) => bound.strip_prefix('_').unwrap_or(bound).to_string(),
+            None => scalar_name(size),
+        }
+    }
+}
+
+/// The edges of `graph` that lead back to a node on the path from `root` to
+/// their source, found by a depth first search.
+fn back_edges(graph: &DiGraph<SketchNode, FieldLabel>, root: NodeIndex) -> HashSet<EdgeIndex> {
+    let mut back = HashSet::new();
+    let mut visited = HashSet::from([root]);
+    let mut on_path = vec![root];
+    // the outgoing edges still to follow, for each node on the path.
+    let mut stack = vec![graph.edges(root).collect::<Vec<_>>()];
+    while let Some(edges) = stack.last_mut() {
+        match edges.pop() {
+            Some(edge) => {
+                let target = edge.target();
+                if on_path.contains(&target) {
+                    back.insert(edge.id());
+                } else if visited.insert(target) {
+                    on_path.push(target);
+                    stack.push(graph.edges(target).collect());
+                }
+            }
+            None => {
+                stack.pop();
+                on_path.pop();
+            }
+        }
+    }
+    back
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use petgraph::graph::DiGraph;
+
+    use super::Sketch;
+    use crate::{
+        lattice::{CTypeLattice, BOTTOM},
+        parser::{constraints_from_json, parse_constraints_line, parse_derived_type_variable},
+        schema::{FieldLabel, Program},
+        solver::Solver,
+    };
+
+    const SLIDES_EXAMPLE: [&str; 9] = [
+        "F.in_stack0 <= 𝛿",
+        "𝛼 <= 𝜑",
+        "𝛿 <= 𝜑",
+        "𝜑.load.σ4@0 <= 𝛼",
+        "𝜑.load.σ4@4 <= 𝛼'",
+        "𝛼' <= close.in_stack0",
+        "close.out_eax <= F.out_eax",
+        "close.in_stack0 <= _FileDescriptor",
+        "_SuccessZ <= close.out_eax",
+    ];
+
+    fn sketch_of(constraints: &[&str], root: &str, lattice: &CTypeLattice) -> Sketch {
+        let constraints = constraints
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let solver = Solver::new(&program);
+        let root = parse_derived_type_variable(root).unwrap().1;
+        let mut sketch =
+            Sketch::from_quotient(&solver.infer_shapes().unwrap().quotient, &root).unwrap();
+        solver.compute_sketch_bounds(&mut sketch, lattice).unwrap();
+        sketch
+    }
+
+    #[test]
+    fn test_structurally_eq() {
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        let slides = sketch_of(&SLIDES_EXAMPLE, "F", &lattice);
+        // the same constraints with other temporaries, and the loop through
+        // 𝜑 written the other way around.
+        let renamed = [
+            "F.in_stack0 <= p",
+            "p.load.σ4@0 <= p",
+            "p.load.σ4@4 <= q",
+            "q <= close.in_stack0",
+            "close.out_eax <= F.out_eax",
+            "close.in_stack0 <= _FileDescriptor",
+            "_SuccessZ <= close.out_eax",
+        ];
+        let other = sketch_of(&renamed, "F", &lattice);
+        assert!(slides.structurally_eq(&other));
+        assert!(other.structurally_eq(&slides));
+
+        let close = sketch_of(&SLIDES_EXAMPLE, "close", &lattice);
+        let mut other = sketch_of(&renamed, "close", &lattice);
+        assert!(close.structurally_eq(&other));
+        let (_, target) = other
+            .fields()
+            .find(|(label, _)| matches!(label, FieldLabel::OutPattern(_)))
+            .unwrap();
+        other.graph.node_weight_mut(target).unwrap().lower_bound = "_FileDescriptor".to_string();
+        assert!(!close.structurally_eq(&other));
+        assert!(!close.structurally_eq(&slides));
+    }
+
+    #[test]
+    fn test_join_meet() {
+        let lattice = CTypeLattice::new(&[], &[("_int", "_num"), ("_uint", "_num")]);
+        let a = sketch_of(&["_int <= F.out_eax", "F.in_0 <= _int"], "F", &lattice);
+        let b = sketch_of(&["_uint <= F.out_eax"], "F", &lattice);
+        let bounds = |sketch: &Sketch, label: &str| {
+            let dtv = parse_derived_type_variable(&format!("F.{}", label))
+                .unwrap()
+                .1;
+            let node = sketch.graph.node_weight(sketch.node_map[&dtv]).unwrap();
+            (node.lower_bound.clone(), node.upper_bound.clone())
+        };
+        assert_eq!(bounds(&a, "out_eax").0, "_int");
+        assert_eq!(bounds(&b, "out_eax").0, "_uint");
+
+        let join = a.join(&b, &lattice);
+        assert_eq!(bounds(&join, "out_eax").0, "_num");
+        // only the paths of both.
+        assert_eq!(join.graph.node_count(), 2);
+        assert!(join.structurally_eq(&b.join(&a, &lattice)));
+
+        let meet = a.meet(&b, &lattice);
+        assert_eq!(bounds(&meet, "out_eax").0, BOTTOM);
+        assert_eq!(bounds(&meet, "in_0"), bounds(&a, "in_0"));
+        assert!(meet.structurally_eq(&b.meet(&a, &lattice)));
+        // a sketch is its own join and meet.
+        assert!(a.join(&a, &lattice).structurally_eq(&a));
+        assert!(a.meet(&a, &lattice).structurally_eq(&a));
+    }
+
+    #[test]
+    fn test_sketch_to_c_type() {
+        let slides = SLIDES_EXAMPLE;
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        let close = sketch_of(&slides, "close", &lattice);
+        assert_eq!(close.to_c_type(&lattice), "SuccessZ (*)(FileDescriptor)");
+        // 𝜑 points to a struct that holds itself at offset 0.
+        let phi = sketch_of(&slides, "𝜑", &lattice);
+        assert_eq!(
+            phi.to_c_type(&lattice),
+            "struct __load { struct __load *field_0; FileDescriptor field_4; } *"
+        );
+        let f = sketch_of(&slides, "F", &lattice);
+        assert_eq!(
+            f.to_c_type(&lattice),
+            "SuccessZ (*)(struct F_in_stack0_load { struct F_in_stack0_load *field_0; FileDescriptor field_4; } *)"
+        );
+    }
+
+    #[test]
+    fn test_sketch_to_dot() {
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        let close = sketch_of(&SLIDES_EXAMPLE, "close", &lattice);
+        let dot = close.to_dot();
+        assert!(
+            dot.contains("[ label = \"close\\l[⊥, ⊤]\" style=bold, peripheries=2]"),
+            "{}",
+            dot
+        );
+        assert!(dot.contains("close.out_eax\\l[_SuccessZ, ⊤]"), "{}", dot);
+        assert!(dot.contains("[ label = \"in_stack0\" ]"), "{}", dot);
+        assert_eq!(dot.matches("peripheries").count(), 1);
+    }
+
+    #[test]
+    fn test_sketch_from_quotient() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let g_quotient = Solver::new(&program).infer_shapes().unwrap().quotient;
+        let root = parse_derived_type_variable("v_0").unwrap().1;
+        let sketch = Sketch::from_quotient(&g_quotient, &root).unwrap();
+
+        // v_0 is only loaded from.
+        let fields: Vec<_> = sketch.fields().collect();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0, &FieldLabel::Load);
+        // v_0.load.σ4@0 is the same class as v_1, which is stored to.
+        let deref = parse_derived_type_variable("v_0.load.σ4@0").unwrap().1;
+        let node = sketch.node_map[&deref];
+        assert_eq!(sketch.graph.node_weight(node).unwrap().dtv, deref);
+        let store = parse_derived_type_variable("v_0.load.σ4@0.store")
+            .unwrap()
+            .1;
+        assert!(sketch.node_map.contains_key(&store));
+
+        let missing = parse_derived_type_variable("no_such_var").unwrap().1;
+        assert!(Sketch::from_quotient(&g_quotient, &missing).is_none());
+    }
+}
@@ -1,33 +1,397 @@
-use std::{collections::HashMap, env, fmt::Debug, fs::File, io::Write};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug},
+    path::PathBuf,
+};
 
 use petgraph::{
-    algo::{condensation, toposort},
-    dot::Dot,
+    algo::tarjan_scc,
+    dot::{Config, Dot},
     graph::{DiGraph, NodeIndex},
     visit::EdgeRef,
 };
+use serde_json::Value;
+
+use crate::{
+    c_types,
+    graph::{
+        canonical, infer_proc_types_ranked, to_graphml, write_debug_graph, ConstraintGraph, Node,
+        SaturationError, DEFAULT_MAX_ITERATIONS,
+    },
+    lattice::{CTypeLattice, Lattice},
+    parser::program_to_json,
+    schema::{Constraint, DerivedTypeVariable, FieldLabel, PointerCapabilities, Program},
+    sketches::{FunctionSignature, Sketch},
+};
+
+/// Two unified variables whose fields cannot belong to the same type, e.g.
+/// one is called like a function and the other is accessed like a struct.
+#[derive(PartialEq, Clone)]
+pub struct TypeConflict {
+    pub left: DerivedTypeVariable,
+    pub right: DerivedTypeVariable,
+}
+
+impl fmt::Display for TypeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "conflicting types: {} and {}", self.left, self.right)
+    }
+}
+
+impl Debug for TypeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Two fields of a struct whose byte spans `(offset, size)` intersect.
+#[derive(PartialEq, Clone)]
+pub struct FieldOverlap {
+    pub class: usize,
+    pub first: (i32, u32),
+    pub second: (i32, u32),
+}
+
+impl fmt::Display for FieldOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "overlapping fields in class {}: σ{}@{} and σ{}@{}",
+            self.class, self.first.1, self.first.0, self.second.1, self.second.0
+        )
+    }
+}
+
+impl Debug for FieldOverlap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// Why solving failed: the shapes of the program conflict, or a saturation
+/// did not terminate.
+#[derive(PartialEq, Clone)]
+pub enum SolveError {
+    Conflicts(Vec<TypeConflict>),
+    Saturation(SaturationError),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Conflicts(conflicts) => {
+                let conflicts: Vec<String> = conflicts.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}", conflicts.join("\n"))
+            }
+            SolveError::Saturation(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Debug for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+impl From<Vec<TypeConflict>> for SolveError {
+    fn from(conflicts: Vec<TypeConflict>) -> Self {
+        SolveError::Conflicts(conflicts)
+    }
+}
+
+impl From<SaturationError> for SolveError {
+    fn from(e: SaturationError) -> Self {
+        SolveError::Saturation(e)
+    }
+}
+
+/// The kind of type a field label implies for the variable it leaves.
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum Capability {
+    Function,
+    Pointer,
+    Struct,
+}
+
+impl Capability {
+    fn of(label: &FieldLabel, pointer: &PointerCapabilities) -> Capability {
+        if pointer.inverse(label).is_some() {
+            return Capability::Pointer;
+        }
+        if label.is_io() {
+            Capability::Function
+        } else if label.is_access() {
+            Capability::Pointer
+        } else {
+            Capability::Struct
+        }
+    }
+}
+
+/// The quotient graph of shape inference: each node is an equivalence class
+/// of derived type variables, edges are the field labels between classes.
+/// Classes are numbered by their position in `classes`.
+#[derive(Debug)]
+pub struct QuotientGraph {
+    pub graph: DiGraph<Vec<DerivedTypeVariable>, FieldLabel>,
+}
+
+impl QuotientGraph {
+    /// The variables of each class, sorted.
+    pub fn classes(&self) -> impl Iterator<Item = &[DerivedTypeVariable]> {
+        self.graph.node_weights().map(|class| class.as_slice())
+    }
+
+    pub fn class(&self, class: usize) -> Option<&[DerivedTypeVariable]> {
+        self.graph
+            .node_weight(NodeIndex::new(class))
+            .map(|class| class.as_slice())
+    }
+
+    /// The labels leaving `class`, with the class each one leads to.
+    pub fn out_fields(&self, class: usize) -> impl Iterator<Item = (&FieldLabel, usize)> {
+        self.graph
+            .edges(NodeIndex::new(class))
+            .map(|edge| (edge.weight(), edge.target().index()))
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// The `(offset, size)` spans of the `DerefPattern` fields leaving
+    /// `class`, sorted, each span once. Fails with every pair of spans that
+    /// share a byte.
+    pub fn struct_layout(&self, class: usize) -> Result<Vec<(i32, u32)>, Vec<FieldOverlap>> {
+        let mut spans: Vec<(i32, u32)> = self
+            .out_fields(class)
+            .filter_map(|(label, _)| match label {
+                FieldLabel::DerefPattern { size, offset, .. } => Some((*offset, *size)),
+                _ => None,
+            })
+            .collect();
+        spans.sort();
+        spans.dedup();
+        let end = |(offset, size): (i32, u32)| offset as i64 + size as i64;
+        let mut overlaps = Vec::new();
+        for (i, first) in spans.iter().enumerate() {
+            for second in spans[i + 1..]
+                .iter()
+                .take_while(|second| (second.0 as i64) < end(*first))
+            {
+                overlaps.push(FieldOverlap {
+                    class,
+                    first: *first,
+                    second: *second,
+                });
+            }
+        }
+        if overlaps.is_empty() {
+            Ok(spans)
+        } else {
+            Err(overlaps)
+        }
+    }
+
+    /// The classes on a cycle, the recursive types, sorted.
+    pub fn recursive_nodes(&self) -> Vec<usize> {
+        let mut recursive: Vec<usize> = tarjan_scc(&self.graph)
+            .into_iter()
+            .filter(|scc| scc.len() > 1 || self.graph.contains_edge(scc[0], scc[0]))
+            .flatten()
+            .map(|ind| ind.index())
+            .collect();
+        recursive.sort();
+        recursive
+    }
+}
+
+/// The result of shape inference (Algorithm E.1).
+#[derive(Debug)]
+pub struct ShapeResult {
+    pub quotient: QuotientGraph,
+    /// the class of each variable in `quotient`.
+    pub class_map: HashMap<DerivedTypeVariable, usize>,
+    /// the unifications left out because they would merge two different
+    /// opaque variables, see `Program::opaque`.
+    pub refused: Vec<TypeConflict>,
+    /// the height of the union-find forest once every constraint is
+    /// unified. Union by rank keeps it under the log2 of the number of
+    /// variables.
+    pub forest_height: usize,
+}
+
+impl ShapeResult {
+    /// The variables unified with `dtv`, including itself.
+    pub fn equiv_class(&self, dtv: &DerivedTypeVariable) -> Option<&[DerivedTypeVariable]> {
+        self.quotient.class(*self.class_map.get(dtv)?)
+    }
+
+    pub fn class_count(&self) -> usize {
+        self.quotient.class_count()
+    }
+
+    /// The quotient graph in GraphML format, each class labeled with its
+    /// variables.
+    pub fn to_graphml(&self) -> String {
+        to_graphml(
+            &canonical(&self.quotient.graph),
+            |class| format!("{:?}", class),
+            |label| label.to_string(),
+        )
+    }
+}
 
-use crate::{graph::infer_proc_types, schema::{Constraint, DerivedTypeVariable, FieldLabel, Program}};
+/// The size of one procedure's saturated constraint graph, see
+/// `Solver::report`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProcReport {
+    pub name: String,
+    pub constraint_count: usize,
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// the rounds saturation took, or the limit if it did not terminate.
+    pub saturation_iterations: usize,
+}
 
 pub struct Solver<'a> {
     pub program: &'a Program,
+    /// only solve the constraints of these procedures, if set.
+    pub selected: Option<HashSet<String>>,
+    /// where to write the intermediate graphs for debugging, if set.
+    pub dot_dir: Option<PathBuf>,
+    /// the labels the pointer rule and shape inference treat as load and
+    /// store.
+    pub pointer_capabilities: PointerCapabilities,
+    /// where `infer_shapes` writes its union-find forest as DOT, each
+    /// variable with an edge to its representative, if set. Taken from the
+    /// `DEBUG_UNIONFIND` environment variable.
+    pub union_find_dump: Option<PathBuf>,
+    /// the rounds each saturation may take, see
+    /// `ConstraintGraph::with_max_iterations`.
+    pub max_iterations: usize,
 }
 
 impl Solver<'_> {
-    pub fn new(program: &Program) -> Solver {
-        Solver { program }
+    pub fn new(program: &Program) -> Solver<'_> {
+        Solver {
+            program,
+            selected: None,
+            dot_dir: None,
+            pointer_capabilities: PointerCapabilities::default(),
+            union_find_dump: std::env::var_os("DEBUG_UNIONFIND").map(PathBuf::from),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+        }
     }
-    pub fn solve(self: Self) -> () {
-        infer_proc_types(self.program);
+    /// Restrict shape inference to the constraints of `procs`.
+    pub fn with_procs(mut self, procs: HashSet<String>) -> Self {
+        self.selected = Some(procs);
+        self
+    }
+    /// Write the intermediate graphs into `dir`: `shapes.dot` and
+    /// `shapes-quotient.{dot,graphml}` from shape inference, and a
+    /// directory with the transducer graphs for each group of procedures
+    /// solved together.
+    pub fn with_dot_dir(mut self, dir: PathBuf) -> Self {
+        self.dot_dir = Some(dir);
+        self
+    }
+    /// Read and write through pointers with `pointer` instead of `load` and
+    /// `store`.
+    pub fn with_pointer_capabilities(mut self, pointer: PointerCapabilities) -> Self {
+        self.pointer_capabilities = pointer;
+        self
+    }
+    /// Fail solving with `SaturationError::IterationLimit` once a
+    /// saturation takes more than `iterations` rounds.
+    pub fn with_max_iterations(mut self, iterations: usize) -> Self {
+        self.max_iterations = iterations;
+        self
+    }
+    /// Each selected procedure with its constraints.
+    fn selected_procs(&self) -> impl Iterator<Item = (&String, &Vec<Constraint>)> {
+        self.program.proc_constraints.iter().filter(|(name, _)| {
+            self.selected
+                .as_ref()
+                .is_none_or(|procs| procs.contains(*name))
+        })
+    }
+    /// The constraints of each selected procedure.
+    fn proc_constraints(&self) -> impl Iterator<Item = &Vec<Constraint>> {
+        self.selected_procs().map(|(_, cons)| cons)
+    }
+    /// Build and saturate the graph of the own constraints of each selected
+    /// procedure, without the schemes of its callees, and report its size.
+    /// Sorted by node count, largest first, then by name.
+    pub fn report(&self) -> Vec<ProcReport> {
+        let mut reports: Vec<ProcReport> = self
+            .selected_procs()
+            .map(|(name, constraints)| {
+                let mut cg = ConstraintGraph::construct().with_max_iterations(self.max_iterations);
+                cg.variance_table = self.program.variance_table.clone();
+                cg.pointer_capabilities = self.pointer_capabilities.clone();
+                let interesting = self.program.interesting_vars(name);
+                let iterations =
+                    match cg.build_and_saturate(constraints.iter().collect(), &interesting, None) {
+                        Ok(stats) => stats.iterations,
+                        Err(SaturationError::IterationLimit { iterations }) => iterations,
+                    };
+                ProcReport {
+                    name: name.clone(),
+                    constraint_count: constraints.len(),
+                    node_count: cg.graph.node_count(),
+                    edge_count: cg.graph.edge_count(),
+                    saturation_iterations: iterations,
+                }
+            })
+            .collect();
+        reports.sort_by(|a, b| {
+            b.node_count
+                .cmp(&a.node_count)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        reports
+    }
+    /// The type scheme of each procedure: its constraints simplified to the
+    /// ones over its interface and the type constants. Fails if a
+    /// saturation does not terminate.
+    pub fn solve(&self) -> Result<HashMap<String, Vec<Constraint>>, SaturationError> {
+        infer_proc_types_ranked(
+            self.program,
+            &self.pointer_capabilities,
+            self.dot_dir.as_deref(),
+            self.max_iterations,
+            true,
+        )
+    }
+    /// The type schemes as a program in the input JSON format, with the
+    /// schemes as the procedures' constraints. Solving it again gives the
+    /// same schemes.
+    pub fn schemes_to_json(&self) -> Result<Value, SaturationError> {
+        let schemes = Program::new(
+            &self.program.language,
+            self.solve()?,
+            self.program.call_graph.clone(),
+        );
+        Ok(program_to_json(&schemes))
     }
 
     // TODO Probably should not do this to the whole program? but for a func at a time
     /// Infer the sketches for a set of constraints.
     /// Algorithm E.1 in paper.
-    pub fn infer_shapes(self: Self) -> () {
+    ///
+    /// Fails with every pair of unified variables whose fields conflict.
+    pub fn infer_shapes(&self) -> Result<ShapeResult, Vec<TypeConflict>> {
         struct Node {
             dtv: DerivedTypeVariable,
             represent: Option<NodeIndex>,
+            /// upper bound of the height of the tree under a representative.
+            rank: u32,
+            /// the opaque variable in the tree, kept at the representative.
+            opaque: Option<String>,
         }
 
         impl Debug for Node {
@@ -57,20 +421,76 @@ impl Solver<'_> {
             ret
         }
 
-        fn unify(g: &mut DiGraph<Node, FieldLabel>, x: NodeIndex, y: NodeIndex) -> () {
-            if x != y {
-                // make x the representative of y
-                let node = g.node_weight_mut(y).unwrap();
-                node.represent = Some(x);
+        fn capabilities(
+            g: &DiGraph<Node, FieldLabel>,
+            ind: NodeIndex,
+            pointer: &PointerCapabilities,
+        ) -> HashSet<Capability> {
+            g.edges(ind)
+                .map(|edge| Capability::of(edge.weight(), pointer))
+                .collect()
+        }
+
+        /// Unify `x` and `y`, and then the targets of their edges with the
+        /// same label. The pairs still to unify are kept on a stack, so deep
+        /// types do not overflow the call stack.
+        fn unify(
+            g: &mut DiGraph<Node, FieldLabel>,
+            x: NodeIndex,
+            y: NodeIndex,
+            pointer: &PointerCapabilities,
+            conflicts: &mut Vec<TypeConflict>,
+            refused: &mut Vec<TypeConflict>,
+        ) {
+            let mut stack = vec![(x, y)];
+            while let Some((x, y)) = stack.pop() {
+                let root_x = find_equiv_group(g, x);
+                let root_y = find_equiv_group(g, y);
+                if root_x == root_y {
+                    continue;
+                }
+                // two different opaque variables stay apart.
+                if let (Some(a), Some(b)) = (&g[root_x].opaque, &g[root_y].opaque) {
+                    refused.push(TypeConflict {
+                        left: DerivedTypeVariable::new(a),
+                        right: DerivedTypeVariable::new(b),
+                    });
+                    continue;
+                }
+                // both have fields, but of no common kind.
+                let caps_x = capabilities(g, x, pointer);
+                let caps_y = capabilities(g, y, pointer);
+                if !caps_x.is_empty() && !caps_y.is_empty() && caps_x.is_disjoint(&caps_y) {
+                    conflicts.push(TypeConflict {
+                        left: g.node_weight(x).unwrap().dtv.clone(),
+                        right: g.node_weight(y).unwrap().dtv.clone(),
+                    });
+                }
+                // attach the lower tree under the higher one
+                let rank_x = g.node_weight(root_x).unwrap().rank;
+                let rank_y = g.node_weight(root_y).unwrap().rank;
+                let (root, child) = if rank_x >= rank_y {
+                    (root_x, root_y)
+                } else {
+                    (root_y, root_x)
+                };
+                g.node_weight_mut(child).unwrap().represent = Some(root);
+                if rank_x == rank_y {
+                    g.node_weight_mut(root).unwrap().rank += 1;
+                }
+                if g[root].opaque.is_none() {
+                    g[root].opaque = g[child].opaque.clone();
+                }
                 let mut to_unify = Vec::new();
                 for edge_x in g.edges_directed(x, petgraph::Direction::Outgoing) {
                     let label_x = edge_x.weight();
                     for edge_y in g.edges_directed(y, petgraph::Direction::Outgoing) {
                         let label_y = edge_y.weight();
                         // unify if the labels are the same, or one is load and the other is store.
-                        if label_x == label_y
-                            || (label_x == &FieldLabel::Load && label_y == &FieldLabel::Store)
-                            || (label_x == &FieldLabel::Store && label_y == &FieldLabel::Load)
+                        if label_x.same_capability(label_y)
+                            || pointer
+                                .inverse(label_x)
+                                .is_some_and(|inverse| inverse.same_capability(label_y))
                         {
                             log::debug!(
                                 "Unify: there is a edge from {:?} to {:?} with label {:?}",
@@ -84,33 +504,44 @@ impl Solver<'_> {
                                 g.node_weight(edge_y.target()),
                                 label_y
                             );
-                            let node_x = edge_x.target();
-                            let node_y = edge_y.target();
-                            to_unify.push((node_x, node_y));
+                            to_unify.push((edge_x.target(), edge_y.target()));
                         }
                     }
                 }
-                for (node_x, node_y) in to_unify {
-                    unify(g, node_x, node_y);
-                }
+                // in reverse, so the pairs are unified in the order found.
+                stack.extend(to_unify.into_iter().rev());
             }
         }
 
-        for (_, cons) in &self.program.proc_constraints {
+        // the variables are indexed by `capability_key`, so the ones that
+        // differ only in deref bounds share a node. These are the variables
+        // with bounds, which get the class of their key.
+        let mut bounded: HashSet<DerivedTypeVariable> = HashSet::new();
+        for cons in self.proc_constraints() {
             // TODO deduplicate dtv beforehand
             for c in cons {
-                for c in [&c.left, &c.right] {
+                // ⊤, ⊥ and the type constants are lattice elements, they have
+                // no shape.
+                for original in [&c.left, &c.right]
+                    .into_iter()
+                    .filter(|c| !c.is_top() && !c.is_bottom() && !self.is_constant(c))
+                {
+                    let c = &original.capability_key();
+                    if c != original {
+                        let subs = (1..=c.fields.len()).map(|i| original.get_sub_dtv(i));
+                        bounded.extend(subs.filter(|sub| *sub != sub.capability_key()));
+                    }
                     let mut prev_id: Option<NodeIndex> = None;
                     // handle base type variable
-                    if c.fields.len() == 0 {
-                        if !gm.contains_key(&c) {
-                            let node = Node {
-                                dtv: c.clone(),
-                                represent: None,
-                            };
-                            let node_index = g.add_node(node);
-                            gm.insert(c.clone(), node_index);
-                        }
+                    if c.fields.is_empty() && !gm.contains_key(c) {
+                        let node = Node {
+                            dtv: c.clone(),
+                            represent: None,
+                            rank: 0,
+                            opaque: None,
+                        };
+                        let node_index = g.add_node(node);
+                        gm.insert(c.clone(), node_index);
                     }
                     // handle derived type variable
                     for i in 1..=c.fields.len() {
@@ -122,12 +553,14 @@ impl Solver<'_> {
                                 let node = Node {
                                     dtv: dtv_l.clone(),
                                     represent: None,
+                                    rank: 0,
+                                    opaque: None,
                                 };
                                 let node_index = g.add_node(node);
                                 gm.insert(dtv_l.clone(), node_index);
                                 node_index
                             } else {
-                                find_equiv_group(&mut g, gm.get(&dtv_l).unwrap().clone())
+                                find_equiv_group(&mut g, *gm.get(&dtv_l).unwrap())
                             }
                         } else {
                             prev_id.unwrap()
@@ -139,19 +572,27 @@ impl Solver<'_> {
                             let node = Node {
                                 dtv: dtv_r.clone(),
                                 represent: None,
+                                rank: 0,
+                                opaque: None,
                             };
                             let node_index = g.add_node(node);
                             gm.insert(dtv_r.clone(), node_index);
                             node_index
                         } else {
-                            find_equiv_group(&mut g, gm.get(&dtv_r).unwrap().clone())
+                            find_equiv_group(&mut g, *gm.get(&dtv_r).unwrap())
                         };
-                        // create edge with field label i, if not exist
-                        if !g
-                            .edges_connecting(node_id, new_node_id)
-                            .any(|edge| edge.weight() == &c.fields[i - 1])
+                        // create edge with field label i, if not exist. The
+                        // edge keeps the meet of the bounds the label has.
+                        let label = &original.fields[i - 1];
+                        match g
+                            .edges(node_id)
+                            .find(|edge| edge.weight().same_capability(label))
+                            .map(|edge| edge.id())
                         {
-                            g.add_edge(node_id, new_node_id, c.fields[i - 1].clone());
+                            Some(edge) => g[edge] = g[edge].meet_bound(label),
+                            None => {
+                                g.add_edge(node_id, new_node_id, label.get().clone());
+                            }
                         }
                         prev_id = Some(new_node_id);
                     }
@@ -160,19 +601,84 @@ impl Solver<'_> {
         }
 
         // print the graph for debugging
-        if let Some(g_path) = env::var("DEBUG_G_GRAPH").ok() {
-            let mut file = File::create(g_path).unwrap();
-            write!(file, "{:?}", Dot::new(&g)).unwrap();
-        }
+        write_debug_graph(self.dot_dir.as_deref(), "shapes.dot", || {
+            let g = g.map(|_, n| n.dtv.clone(), |_, e| e.clone());
+            format!("{:?}", Dot::new(&canonical(&g)))
+        });
 
-        for (_, cons) in &self.program.proc_constraints {
+        for (dtv, ind) in &gm {
+            if dtv.fields.is_empty() && self.program.opaque.contains(&*dtv.name) {
+                g[*ind].opaque = Some(dtv.name.to_string());
+            }
+        }
+        let mut conflicts = Vec::new();
+        let mut refused = Vec::new();
+        for cons in self.proc_constraints() {
             for c in cons {
-                let ind = gm.get(&c.left).unwrap();
+                if [&c.left, &c.right]
+                    .iter()
+                    .any(|c| c.is_top() || c.is_bottom())
+                {
+                    continue;
+                }
+                if self.is_constant(&c.left) || self.is_constant(&c.right) {
+                    // two constants are only related if the lattice says so.
+                    if self.is_constant(&c.left)
+                        && self.is_constant(&c.right)
+                        && !self.constants_ordered(c)
+                    {
+                        conflicts.push(TypeConflict {
+                            left: c.left.clone(),
+                            right: c.right.clone(),
+                        });
+                    }
+                    continue;
+                }
+                let ind = gm.get(&c.left.capability_key()).unwrap();
                 let x = find_equiv_group(&mut g, *ind);
-                let ind2 = gm.get(&c.right).unwrap();
+                let ind2 = gm.get(&c.right.capability_key()).unwrap();
                 let y = find_equiv_group(&mut g, *ind2);
-                unify(&mut g, x, y)
+                unify(
+                    &mut g,
+                    x,
+                    y,
+                    &self.pointer_capabilities,
+                    &mut conflicts,
+                    &mut refused,
+                )
+            }
+        }
+        // before the quotient graph compresses the paths.
+        let forest_height = g
+            .node_indices()
+            .map(|mut ind| {
+                let mut height = 0;
+                while let Some(rep) = g[ind].represent.filter(|rep| *rep != ind) {
+                    ind = rep;
+                    height += 1;
+                }
+                height
+            })
+            .max()
+            .unwrap_or(0);
+        if let Some(path) = &self.union_find_dump {
+            let mut forest = g.map(|_, n| n.dtv.clone(), |_, _| ());
+            forest.clear_edges();
+            for ind in g.node_indices() {
+                match g[ind].represent {
+                    Some(rep) if rep != ind => {
+                        forest.add_edge(ind, rep, ());
+                    }
+                    _ => {}
+                }
             }
+            let dot = format!("{:?}", Dot::with_config(&forest, &[Config::EdgeNoLabel]));
+            if let Err(e) = std::fs::write(path, dot) {
+                log::error!("cannot write {}: {}", path.display(), e);
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(conflicts);
         }
         // build the g quotient graph
         let mut g_quotient = DiGraph::<Vec<DerivedTypeVariable>, FieldLabel>::new();
@@ -182,18 +688,30 @@ impl Solver<'_> {
         for ind in g.node_indices() {
             let rep = find_equiv_group(&mut g, ind);
             let node = g.node_weight(ind).unwrap();
-            if !gm_quotient.contains_key(&rep) {
-                let mut vec = Vec::new();
-                vec.push(node.dtv.clone());
-                let node = g_quotient.add_node(vec);
-                gm_quotient.insert(rep, node);
+            if let std::collections::hash_map::Entry::Vacant(e) = gm_quotient.entry(rep) {
+                let node = g_quotient.add_node(vec![node.dtv.clone()]);
+                e.insert(node);
             } else {
                 let node2 = g_quotient
-                    .node_weight_mut(gm_quotient.get(&rep).unwrap().clone())
+                    .node_weight_mut(*gm_quotient.get(&rep).unwrap())
                     .unwrap();
                 node2.push(node.dtv.clone());
             }
         }
+        for class in g_quotient.node_weights_mut() {
+            class.sort();
+        }
+        let mut class_map: HashMap<DerivedTypeVariable, usize> = gm
+            .iter()
+            .map(|(dtv, ind)| {
+                let rep = find_equiv_group(&mut g, *ind);
+                (dtv.clone(), gm_quotient[&rep].index())
+            })
+            .collect();
+        for dtv in bounded {
+            let class = class_map[&dtv.capability_key()];
+            class_map.insert(dtv, class);
+        }
 
         for ind in g.edge_indices() {
             let source = g.edge_endpoints(ind).unwrap().0;
@@ -203,17 +721,852 @@ impl Solver<'_> {
             let source_quotient = gm_quotient.get(&source_rep).unwrap();
             let target_quotient = gm_quotient.get(&target_rep).unwrap();
             let edge = g.edge_weight(ind).unwrap();
-            g_quotient.add_edge(
-                source_quotient.clone(),
-                target_quotient.clone(),
-                edge.clone(),
-            );
+            g_quotient.add_edge(*source_quotient, *target_quotient, edge.clone());
         }
 
         // print the graph for debugging
-        if let Some(g_quotient_path) = env::var("DEBUG_G_QUOTIENT_GRAPH").ok() {
-            let mut file = File::create(g_quotient_path).unwrap();
-            write!(file, "{:?}", Dot::new(&g_quotient)).unwrap();
+        write_debug_graph(self.dot_dir.as_deref(), "shapes-quotient.dot", || {
+            format!("{:?}", Dot::new(&canonical(&g_quotient)))
+        });
+        let shapes = ShapeResult {
+            quotient: QuotientGraph { graph: g_quotient },
+            class_map,
+            refused,
+            forest_height,
+        };
+        write_debug_graph(self.dot_dir.as_deref(), "shapes-quotient.graphml", || {
+            shapes.to_graphml()
+        });
+        Ok(shapes)
+    }
+
+    /// Whether `dtv` is a type constant. Procedures may be named with a
+    /// leading `_` too, those are variables.
+    fn is_constant(&self, dtv: &DerivedTypeVariable) -> bool {
+        dtv.is_type_constant()
+            && !self.program.proc_constraints.contains_key(&*dtv.name)
+            && !self
+                .program
+                .call_graph
+                .node_weights()
+                .any(|proc| **proc == *dtv.name)
+    }
+
+    /// Whether the constraint between two type constants holds in the
+    /// program's lattice. Without a lattice, only `_A <= _A` does.
+    fn constants_ordered(&self, c: &Constraint) -> bool {
+        match &self.program.types {
+            Some(lattice) => lattice.leq(&c.left.name.to_string(), &c.right.name.to_string()),
+            None => c.left == c.right,
+        }
+    }
+
+    /// Attach lattice bounds to the nodes of `sketch`.
+    ///
+    /// The constraints of the program are simplified to the ones between the
+    /// sketch's root variable and the lattice atoms. For each sketch node,
+    /// the lower bound is the join of the atoms below it, and the upper bound
+    /// the meet of the atoms above it. A type hint of the program pins its
+    /// variable to the atom from both sides.
+    ///
+    /// Fails if the saturation does not terminate, leaving the bounds as
+    /// they were.
+    pub fn compute_sketch_bounds(
+        &self,
+        sketch: &mut Sketch,
+        lattice: &CTypeLattice,
+    ) -> Result<(), SaturationError> {
+        let root = sketch
+            .graph
+            .node_weight(sketch.root)
+            .unwrap()
+            .dtv
+            .name
+            .clone();
+        let bounds = self.lattice_bounds(&root, lattice)?;
+        sketch.add_bounds(&bounds, lattice);
+        Ok(())
+    }
+
+    /// The constraints between the variables based on `root` and the atoms
+    /// of `lattice`, from the saturated graph of the whole program and its
+    /// type hints.
+    fn lattice_bounds(
+        &self,
+        root: &str,
+        lattice: &CTypeLattice,
+    ) -> Result<Vec<Constraint>, SaturationError> {
+        let hints: Vec<Constraint> = self
+            .program
+            .type_hints
+            .iter()
+            .flat_map(|(dtv, atom)| {
+                let atom = DerivedTypeVariable::new(atom);
+                [
+                    Constraint::new(atom.clone(), dtv.clone()),
+                    Constraint::new(dtv.clone(), atom),
+                ]
+            })
+            .collect();
+        let constraints: Vec<&Constraint> =
+            self.proc_constraints().flatten().chain(&hints).collect();
+        let mut cg = ConstraintGraph::construct().with_max_iterations(self.max_iterations);
+        cg.variance_table = self.program.variance_table.clone();
+        cg.pointer_capabilities = self.pointer_capabilities.clone();
+        cg.build_and_saturate(constraints, &HashSet::new(), None)?;
+        let interesting: HashSet<Node> = cg
+            .graph_node_map
+            .keys()
+            .filter(|n| {
+                n.base.fields.is_empty()
+                    && (*n.base.name == *root || lattice.contains(&n.base.name))
+            })
+            .cloned()
+            .collect();
+        Ok(cg.path_expr(&interesting))
+    }
+
+    /// The sketches of the parameters and return value of `proc`, with the
+    /// bounds from the program's lattice, or the default one. `None` if
+    /// `proc` has no shape.
+    pub fn function_signature(&self, proc: &str) -> Result<Option<FunctionSignature>, SolveError> {
+        let shapes = self.infer_shapes()?;
+        let base = DerivedTypeVariable::new(proc);
+        let class = match shapes.class_map.get(&base) {
+            Some(class) => *class,
+            None => return Ok(None),
+        };
+        let lattice = self.program.types.clone().unwrap_or_default();
+        // all the sketches are rooted at `proc`, they share the bounds.
+        let bounds = self.lattice_bounds(proc, &lattice)?;
+        let mut fields: Vec<(&FieldLabel, usize)> = shapes.quotient.out_fields(class).collect();
+        fields.sort();
+        let mut signature = FunctionSignature::default();
+        for (label, target) in fields {
+            let sketch = || {
+                let mut sketch =
+                    Sketch::from_class(&shapes.quotient, target, &base.with_field(label.clone()));
+                sketch.add_bounds(&bounds, &lattice);
+                sketch
+            };
+            match (label, label.index()) {
+                (FieldLabel::InPattern(_), Some(i)) => {
+                    signature.params.insert(i, sketch());
+                }
+                (FieldLabel::InPattern(name), None) => {
+                    signature.named_params.insert(name.clone(), sketch());
+                }
+                (FieldLabel::OutPattern(_), _) if signature.ret.is_none() => {
+                    signature.ret = Some(sketch())
+                }
+                _ => {}
+            }
+        }
+        Ok(Some(signature))
+    }
+
+    /// Emit C declarations for the shapes recovered by `infer_shapes`.
+    pub fn emit_c_types(&self) -> Result<String, Vec<TypeConflict>> {
+        Ok(c_types::emit_c_types(&self.infer_shapes()?.quotient))
+    }
+
+    /// Describe the shapes recovered by `infer_shapes` as JSON for Ghidra,
+    /// see `c_types::emit_ghidra_json`.
+    pub fn emit_ghidra_json(&self) -> Result<Value, Vec<TypeConflict>> {
+        Ok(c_types::emit_ghidra_json(&self.infer_shapes()?.quotient))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use petgraph::graph::DiGraph;
+    use serde_json::json;
+
+    use super::{FieldOverlap, SolveError, Solver, TypeConflict};
+    use crate::schema::{Bound, FieldLabel};
+    use crate::{
+        graph::SaturationError,
+        lattice::{CTypeLattice, BOTTOM, TOP},
+        parser::{
+            constraints_from_json, parse_constraint, parse_constraints_line,
+            parse_derived_type_variable, program_from_json,
+        },
+        schema::Program,
+        sketches::Sketch,
+    };
+
+    const SLIDES_EXAMPLE: [&str; 9] = [
+        "F.in_stack0 <= 𝛿",
+        "𝛼 <= 𝜑",
+        "𝛿 <= 𝜑",
+        "𝜑.load.σ4@0 <= 𝛼",
+        "𝜑.load.σ4@4 <= 𝛼'",
+        "𝛼' <= close.in_stack0",
+        "close.out_eax <= F.out_eax",
+        "close.in_stack0 <= _FileDescriptor",
+        "_SuccessZ <= close.out_eax",
+    ];
+
+    #[test]
+    fn test_emit_c_types() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let solver = Solver::new(&program);
+        let c = solver.emit_c_types().unwrap();
+        // v_0 points to a struct whose field at offset 0 is v_1.
+        assert!(c.contains("typedef struct v_0_load *v_0;"), "{}", c);
+        assert!(
+            c.contains("struct v_1_store *field_0; // offset 0"),
+            "{}",
+            c
+        );
+        // v_1 stores to four fields below its base.
+        for offset in [-4, -8, -12, -16] {
+            let field = format!("uint32_t field_neg{}; // offset {}", -offset, offset);
+            assert!(c.contains(&field), "{}", c);
+        }
+        assert!(c.contains("int main(void);"), "{}", c);
+        assert!(c.contains("int main_2(int, int);"), "{}", c);
+    }
+
+    #[test]
+    fn test_emit_ghidra_json() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let ghidra = Solver::new(&program).emit_ghidra_json().unwrap();
+        let structures = ghidra["structures"].as_array().unwrap();
+        let store = structures
+            .iter()
+            .find(|s| s["name"] == "v_1_store")
+            .unwrap();
+        let field = store["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|f| f["offset"] == -16)
+            .unwrap();
+        assert_eq!(
+            field,
+            &json!({"name": "field_neg16", "offset": -16, "size": 4, "type": "uint32_t"})
+        );
+        let pointers = ghidra["pointers"].as_array().unwrap();
+        assert!(
+            pointers.contains(&json!({"name": "v_0", "target": "struct v_0_load"})),
+            "{}",
+            ghidra
+        );
+        let functions = ghidra["functions"].as_array().unwrap();
+        let main_2 = functions.iter().find(|f| f["name"] == "main_2").unwrap();
+        assert_eq!(main_2["return"], "int");
+        assert_eq!(
+            main_2["parameters"][1],
+            json!({"name": "in_1", "type": "int"})
+        );
+    }
+
+    #[test]
+    fn test_equiv_class() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // v_0.load.σ4@0 <= v_1
+        let class = shapes.equiv_class(&dtv("v_0.load.σ4@0")).unwrap();
+        assert!(class.contains(&dtv("v_1")), "{:?}", class);
+        assert_eq!(
+            shapes.class_map[&dtv("v_0.load.σ4@0")],
+            shapes.class_map[&dtv("v_1")]
+        );
+        assert_ne!(shapes.class_map[&dtv("v_0")], shapes.class_map[&dtv("v_1")]);
+        assert!(shapes.equiv_class(&dtv("no_such_var")).is_none());
+        let graphml = shapes.to_graphml();
+        assert_eq!(graphml.matches("<node ").count(), shapes.class_count());
+    }
+
+    #[test]
+    fn test_quotient_graph() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let quotient = &shapes.quotient;
+        assert_eq!(quotient.classes().count(), 17);
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // v_1 is stored to at four offsets below its base.
+        let v_1 = shapes.class_map[&dtv("v_1")];
+        assert_eq!(quotient.class(v_1), shapes.equiv_class(&dtv("v_1")));
+        let fields: Vec<(&FieldLabel, usize)> = quotient.out_fields(v_1).collect();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0, &FieldLabel::Store);
+        let mut offsets: Vec<String> = quotient
+            .out_fields(fields[0].1)
+            .map(|(label, _)| label.to_string())
+            .collect();
+        offsets.sort();
+        assert_eq!(offsets, ["σ4@-12", "σ4@-16", "σ4@-4", "σ4@-8"]);
+    }
+
+    #[test]
+    fn test_recursive_nodes() {
+        let constraints = ["list <= list.load.σ8@0", "x <= y"]
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        let list = shapes.class_map[&dtv("list")];
+        let node = shapes.class_map[&dtv("list.load")];
+        assert_eq!(shapes.class_map[&dtv("list.load.σ8@0")], list);
+        let mut expected = vec![list, node];
+        expected.sort();
+        assert_eq!(shapes.quotient.recursive_nodes(), expected);
+
+        // the sketch refers back to its root instead of unrolling the list.
+        let sketch = Sketch::from_quotient(&shapes.quotient, &dtv("list")).unwrap();
+        assert_eq!(sketch.graph.node_count(), 2);
+        let back: Vec<_> = sketch
+            .back_edges
+            .iter()
+            .map(|e| sketch.graph.edge_endpoints(*e).unwrap())
+            .collect();
+        assert_eq!(back, [(sketch.node_map[&dtv("list.load")], sketch.root)]);
+        let x = Sketch::from_quotient(&shapes.quotient, &dtv("x")).unwrap();
+        assert!(x.back_edges.is_empty());
+    }
+
+    #[test]
+    fn test_struct_layout() {
+        let shapes = |constraints: &[&str]| {
+            let constraints = constraints
+                .iter()
+                .flat_map(|c| parse_constraints_line(c).unwrap().1)
+                .collect();
+            let program = Program::new(
+                "x86",
+                HashMap::from([("f".to_string(), constraints)]),
+                DiGraph::new(),
+            );
+            Solver::new(&program).infer_shapes().unwrap()
+        };
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        let s = shapes(&["x.σ4@0 <= a", "x.σ4@4 <= b", "y <= x.σ4@4", "x.load <= c"]);
+        assert_eq!(
+            s.quotient.struct_layout(s.class_map[&dtv("x")]),
+            Ok(vec![(0, 4), (4, 4)])
+        );
+        assert_eq!(s.quotient.struct_layout(s.class_map[&dtv("c")]), Ok(vec![]));
+
+        let s = shapes(&["x.σ4@0 <= a", "x.σ8@2 <= b", "x.σ1@9 <= c"]);
+        let x = s.class_map[&dtv("x")];
+        let overlaps = s.quotient.struct_layout(x).unwrap_err();
+        assert_eq!(
+            overlaps,
+            [
+                FieldOverlap {
+                    class: x,
+                    first: (0, 4),
+                    second: (2, 8)
+                },
+                FieldOverlap {
+                    class: x,
+                    first: (2, 8),
+                    second: (9, 1)
+                },
+            ]
+        );
+        assert_eq!(
+            overlaps[0].to_string(),
+            format!("overlapping fields in class {}: σ4@0 and σ8@2", x)
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let report = Solver::new(&program).report();
+        let names: Vec<&str> = report.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["main", "main_2", "main_1", "__wasm_call_dtors", "dummy"]
+        );
+        let main = &report[0];
+        assert_eq!(main.constraint_count, 15);
+        assert!(
+            main.node_count > 2 * 15 && main.edge_count > main.node_count / 2,
+            "{:?}",
+            main
+        );
+        assert!(main.saturation_iterations >= 1);
+        for r in &report {
+            assert_eq!(r.constraint_count == 0, r.node_count == 0, "{:?}", r);
+            assert_eq!(r.node_count == 0, r.edge_count == 0, "{:?}", r);
         }
+
+        let selected = Solver::new(&program)
+            .with_procs(HashSet::from(["main_1".to_string()]))
+            .report();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0], report[2]);
+    }
+
+    #[test]
+    fn test_union_find_dump() {
+        let constraints = ["x <= y", "y.load <= z", "w <= w2"]
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let path = std::env::temp_dir().join("retypd-test-union-find.dot");
+        let _ = std::fs::remove_file(&path);
+        let mut solver = Solver::new(&program);
+        solver.union_find_dump = Some(path.clone());
+        solver.infer_shapes().unwrap();
+        let dot = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(dot.starts_with("digraph {"), "{}", dot);
+        for name in ["x", "y", "y.load", "z", "w", "w2"] {
+            assert!(dot.contains(&format!("label = \"{}\"", name)), "{}", dot);
+        }
+        // each of the three unions gives one parent pointer.
+        assert_eq!(dot.matches(" -> ").count(), 3, "{}", dot);
+    }
+
+    #[test]
+    fn test_opaque() {
+        let constraints: Vec<_> = [
+            "x <= slot",
+            "slot <= y",
+            "x.load <= a",
+            "y.load <= b",
+            "y <= z",
+        ]
+        .iter()
+        .flat_map(|c| parse_constraints_line(c).unwrap().1)
+        .collect();
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        assert_eq!(shapes.class_map[&dtv("x")], shapes.class_map[&dtv("y")]);
+        assert!(shapes.refused.is_empty());
+
+        program.opaque = HashSet::from(["x".to_string(), "y".to_string()]);
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let class = |s: &str| shapes.class_map[&dtv(s)];
+        assert_ne!(class("x"), class("y"));
+        assert_ne!(class("a"), class("b"));
+        // the other variables still unify with one of them.
+        assert_eq!(class("slot"), class("x"));
+        assert_eq!(class("z"), class("y"));
+        assert_eq!(shapes.refused.len(), 1);
+        let mut refused = [
+            &*shapes.refused[0].left.name,
+            &*shapes.refused[0].right.name,
+        ];
+        refused.sort();
+        assert_eq!(refused, ["x", "y"]);
+    }
+
+    #[test]
+    fn test_selected_procs() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let solver = Solver::new(&program).with_procs(HashSet::from(["main_1".to_string()]));
+        let shapes = solver.infer_shapes().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // v_15 <= main_1.out
+        assert!(shapes.class_map.contains_key(&dtv("v_15")));
+        assert!(!shapes.class_map.contains_key(&dtv("v_0")));
+    }
+
+    #[test]
+    fn test_union_by_rank() {
+        let constraints = (0..1000)
+            .map(|i| {
+                parse_constraint(&format!("a_{} <= a_{}", i, i + 1))
+                    .unwrap()
+                    .1
+            })
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        assert_eq!(shapes.class_count(), 1);
+        // a chain unified one link at a time would be 1000 high.
+        assert!(
+            shapes.forest_height <= 1001f64.log2().ceil() as usize + 1,
+            "{}",
+            shapes.forest_height
+        );
+        assert_eq!(
+            shapes
+                .equiv_class(&parse_derived_type_variable("a_0").unwrap().1)
+                .unwrap()
+                .len(),
+            1001
+        );
+    }
+
+    #[test]
+    fn test_top_bottom_shapes() {
+        let constraints = ["x <= ⊤", "y <= ⊤", "⊥ <= x", "x.load <= a", "y.σ4@0 <= b"]
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        // x and y are not unified through ⊤, so their fields do not conflict.
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert_ne!(shapes.class_map[&dtv("x")], shapes.class_map[&dtv("y")]);
+        assert!(!shapes.class_map.contains_key(&dtv("⊤")));
+        assert!(!shapes.class_map.contains_key(&dtv("⊥")));
+    }
+
+    #[test]
+    fn test_deref_bounds() {
+        let constraints = [
+            "p.σ4@0*[8] <= a",
+            "p.σ4@0 <= b",
+            "p.σ4@4*[nullterm] <= c",
+            "p.σ4@4*[nobound] <= d",
+        ]
+        .iter()
+        .flat_map(|c| parse_constraints_line(c).unwrap().1)
+        .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let shapes = Solver::new(&program).infer_shapes().unwrap();
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        assert_eq!(
+            shapes.class_map[&dtv("p.σ4@0*[8]")],
+            shapes.class_map[&dtv("p.σ4@0")]
+        );
+        assert_eq!(shapes.class_map[&dtv("a")], shapes.class_map[&dtv("b")]);
+        // in one node, named without the bound.
+        assert_eq!(
+            shapes.equiv_class(&dtv("p.σ4@0*[8]")).unwrap(),
+            [dtv("a"), dtv("b"), dtv("p.σ4@0")]
+        );
+        let mut labels: Vec<String> = shapes
+            .quotient
+            .out_fields(shapes.class_map[&dtv("p")])
+            .map(|(label, _)| label.to_string())
+            .collect();
+        labels.sort();
+        assert_eq!(labels, ["σ4@0*[8]", "σ4@4*[nullterm]"]);
+        assert_eq!(
+            Bound::meet(&Some(Bound::Fixed(4)), &Some(Bound::NoBound)),
+            Some(Bound::Fixed(4))
+        );
+        assert_eq!(
+            Bound::meet(&None, &Some(Bound::NoBound)),
+            Some(Bound::NoBound)
+        );
+    }
+
+    #[test]
+    fn test_shape_conflict() {
+        let constraints = ["p <= q", "p.out_0 <= a", "q.σ4@0 <= b"]
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let conflicts = Solver::new(&program).infer_shapes().unwrap_err();
+        let p = parse_derived_type_variable("p").unwrap().1;
+        let q = parse_derived_type_variable("q").unwrap().1;
+        assert_eq!(conflicts, vec![TypeConflict { left: p, right: q }]);
+        // a conflict is not a missing procedure.
+        assert!(matches!(
+            Solver::new(&program).function_signature("p"),
+            Err(SolveError::Conflicts(_))
+        ));
+    }
+
+    #[test]
+    fn test_schemes_to_json() {
+        let program = constraints_from_json("tests/retypd-constrains-simple.json").unwrap();
+        let json = Solver::new(&program).schemes_to_json().unwrap();
+        let schemes = program_from_json(&json).unwrap();
+        assert_eq!(schemes.language, program.language);
+        assert_eq!(
+            schemes.call_graph.node_count(),
+            program.call_graph.node_count()
+        );
+        let mut procs: Vec<&String> = schemes.proc_constraints.keys().collect();
+        procs.sort();
+        assert_eq!(
+            procs,
+            ["__wasm_call_dtors", "dummy", "main", "main_1", "main_2"]
+        );
+
+        let constraints = SLIDES_EXAMPLE
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let mut call_graph = DiGraph::new();
+        let f = call_graph.add_node("F".to_string());
+        let close = call_graph.add_node("close".to_string());
+        call_graph.add_edge(f, close, 1);
+        let program = Program::new(
+            "x86",
+            HashMap::from([
+                ("F".to_string(), constraints),
+                ("close".to_string(), Vec::new()),
+            ]),
+            call_graph,
+        );
+        let json = Solver::new(&program).schemes_to_json().unwrap();
+        let schemes = program_from_json(&json).unwrap();
+        let scheme: Vec<String> = schemes.proc_constraints["F"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            scheme,
+            [
+                "F.in_stack0.load.σ4@4 <= _FileDescriptor",
+                "_SuccessZ <= F.out_eax"
+            ]
+        );
+        // solving the schemes again is a fixpoint.
+        assert_eq!(Solver::new(&schemes).schemes_to_json().unwrap(), json);
+    }
+
+    #[test]
+    fn test_deep_unify() {
+        // unifying p and q unifies the pairs of loads below them. Each
+        // variable holds its whole path, so the chain is kept short and the
+        // stack small instead.
+        let depth = 1000;
+        let chain = |name: &str| format!("{}{}", name, ".load".repeat(depth));
+        let constraints = [chain("p") + " <= " + &chain("q"), "p <= q".to_string()]
+            .iter()
+            .map(|c| parse_constraint(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("f".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let shapes = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || Solver::new(&program).infer_shapes().unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(shapes.class_count(), depth + 1);
+        let p = parse_derived_type_variable(&chain("p")).unwrap().1;
+        let q = parse_derived_type_variable(&chain("q")).unwrap().1;
+        assert!(shapes.equiv_class(&q).unwrap().contains(&p));
+    }
+
+    #[test]
+    fn test_type_constants() {
+        let program = |cs: &[&str]| {
+            let constraints = cs
+                .iter()
+                .flat_map(|c| parse_constraints_line(c).unwrap().1)
+                .collect();
+            Program::new(
+                "x86",
+                HashMap::from([("_f".to_string(), constraints)]),
+                DiGraph::new(),
+            )
+        };
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        // x is below both constants, they stay apart.
+        let p = program(&["_A <= x", "_B <= x", "x <= _f.out_eax"]);
+        let shapes = Solver::new(&p).infer_shapes().unwrap();
+        assert!(shapes.equiv_class(&dtv("_A")).is_none());
+        assert!(shapes.equiv_class(&dtv("_B")).is_none());
+        // a procedure named with `_` is not a constant.
+        assert!(shapes
+            .equiv_class(&dtv("_f.out_eax"))
+            .unwrap()
+            .contains(&dtv("x")));
+
+        let mut p = program(&["_A <= _B", "_A <= _A"]);
+        let conflicts = Solver::new(&p).infer_shapes().unwrap_err();
+        assert_eq!(
+            conflicts,
+            vec![TypeConflict {
+                left: dtv("_A"),
+                right: dtv("_B")
+            }]
+        );
+        p.types = Some(CTypeLattice::new(&[], &[("_A", "_B")]));
+        assert!(Solver::new(&p).infer_shapes().is_ok());
+    }
+
+    #[test]
+    fn test_function_signature() {
+        let constraints = SLIDES_EXAMPLE
+            .iter()
+            .chain(&[
+                "g.in_2 <= _FileDescriptor",
+                "g.in_0 <= x",
+                "h.in_4000000000 <= y",
+                "h.in_03 <= z",
+                "h.in_3 <= _FileDescriptor",
+            ])
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        program.types = Some(lattice.clone());
+        let solver = Solver::new(&program);
+
+        let close = solver.function_signature("close").unwrap().unwrap();
+        assert!(close.params.is_empty());
+        assert_eq!(close.named_params.len(), 1);
+        assert_eq!(
+            close.named_params["stack0"].to_c_type(&lattice),
+            "FileDescriptor"
+        );
+        assert_eq!(close.ret.unwrap().to_c_type(&lattice), "SuccessZ");
+
+        // in_1 is missing.
+        let g = solver.function_signature("g").unwrap().unwrap();
+        assert_eq!(g.params.keys().collect::<Vec<_>>(), vec![&0, &2]);
+        assert_eq!(g.params[&2].to_c_type(&lattice), "FileDescriptor");
+        assert!(g.ret.is_none());
+        // the positions are not a dense vector, and `in_03` is not `in_3`.
+        let h = solver.function_signature("h").unwrap().unwrap();
+        assert_eq!(h.params.keys().collect::<Vec<_>>(), vec![&3, &4000000000]);
+        assert_eq!(h.params[&3].to_c_type(&lattice), "FileDescriptor");
+        assert_eq!(h.named_params.keys().collect::<Vec<_>>(), vec!["03"]);
+        assert!(solver.function_signature("no_such_proc").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_iteration_limit() {
+        let constraints = SLIDES_EXAMPLE
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let mut call_graph = DiGraph::new();
+        call_graph.add_node("F".to_string());
+        let program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), constraints)]),
+            call_graph,
+        );
+        let solver = Solver::new(&program).with_max_iterations(0);
+        let limit = SaturationError::IterationLimit { iterations: 0 };
+        assert_eq!(solver.solve().err(), Some(limit.clone()));
+        assert_eq!(solver.schemes_to_json().err(), Some(limit.clone()));
+        assert_eq!(
+            solver.function_signature("close").err(),
+            Some(SolveError::Saturation(limit.clone()))
+        );
+        let shapes = solver.infer_shapes().unwrap();
+        let root = parse_derived_type_variable("F").unwrap().1;
+        let mut sketch = Sketch::from_quotient(&shapes.quotient, &root).unwrap();
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        assert_eq!(
+            solver.compute_sketch_bounds(&mut sketch, &lattice),
+            Err(limit)
+        );
+        // the report shows the limit instead of failing.
+        assert_eq!(solver.report()[0].saturation_iterations, 0);
+    }
+
+    #[test]
+    fn test_sketch_bounds() {
+        let constraints = SLIDES_EXAMPLE
+            .iter()
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        let solver = Solver::new(&program);
+        let root = parse_derived_type_variable("close").unwrap().1;
+        let mut sketch =
+            Sketch::from_quotient(&solver.infer_shapes().unwrap().quotient, &root).unwrap();
+        solver.compute_sketch_bounds(&mut sketch, &lattice).unwrap();
+
+        let bounds = |dtv: &str| {
+            let dtv = parse_derived_type_variable(dtv).unwrap().1;
+            let node = sketch.graph.node_weight(sketch.node_map[&dtv]).unwrap();
+            (node.lower_bound.clone(), node.upper_bound.clone())
+        };
+        // close.in_stack0 <= _FileDescriptor
+        assert_eq!(
+            bounds("close.in_stack0"),
+            (BOTTOM.to_string(), "_FileDescriptor".to_string())
+        );
+        // _SuccessZ <= close.out_eax
+        assert_eq!(
+            bounds("close.out_eax"),
+            ("_SuccessZ".to_string(), TOP.to_string())
+        );
+        assert_eq!(bounds("close"), (BOTTOM.to_string(), TOP.to_string()));
+    }
+
+    #[test]
+    fn test_type_hints() {
+        let constraints = SLIDES_EXAMPLE
+            .iter()
+            .filter(|c| !c.contains("_FileDescriptor"))
+            .flat_map(|c| parse_constraints_line(c).unwrap().1)
+            .collect();
+        let mut program = Program::new(
+            "x86",
+            HashMap::from([("F".to_string(), constraints)]),
+            DiGraph::new(),
+        );
+        let dtv = |s: &str| parse_derived_type_variable(s).unwrap().1;
+        program
+            .type_hints
+            .insert(dtv("close.in_stack0"), "_FileDescriptor".to_string());
+        let lattice = CTypeLattice::new(&["_FileDescriptor", "_SuccessZ"], &[]);
+        let solver = Solver::new(&program);
+        let shapes = solver.infer_shapes().unwrap();
+        let bounds = |root: &str, var: &str| {
+            let mut sketch = Sketch::from_quotient(&shapes.quotient, &dtv(root)).unwrap();
+            solver.compute_sketch_bounds(&mut sketch, &lattice).unwrap();
+            let node = sketch
+                .graph
+                .node_weight(sketch.node_map[&dtv(var)])
+                .unwrap();
+            (node.lower_bound.clone(), node.upper_bound.clone())
+        };
+        let fd = "_FileDescriptor".to_string();
+        assert_eq!(bounds("close", "close.in_stack0"), (fd.clone(), fd.clone()));
+        // and the upper bound flows to what is passed to close.
+        assert_eq!(
+            bounds("F", "F.in_stack0.load.σ4@4"),
+            (BOTTOM.to_string(), fd)
+        );
     }
 }
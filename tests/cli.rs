@@ -0,0 +1,289 @@
+use std::process::Command;
+
+fn retypd() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_retypd-rust"))
+}
+
+#[test]
+fn test_list_procs() {
+    let output = retypd()
+        .args([
+            "solve",
+            "tests/retypd-constrains-simple.json",
+            "--list-procs",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let procs: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        procs,
+        ["__wasm_call_dtors", "dummy", "main", "main_1", "main_2"]
+    );
+}
+
+#[test]
+fn test_report() {
+    let output = retypd()
+        .args(["solve", "tests/retypd-constrains-simple.json", "--report"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("main: 15 constraints, "), "{}", stdout);
+    assert!(
+        lines[4].starts_with("dummy: 0 constraints, 0 nodes, 0 edges"),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_strict_language() {
+    let path = std::env::temp_dir().join("retypd-test-strict-language.json");
+    std::fs::write(
+        &path,
+        r#"{"language": "z80", "callgraph": {}, "constraints": {"f": ["x <= y"]}}"#,
+    )
+    .unwrap();
+    let output = retypd()
+        .arg("solve")
+        .arg(&path)
+        .arg("--list-procs")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = retypd()
+        .arg("solve")
+        .arg(&path)
+        .args(["--list-procs", "--strict"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("unknown language: z80"), "{}", stderr);
+    std::fs::remove_file(path).unwrap();
+    let output = retypd()
+        .args([
+            "solve",
+            "tests/retypd-constrains-simple.json",
+            "--list-procs",
+            "--strict",
+        ])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_unknown_proc() {
+    let output = retypd()
+        .args([
+            "solve",
+            "tests/retypd-constrains-simple.json",
+            "--proc",
+            "nonexistent",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("unknown procedure: nonexistent"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_emit_schemes() {
+    let path = std::env::temp_dir().join("retypd-test-emit-schemes.json");
+    let output = retypd()
+        .args([
+            "solve",
+            "tests/retypd-constrains-simple.json",
+            "--emit-schemes",
+        ])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // the schemes can be read back as constraints.
+    let output = retypd()
+        .arg("solve")
+        .arg(&path)
+        .arg("--list-procs")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 5);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_dot_dir() {
+    let dir = std::env::temp_dir().join("retypd-test-dot-dir");
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = retypd()
+        .args(["solve", "tests/retypd-constrains-simple.json", "--dot-dir"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for file in [
+        "shapes.dot",
+        "shapes-quotient.dot",
+        "shapes-quotient.graphml",
+    ] {
+        assert!(dir.join(file).is_file(), "missing {}", file);
+    }
+    for proc in ["__wasm_call_dtors", "dummy", "main", "main_1", "main_2"] {
+        for file in [
+            "transducer-init.dot",
+            "transducer-sat.dot",
+            "transducer-sat.graphml",
+        ] {
+            assert!(
+                dir.join(proc).join(file).is_file(),
+                "missing {}/{}",
+                proc,
+                file
+            );
+        }
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_dot_dir_names() {
+    let dir = std::env::temp_dir().join("retypd-test-dot-dir-names");
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = std::env::temp_dir().join("retypd-test-dot-dir-names.json");
+    let json = r#"{"language": "x86", "callgraph": {"../up": [], "a/b": []}, "constraints": {"../up": ["x <= y"], "a/b": []}}"#;
+    std::fs::write(&path, json).unwrap();
+    let output = retypd()
+        .args(["solve", path.to_str().unwrap(), "--dot-dir"])
+        .arg(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for proc in ["___up", "a_b"] {
+        assert!(
+            dir.join(proc).join("transducer-sat.dot").is_file(),
+            "missing {}",
+            proc
+        );
+    }
+    assert!(!std::env::temp_dir().join("up").exists());
+
+    // a directory that cannot be created does not stop the solver.
+    let output = retypd()
+        .args(["solve", path.to_str().unwrap(), "--dot-dir"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_strict() {
+    let output = retypd()
+        .args([
+            "solve",
+            "tests/retypd-constrains-illformed.json",
+            "--strict",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("in f: ill-formed x.load.in_0: field 1"),
+        "{}",
+        stderr
+    );
+    // accepted without the flag
+    let output = retypd()
+        .args(["solve", "tests/retypd-constrains-illformed.json"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+fn test_check() {
+    let output = retypd()
+        .args(["check", "tests/retypd-constrains-dangling.json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("main calls missing, which is not in the call graph"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("failed with 1 unknown callees, 0 unparsable"),
+        "{}",
+        stderr
+    );
+
+    let output = retypd()
+        .args(["check", "tests/retypd-constrains-simple.json"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("5 procedures, 19 constraints, no problems"),
+        "{}",
+        stdout
+    );
+}